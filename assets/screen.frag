@@ -0,0 +1,12 @@
+#version 330
+precision mediump float;
+
+in vec2 uv;
+
+out vec4 color;
+
+uniform sampler2D screen_sampler;
+
+void main() {
+	color = texture(screen_sampler, uv);
+}
@@ -0,0 +1,577 @@
+use crate::asc::MemoryMapped;
+use crate::irq::{IrqLine, IrqSource};
+
+pub const SAMPLE_RATE: u32 = 44100;
+const CPU_FREQUENCY: f64 = 1_789_773.0;
+
+// Headless runs never consume the samples, keep at most a second of audio.
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize;
+
+// Based on https://www.nesdev.org/wiki/APU_Length_Counter
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// Based on https://www.nesdev.org/wiki/APU_Noise, in CPU cycles
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+// CPU cycles at which the frame counter steps, based on
+// https://www.nesdev.org/wiki/APU_Frame_Counter
+const FOUR_STEP_CYCLES: [u32; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP_CYCLES: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum FrameCounterMode {
+    #[default]
+    FourStep,
+    // Has no IRQ and an extra step that doesn't clock anything
+    FiveStep,
+}
+
+#[derive(Debug, Default)]
+struct Envelope {
+    start: bool,
+    // Doubles as the length counter halt flag
+    looping: bool,
+    constant: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+            return;
+        }
+
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+
+        self.divider = self.volume;
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if self.looping {
+            self.decay = 15;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+}
+
+#[derive(Debug, Default)]
+struct Pulse {
+    // Pulse 1 negates its sweep with ones' complement, pulse 2 with two's
+    ones_complement: bool,
+    enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    timer_period: u16,
+    timer: u16,
+    length: u8,
+    envelope: Envelope,
+    sweep: Sweep,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Pulse {
+        Pulse {
+            ones_complement,
+            ..Default::default()
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.duty = value >> 6;
+                self.envelope.looping = value & 0x20 != 0;
+                self.envelope.constant = value & 0x10 != 0;
+                self.envelope.volume = value & 0x0f;
+            }
+            1 => {
+                self.sweep.enabled = value & 0x80 != 0;
+                self.sweep.period = (value >> 4) & 0x07;
+                self.sweep.negate = value & 0x08 != 0;
+                self.sweep.shift = value & 0x07;
+                self.sweep.reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0xff) | ((value as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.duty_step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.duty_step = (self.duty_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep.shift;
+        if self.sweep.negate {
+            self.timer_period
+                .saturating_sub(change + self.ones_complement as u16)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    // The sweep unit silences the channel when the period is out of range,
+    // even if it is not enabled.
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7ff
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.is_muted()
+        {
+            self.timer_period = self.sweep_target();
+        }
+
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0
+            || self.is_muted()
+            || DUTY_TABLE[self.duty as usize][self.duty_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Triangle {
+    enabled: bool,
+    // Halts the length counter and keeps reloading the linear counter
+    control: bool,
+    linear_period: u8,
+    linear: u8,
+    linear_reload: bool,
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    length: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.linear_period = value & 0x7f;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0xff) | ((value as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    // Unlike the pulses, the triangle timer runs at the CPU clock and the
+    // sequencer only moves while both counters are non-zero.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.linear > 0 && self.length > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_period;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    // A halted sequencer holds its last value instead of going silent
+    fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.step as usize]
+    }
+}
+
+#[derive(Debug)]
+struct Noise {
+    enabled: bool,
+    // Short mode taps bit 6 instead of bit 1, giving a 93 step metallic tone
+    short_mode: bool,
+    shift: u16,
+    timer_period: u16,
+    timer: u16,
+    length: u8,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn new() -> Noise {
+        Noise {
+            enabled: false,
+            short_mode: false,
+            shift: 1,
+            timer_period: NOISE_PERIODS[0],
+            timer: 0,
+            length: 0,
+            envelope: Envelope::default(),
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.envelope.looping = value & 0x20 != 0;
+                self.envelope.constant = value & 0x10 != 0;
+                self.envelope.volume = value & 0x0f;
+            }
+            1 => {}
+            2 => {
+                self.short_mode = value & 0x80 != 0;
+                self.timer_period = NOISE_PERIODS[(value & 0x0f) as usize];
+            }
+            _ => {
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.timer_period - 1;
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+        self.shift = (self.shift >> 1) | (feedback << 14);
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+pub struct Apu {
+    pulse: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+
+    cycle: u64,
+    frame_cycle: u32,
+    frame_mode: FrameCounterMode,
+    frame_irq_inhibit: bool,
+    irq: IrqLine,
+    // Accumulates SAMPLE_RATE every CPU cycle, a sample is taken each time it
+    // goes past the CPU frequency.
+    sample_clock: f64,
+    samples: Vec<f32>,
+}
+
+impl Apu {
+    pub fn new(irq: IrqLine) -> Apu {
+        Apu {
+            pulse: [Pulse::new(true), Pulse::new(false)],
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+
+            cycle: 0,
+            frame_cycle: 0,
+            frame_mode: FrameCounterMode::default(),
+            frame_irq_inhibit: false,
+            irq,
+            sample_clock: 0.0,
+            samples: vec![],
+        }
+    }
+
+    // Advances the APU by `cycles` CPU cycles, returns how many samples it
+    // made meanwhile
+    pub fn clock(&mut self, cycles: usize) -> usize {
+        (0..cycles).filter(|_| self.tick()).count()
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // Returns whether a sample was taken
+    fn tick(&mut self) -> bool {
+        self.cycle += 1;
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+
+        // The pulse timers run at half the CPU clock
+        if self.cycle.is_multiple_of(2) {
+            for pulse in &mut self.pulse {
+                pulse.clock_timer();
+            }
+        }
+
+        self.clock_frame_counter();
+
+        self.sample_clock += SAMPLE_RATE as f64;
+        if self.sample_clock >= CPU_FREQUENCY {
+            self.sample_clock -= CPU_FREQUENCY;
+            if self.samples.len() >= MAX_BUFFERED_SAMPLES {
+                self.samples.drain(..SAMPLE_RATE as usize / 60);
+            }
+            let sample = self.output();
+            self.samples.push(sample);
+            return true;
+        }
+        false
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+
+        let steps: &[u32] = match self.frame_mode {
+            FrameCounterMode::FourStep => &FOUR_STEP_CYCLES,
+            FrameCounterMode::FiveStep => &FIVE_STEP_CYCLES,
+        };
+        let Some(step) = steps.iter().position(|&c| c == self.frame_cycle) else {
+            return;
+        };
+        let last = step == steps.len() - 1;
+
+        if !(self.frame_mode == FrameCounterMode::FiveStep && step == 3) {
+            self.clock_quarter_frame();
+            if step == 1 || last {
+                self.clock_half_frame();
+            }
+        }
+
+        if last {
+            if self.frame_mode == FrameCounterMode::FourStep && !self.frame_irq_inhibit {
+                self.irq.assert(IrqSource::FrameCounter);
+            }
+            self.frame_cycle = 0;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        for pulse in &mut self.pulse {
+            pulse.envelope.clock();
+        }
+        self.triangle.clock_linear();
+        self.noise.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        for pulse in &mut self.pulse {
+            pulse.clock_length();
+            pulse.clock_sweep();
+        }
+        self.triangle.clock_length();
+        self.noise.clock_length();
+    }
+
+    // Based on https://www.nesdev.org/wiki/APU_Mixer
+    fn output(&self) -> f32 {
+        let pulse = (self.pulse[0].output() + self.pulse[1].output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = self.triangle.output() as f32 / 8227.0 + self.noise.output() as f32 / 12241.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+
+    fn lengths(&self) -> [u8; 4] {
+        [
+            self.pulse[0].length,
+            self.pulse[1].length,
+            self.triangle.length,
+            self.noise.length,
+        ]
+    }
+}
+
+impl MemoryMapped for Apu {
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse[0].write(addr & 0x03, value),
+            0x4004..=0x4007 => self.pulse[1].write(addr & 0x03, value),
+            0x4008..=0x400b => self.triangle.write(addr & 0x03, value),
+            0x400c..=0x400f => self.noise.write(addr & 0x03, value),
+            0x4015 => {
+                self.pulse[0].set_enabled(value & 0x01 != 0);
+                self.pulse[1].set_enabled(value & 0x02 != 0);
+                self.triangle.set_enabled(value & 0x04 != 0);
+                self.noise.set_enabled(value & 0x08 != 0);
+            }
+            0x4017 => {
+                self.frame_mode = if value & 0x80 != 0 {
+                    FrameCounterMode::FiveStep
+                } else {
+                    FrameCounterMode::FourStep
+                };
+                self.frame_irq_inhibit = value & 0x40 != 0;
+                if self.frame_irq_inhibit {
+                    self.irq.acknowledge(IrqSource::FrameCounter);
+                }
+
+                // The 5-step mode clocks everything right away on the write
+                self.frame_cycle = 0;
+                if self.frame_mode == FrameCounterMode::FiveStep {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => panic!("Address {addr:#x} is not registered by the APU"),
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.peek(addr);
+        // Reading the status acknowledges the frame interrupt
+        if addr == 0x4015 {
+            self.irq.acknowledge(IrqSource::FrameCounter);
+        }
+        value
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4015 => {
+                let status = self
+                    .lengths()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, length)| **length > 0)
+                    .fold(0, |status, (i, _)| status | 1 << i);
+                let frame_irq = self.irq.is_pending(IrqSource::FrameCounter);
+                status | (frame_irq as u8) << 6
+            }
+            // The rest of the registers are write only
+            0x4000..=0x400f | 0x4017 => 0,
+            _ => panic!("Address {addr:#x} is not registered by the APU"),
+        }
+    }
+
+    // Writes restart the length counters and the frame sequencer
+    fn poke(&mut self, _addr: u16, _value: u8) {}
+}
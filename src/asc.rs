@@ -1,65 +1,550 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::heatmap::Heatmap;
+use crate::watch::Access;
+
+// Zero page and stack, which take most of the CPU accesses
+pub const FAST_RAM_SIZE: usize = 0x200;
 
 pub trait MemoryMapped {
     fn write(&mut self, addr: u16, value: u8);
 
     fn read(&mut self, addr: u16) -> u8;
+
+    // What a read of `addr` would return, without its side effects: no flags
+    // cleared, no pointers moved, no input taken. For debugging tools, it
+    // must not change what the program sees. Plain memory has no side
+    // effects, so by default it reads.
+    fn peek(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    // Sets what `addr` holds without the side effects of a write. Registers
+    // that don't hold a value can ignore it.
+    fn poke(&mut self, addr: u16, value: u8) {
+        self.write(addr, value);
+    }
+}
+
+// For addresses where reads and writes reach different devices, like $4017
+// (second controller on reads, APU frame counter on writes).
+pub struct SplitDevice {
+    reader: Device,
+    writer: Device,
+}
+
+impl SplitDevice {
+    pub fn new(reader: Device, writer: Device) -> SplitDevice {
+        SplitDevice { reader, writer }
+    }
+}
+
+impl MemoryMapped for SplitDevice {
+    fn write(&mut self, addr: u16, value: u8) {
+        self.writer.borrow_mut().write(addr, value);
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.reader.borrow_mut().read(addr)
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        self.reader.borrow_mut().peek(addr)
+    }
+
+    fn poke(&mut self, addr: u16, value: u8) {
+        self.writer.borrow_mut().poke(addr, value);
+    }
+}
+
+// Passes the accesses on to a device, raising a flag on every write. Used to
+// notice the game saving to battery-backed RAM.
+pub struct WriteTracker {
+    device: Device,
+    written: Rc<Cell<bool>>,
+}
+
+impl WriteTracker {
+    pub fn new(device: Device, written: Rc<Cell<bool>>) -> WriteTracker {
+        WriteTracker { device, written }
+    }
+}
+
+impl MemoryMapped for WriteTracker {
+    fn write(&mut self, addr: u16, value: u8) {
+        self.written.set(true);
+        self.device.borrow_mut().write(addr, value);
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.device.borrow_mut().read(addr)
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        self.device.borrow_mut().peek(addr)
+    }
+
+    // Not the game saving, so the flag stays as it is
+    fn poke(&mut self, addr: u16, value: u8) {
+        self.device.borrow_mut().poke(addr, value);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnmappedPolicy {
+    Ignore,
+    #[default]
+    LogOnce,
+    LogAll,
+    // Logs the access and stops the machine
+    Break,
+}
+
+impl UnmappedPolicy {
+    // Names used by `--unmapped` and the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            UnmappedPolicy::Ignore => "ignore",
+            UnmappedPolicy::LogOnce => "once",
+            UnmappedPolicy::LogAll => "all",
+            UnmappedPolicy::Break => "break",
+        }
+    }
+}
+
+impl FromStr for UnmappedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(UnmappedPolicy::Ignore),
+            "once" => Ok(UnmappedPolicy::LogOnce),
+            "all" => Ok(UnmappedPolicy::LogAll),
+            "break" => Ok(UnmappedPolicy::Break),
+            _ => Err(format!("unknown unmapped access policy {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AccessCount {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+pub type Device = Rc<RefCell<dyn MemoryMapped>>;
+
+const PAGE_SIZE: usize = 0x100;
+const PAGES: usize = 0x10000 / PAGE_SIZE;
+
+// What a range of addresses registered on the bus leads to
+enum Mapping {
+    Device(Device),
+    // The addresses show whatever is at `addr & mask`
+    Mirror(u16),
+}
+
+// Devices by address, in a table of 256 byte pages. A page holds the number
+// of the mapping of each of its addresses, counting from 1 so 0 is nothing,
+// and pages with nothing on them aren't allocated.
+struct DeviceMap {
+    pages: Vec<Option<Box<[u16; PAGE_SIZE]>>>,
+    mappings: Vec<Mapping>,
+}
+
+impl DeviceMap {
+    fn new() -> DeviceMap {
+        DeviceMap {
+            pages: vec![None; PAGES],
+            mappings: vec![],
+        }
+    }
+
+    fn mapping(&self, addr: u16) -> Option<&Mapping> {
+        let page = self.pages[addr as usize / PAGE_SIZE].as_ref()?;
+        match page[addr as usize % PAGE_SIZE] {
+            0 => None,
+            number => Some(&self.mappings[number as usize - 1]),
+        }
+    }
+
+    // The address a mirror leads to, or `addr` itself
+    fn resolve(&self, addr: u16) -> u16 {
+        match self.mapping(addr) {
+            Some(Mapping::Mirror(mask)) => addr & mask,
+            _ => addr,
+        }
+    }
+
+    // The device at `addr`, through its mirrors
+    fn get(&self, addr: u16) -> Option<&Device> {
+        match self.mapping(self.resolve(addr))? {
+            Mapping::Device(dev) => Some(dev),
+            // Mirrors of mirrors aren't followed
+            Mapping::Mirror(_) => None,
+        }
+    }
+
+    fn insert(&mut self, addrs: impl Iterator<Item = u16>, mapping: Mapping) {
+        // A device registered in several calls keeps a single mapping
+        let existing = self.mappings.iter().position(|m| match (m, &mapping) {
+            (Mapping::Device(a), Mapping::Device(b)) => {
+                Rc::as_ptr(a) as *const () == Rc::as_ptr(b) as *const ()
+            }
+            (Mapping::Mirror(a), Mapping::Mirror(b)) => a == b,
+            _ => false,
+        });
+        let number = match existing {
+            Some(i) => i + 1,
+            None => {
+                self.mappings.push(mapping);
+                self.mappings.len()
+            }
+        };
+
+        for addr in addrs {
+            let page = self.pages[addr as usize / PAGE_SIZE]
+                .get_or_insert_with(|| Box::new([0; PAGE_SIZE]));
+            page[addr as usize % PAGE_SIZE] = number as u16;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pages.fill(None);
+        self.mappings.clear();
+    }
 }
 
 pub struct Asc {
-    devices: HashMap<u16, Rc<RefCell<dyn MemoryMapped>>>,
+    devices: DeviceMap,
+    // Last value seen on the data bus, returned by reads from addresses no
+    // device answers to.
+    open_bus: u8,
+    heatmap: Option<Heatmap>,
+    // Keyed by the address of the device
+    stats: Option<HashMap<*const (), AccessCount>>,
+
+    unmapped_policy: UnmappedPolicy,
+    unmapped_seen: HashSet<u16>,
+    unmapped_break: bool,
+
+    // Copy of the RAM at $0000-$01FF served without looking up the device.
+    // While it is in use the RAM device doesn't see accesses to that range,
+    // so it is off until whoever maps the devices knows plain RAM is there.
+    fast_ram: Option<Box<[u8; FAST_RAM_SIZE]>>,
 }
 
 impl Asc {
     pub fn new() -> Asc {
         Asc {
-            devices: HashMap::new(),
+            devices: DeviceMap::new(),
+            open_bus: 0,
+            heatmap: None,
+            stats: None,
+
+            unmapped_policy: UnmappedPolicy::default(),
+            unmapped_seen: HashSet::new(),
+            unmapped_break: false,
+
+            fast_ram: None,
         }
     }
 
-    pub fn register_device(&mut self, addr: u16, dev: Rc<RefCell<dyn MemoryMapped>>) {
-        self.devices.insert(addr, dev);
+    pub fn enable_stats(&mut self) {
+        // Counting the accesses needs the device of every address
+        self.set_fast_path(false);
+        self.stats = Some(HashMap::new());
     }
 
-    pub fn register_device_range(
-        &mut self,
-        addrs: impl Iterator<Item = u16>,
-        dev: Rc<RefCell<dyn MemoryMapped>>,
-    ) {
-        for addr in addrs {
-            self.devices.insert(addr, dev.clone());
+    // Without the fast path every access goes through the device map, which
+    // is slower but useful to rule the fast path out when chasing bugs. It
+    // stays off while the stats are counted.
+    pub fn set_fast_path(&mut self, enabled: bool) {
+        let enabled = enabled && self.stats.is_none();
+        self.fast_ram = match (enabled, self.fast_ram.take()) {
+            (true, None) => {
+                let mut ram = Box::new([0; FAST_RAM_SIZE]);
+                for (addr, value) in ram.iter_mut().enumerate() {
+                    if let Some(dev) = self.devices.get(addr as u16) {
+                        *value = dev.borrow_mut().read(addr as u16);
+                    }
+                }
+                Some(ram)
+            }
+            (false, Some(ram)) => {
+                for (addr, value) in ram.iter().enumerate() {
+                    if let Some(dev) = self.devices.get(addr as u16) {
+                        dev.borrow_mut().write(addr as u16, *value);
+                    }
+                }
+                None
+            }
+            (_, ram) => ram,
+        };
+    }
+
+    // Reads from whatever holds `addr` without the side effects of a CPU
+    // access, on the bus (open bus, heatmap, stats) or the device (see
+    // `MemoryMapped::peek`). Debugging tools may run while a device is in
+    // the middle of an access, so a busy device is reported as None instead
+    // of panicking.
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        let addr = self.devices.resolve(addr);
+        if let Some(ram) = &self.fast_ram {
+            if let Some(&value) = ram.get(addr as usize) {
+                return Some(value);
+            }
         }
+
+        let dev = self.devices.get(addr)?;
+        let mut dev = dev.try_borrow_mut().ok()?;
+        Some(dev.peek(addr))
+    }
+
+    // Writes to whatever holds `addr` without the side effects of a CPU
+    // access, on the bus or the device, for debugging tools. Returns false
+    // if nothing was written because no device is there or it is busy.
+    pub fn poke(&mut self, addr: u16, value: u8) -> bool {
+        let addr = self.devices.resolve(addr);
+        if let Some(ram) = &mut self.fast_ram {
+            if let Some(byte) = ram.get_mut(addr as usize) {
+                *byte = value;
+                return true;
+            }
+        }
+
+        let Some(dev) = self.devices.get(addr) else {
+            return false;
+        };
+        match dev.try_borrow_mut() {
+            Ok(mut dev) => {
+                dev.poke(addr, value);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // Access counts of every device, labeled by the address ranges it is
+    // registered at, e.g. `$2000-$2007 $4014`.
+    pub fn stats(&self) -> Vec<(String, AccessCount)> {
+        let Some(stats) = &self.stats else {
+            return vec![];
+        };
+
+        let mut labels: Vec<(*const (), String)> = vec![];
+        let mut range: Option<(*const (), u16, u16)> = None;
+        let mut close_range = |range: Option<(*const (), u16, u16)>| {
+            let Some((dev, start, end)) = range else {
+                return;
+            };
+            let part = if start == end {
+                format!("${start:04X}")
+            } else {
+                format!("${start:04X}-${end:04X}")
+            };
+            match labels.iter_mut().find(|(d, _)| *d == dev) {
+                Some((_, label)) => *label += &format!(" {part}"),
+                None => labels.push((dev, part)),
+            }
+        };
+
+        for addr in 0..=u16::MAX {
+            let dev = self.devices.get(addr).map(|d| Rc::as_ptr(d) as *const ());
+            range = match (range, dev) {
+                (Some((current, start, _)), Some(dev)) if current == dev => {
+                    Some((dev, start, addr))
+                }
+                (_, dev) => {
+                    close_range(range);
+                    dev.map(|dev| (dev, addr, addr))
+                }
+            };
+        }
+        close_range(range);
+
+        labels
+            .into_iter()
+            .map(|(dev, label)| (label, stats.get(&dev).copied().unwrap_or_default()))
+            .collect()
+    }
+
+    pub fn set_unmapped_policy(&mut self, policy: UnmappedPolicy) {
+        self.unmapped_policy = policy;
+    }
+
+    // Whether an unmapped access under the `Break` policy happened since the
+    // last call.
+    pub fn take_unmapped_break(&mut self) -> bool {
+        std::mem::take(&mut self.unmapped_break)
+    }
+
+    // A device reaching back into the bus while it handles an access is a
+    // bug in how the devices own each other, reported whatever the unmapped
+    // access policy says and fatal in debug builds
+    fn busy_device(&mut self, message: String) {
+        eprintln!("[ERROR]: {message}");
+        debug_assert!(false, "{message}");
+        if self.unmapped_policy == UnmappedPolicy::Break {
+            self.unmapped_break = true;
+        }
+    }
+
+    fn unmapped_access(&mut self, addr: u16, message: impl FnOnce() -> String) {
+        let log = match self.unmapped_policy {
+            UnmappedPolicy::Ignore => false,
+            UnmappedPolicy::LogOnce => self.unmapped_seen.insert(addr),
+            UnmappedPolicy::LogAll => true,
+            UnmappedPolicy::Break => {
+                self.unmapped_break = true;
+                true
+            }
+        };
+
+        if log {
+            eprintln!("[WARN]: {}", message());
+        }
+    }
+
+    pub fn enable_heatmap(&mut self) {
+        self.heatmap = Some(Heatmap::new());
+    }
+
+    pub fn heatmap_mut(&mut self) -> Option<&mut Heatmap> {
+        self.heatmap.as_mut()
+    }
+
+    // Also turns the fast path off, the RAM it stood for is gone
+    pub fn unregister_all(&mut self) {
+        self.devices.clear();
+        self.fast_ram = None;
+    }
+
+    pub fn register_device(&mut self, addr: u16, dev: Device) {
+        self.register_device_range(std::iter::once(addr), dev);
+    }
+
+    pub fn register_device_range(&mut self, addrs: impl Iterator<Item = u16>, dev: Device) {
+        let addrs: Vec<u16> = addrs.collect();
+        if let Some(ram) = &mut self.fast_ram {
+            for &addr in &addrs {
+                if let Some(byte) = ram.get_mut(addr as usize) {
+                    *byte = dev.borrow_mut().read(addr);
+                }
+            }
+        }
+        self.devices.insert(addrs.into_iter(), Mapping::Device(dev));
+    }
+
+    // Makes `addrs` show whatever is mapped at `addr & mask`, for chips
+    // that don't decode the upper address lines, like the 2KB of NES RAM
+    // repeating up to $1FFF
+    pub fn register_mirror(&mut self, addrs: impl Iterator<Item = u16>, mask: u16) {
+        self.devices.insert(addrs, Mapping::Mirror(mask));
     }
 }
 
 impl MemoryMapped for Asc {
     fn write(&mut self, addr: u16, value: u8) {
-        let dev = self.devices.get_mut(&addr);
+        self.open_bus = value;
+        let addr = self.devices.resolve(addr);
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(addr, Access::Write);
+        }
+
+        if let Some(ram) = &mut self.fast_ram {
+            if let Some(byte) = ram.get_mut(addr as usize) {
+                *byte = value;
+                return;
+            }
+        }
+
+        let dev = self.devices.get(addr);
 
         if let Some(dev) = dev {
-            dev.borrow_mut().write(addr, value);
+            if let Some(stats) = &mut self.stats {
+                stats
+                    .entry(Rc::as_ptr(dev) as *const ())
+                    .or_default()
+                    .writes += 1;
+            }
+
+            // A device that reaches back into the bus while handling an
+            // access would panic on the second borrow
+            let busy = match dev.try_borrow_mut() {
+                Ok(mut dev) => {
+                    dev.write(addr, value);
+                    false
+                }
+                Err(_) => true,
+            };
+            if busy {
+                self.busy_device(format!(
+                    "tried to write value {value:#x} to address {addr:#x} while its device is busy"
+                ));
+            }
         } else {
-            eprintln!(
-                "[WARN]: tried to write value {:#x} to address {:#x} that no device is registred",
-                value, addr
-            );
+            self.unmapped_access(addr, || {
+                format!(
+                    "tried to write value {:#x} to address {:#x} that no device is registred",
+                    value, addr
+                )
+            });
         }
     }
 
     fn read(&mut self, addr: u16) -> u8 {
-        let dev = self.devices.get_mut(&addr);
+        let addr = self.devices.resolve(addr);
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(addr, Access::Read);
+        }
+
+        if let Some(ram) = &self.fast_ram {
+            if let Some(&value) = ram.get(addr as usize) {
+                self.open_bus = value;
+                return value;
+            }
+        }
+
+        let dev = self.devices.get(addr);
 
         if let Some(dev) = dev {
-            let value = dev.borrow_mut().read(addr);
-            value
+            if let Some(stats) = &mut self.stats {
+                stats.entry(Rc::as_ptr(dev) as *const ()).or_default().reads += 1;
+            }
+
+            let value = dev.try_borrow_mut().map(|mut dev| dev.read(addr));
+            match value {
+                Ok(value) => {
+                    self.open_bus = value;
+                    value
+                }
+                Err(_) => {
+                    self.busy_device(format!(
+                        "tried to read from address {addr:#x} while its device is busy"
+                    ));
+                    self.open_bus
+                }
+            }
         } else {
-            eprintln!(
-                "[WARN]: Tried to read from address {:#x} that no device is registred",
-                addr
-            );
-            0
+            self.unmapped_access(addr, || {
+                format!(
+                    "Tried to read from address {:#x} that no device is registred",
+                    addr
+                )
+            });
+            self.open_bus
         }
     }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        Asc::peek(self, addr).unwrap_or(self.open_bus)
+    }
+
+    fn poke(&mut self, addr: u16, value: u8) {
+        Asc::poke(self, addr, value);
+    }
 }
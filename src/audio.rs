@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired, AudioStatus};
+use sdl2::AudioSubsystem;
+
+use crate::apu::SAMPLE_RATE;
+
+// Samples kept between the emulation loop and the audio thread. A few frames
+// worth absorbs the jitter of the frame timing without adding much latency.
+const BUFFER_SIZE: usize = SAMPLE_RATE as usize / 15;
+
+#[derive(Debug, Default)]
+struct RingBuffer {
+    samples: VecDeque<f32>,
+    // Played again on underruns, dropping to silence instead would pop
+    last: f32,
+}
+
+struct Playback {
+    buffer: Arc<Mutex<RingBuffer>>,
+    // APU samples consumed per device sample, for devices that didn't accept
+    // the APU sample rate.
+    step: f64,
+    position: f64,
+}
+
+impl AudioCallback for Playback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for sample in out.iter_mut() {
+            self.position += self.step;
+            while self.position >= 1.0 {
+                self.position -= 1.0;
+                if let Some(next) = buffer.samples.pop_front() {
+                    buffer.last = next;
+                }
+            }
+            *sample = buffer.last;
+        }
+    }
+}
+
+pub struct AudioOutput {
+    audio: AudioSubsystem,
+    // Device asked for by the user, None for the system default
+    preferred: Option<String>,
+    // Whether the default device is playing because the preferred one is
+    // missing
+    fallback: bool,
+    buffer: Arc<Mutex<RingBuffer>>,
+    device: AudioDevice<Playback>,
+}
+
+impl AudioOutput {
+    // Opens the playback device named `device`, or the default one if it is
+    // None or not connected
+    pub fn open(sdl: &sdl2::Sdl, device: Option<&str>) -> Result<AudioOutput, String> {
+        let audio = sdl.audio()?;
+        let buffer = Arc::new(Mutex::new(RingBuffer::default()));
+        let (output, fallback) = open_device(&audio, device, &buffer)?;
+        if let (Some(name), true) = (device, fallback) {
+            eprintln!("[WARN]: audio device {name} not found, using the default one");
+        }
+
+        Ok(AudioOutput {
+            audio,
+            preferred: device.map(str::to_string),
+            fallback,
+            buffer,
+            device: output,
+        })
+    }
+
+    // Names of the playback devices, as accepted by `open`
+    pub fn device_names(sdl: &sdl2::Sdl) -> Result<Vec<String>, String> {
+        let audio = sdl.audio()?;
+        playback_devices(&audio)
+    }
+
+    // To be called when SDL reports a playback device was added or removed.
+    // Moves to the default device when the one in use is gone, and back to
+    // the preferred one when it shows up again.
+    pub fn devices_changed(&mut self) {
+        let lost = self.device.status() == AudioStatus::Stopped;
+        if !lost && !self.fallback {
+            return;
+        }
+
+        match open_device(&self.audio, self.preferred.as_deref(), &self.buffer) {
+            // Still missing, keep playing on the default device
+            Ok((_, true)) if !lost => {}
+            Ok((device, fallback)) => {
+                self.device = device;
+                self.fallback = fallback;
+                eprintln!("audio: switched to {}", self.device_name());
+            }
+            Err(e) => eprintln!("[WARN]: could not reopen the audio device: {e}"),
+        }
+    }
+
+    fn device_name(&self) -> &str {
+        match &self.preferred {
+            Some(name) if !self.fallback => name,
+            _ => "the default device",
+        }
+    }
+
+    pub fn queue(&self, samples: &[f32]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.samples.extend(samples);
+
+        // The emulation ran ahead of the audio device, drop the oldest
+        // samples to keep the latency bounded.
+        let excess = buffer.samples.len().saturating_sub(BUFFER_SIZE);
+        buffer.samples.drain(..excess);
+    }
+}
+
+fn playback_devices(audio: &AudioSubsystem) -> Result<Vec<String>, String> {
+    let count = audio.num_audio_playback_devices().unwrap_or(0);
+    (0..count)
+        .map(|i| audio.audio_playback_device_name(i))
+        .collect()
+}
+
+// Returns the device and whether it is the default one standing in for a
+// missing `name`
+fn open_device(
+    audio: &AudioSubsystem,
+    name: Option<&str>,
+    buffer: &Arc<Mutex<RingBuffer>>,
+) -> Result<(AudioDevice<Playback>, bool), String> {
+    let desired = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+
+    let available = playback_devices(audio)?;
+    let fallback = name.is_some_and(|name| !available.iter().any(|d| d == name));
+    let name = if fallback { None } else { name };
+
+    let device = audio.open_playback(name, &desired, |spec| Playback {
+        buffer: buffer.clone(),
+        step: SAMPLE_RATE as f64 / spec.freq as f64,
+        position: 0.0,
+    })?;
+    device.resume();
+
+    Ok((device, fallback))
+}
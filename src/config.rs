@@ -0,0 +1,504 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sdl2::controller::Button as PadButton;
+use sdl2::keyboard::Keycode;
+
+use crate::asc::UnmappedPolicy;
+use crate::filter;
+use crate::gfx::ColorBlindness;
+use crate::input::Button;
+
+// Players left out of the config file keep these bindings. Keys use the SDL
+// key names and gamepad buttons the SDL game controller mapping names.
+const DEFAULT_CONFIG: &str = r#"
+[player1.keyboard]
+a = "X"
+b = "Z"
+select = "Right Shift"
+start = "Return"
+up = "Up"
+down = "Down"
+left = "Left"
+right = "Right"
+
+[player1.gamepad]
+a = "b"
+b = "a"
+select = "back"
+start = "start"
+up = "dpup"
+down = "dpdown"
+left = "dpleft"
+right = "dpright"
+
+[player2.gamepad]
+a = "b"
+b = "a"
+select = "back"
+start = "start"
+up = "dpup"
+down = "dpdown"
+left = "dpleft"
+right = "dpright"
+"#;
+
+const PLAYERS: usize = 2;
+
+// What the emulator does while none of its windows has the focus
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Unfocused {
+    // Keep going as usual
+    #[default]
+    Run,
+    Pause,
+    // Mute and slow down to a fraction of the speed, to save CPU time
+    Throttle,
+}
+
+impl Unfocused {
+    fn from_name(name: &str) -> Option<Unfocused> {
+        match name {
+            "run" => Some(Unfocused::Run),
+            "pause" => Some(Unfocused::Pause),
+            "throttle" => Some(Unfocused::Throttle),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Unfocused::Run => "run",
+            Unfocused::Pause => "pause",
+            Unfocused::Throttle => "throttle",
+        }
+    }
+}
+
+// What fills the window around the picture
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Borders {
+    // The backdrop color, like the overscan area of a TV
+    #[default]
+    Backdrop,
+    Black,
+}
+
+impl Borders {
+    fn from_name(name: &str) -> Option<Borders> {
+        match name {
+            "backdrop" => Some(Borders::Backdrop),
+            "black" => Some(Borders::Black),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Borders::Backdrop => "backdrop",
+            Borders::Black => "black",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    section: String,
+    key: String,
+    values: Vec<String>,
+}
+
+// Maps keyboard keys and gamepad buttons to the NES controllers. The n-th
+// connected gamepad drives the n-th controller port.
+#[derive(Debug, Clone)]
+pub struct Config {
+    keys: HashMap<Keycode, (usize, Button)>,
+    gamepad: [HashMap<PadButton, Button>; PLAYERS],
+    // `[audio] device`, the SDL name of the playback device to use
+    pub audio_device: Option<String>,
+    // `[window] unfocused`
+    pub unfocused: Unfocused,
+    // `[window] borders`
+    pub borders: Borders,
+    // `[window] filter`, the name of the video filter, see `filter::new`
+    pub filter: String,
+    // `[colorblind]` filters, by the CRC32 of the ROM or "default" for the
+    // games not listed. None turns the filter off.
+    colorblind: HashMap<String, Option<ColorBlindness>>,
+    // `[debug] unmapped`, what to do on accesses no device answers to
+    pub unmapped: UnmappedPolicy,
+    // Stand-in for a config file that didn't load, see `Config::fallback`
+    fallback: bool,
+}
+
+impl Config {
+    // `$XDG_CONFIG_HOME/rodomo/config.toml`, or `~/.config/rodomo/config.toml`
+    pub fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("rodomo").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Config::parse(&text)
+    }
+
+    // The defaults, to run with when the config file didn't load. They are
+    // never saved, that would replace the user's settings.
+    pub fn fallback() -> Config {
+        Config {
+            fallback: true,
+            ..Config::default()
+        }
+    }
+
+    // Writes every setting, creating the config directory if needed. The
+    // file is rewritten from scratch, so its comments are lost. A file that
+    // doesn't load is left alone, it may hold settings worth fixing.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if self.fallback || (path.exists() && Config::load(path).is_err()) {
+            return Err(format!(
+                "{} didn't load, fix it first so it isn't overwritten",
+                path.display()
+            ));
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, self.to_toml()).map_err(|e| e.to_string())
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut text = String::new();
+        for port in 0..PLAYERS {
+            let keys = |button| {
+                let mut names: Vec<String> = self
+                    .keys
+                    .iter()
+                    .filter(|(_, &binding)| binding == (port, button))
+                    .map(|(key, _)| key.name())
+                    .collect();
+                names.sort();
+                names
+            };
+            let pad_buttons = |button| {
+                let mut names: Vec<String> = self.gamepad[port]
+                    .iter()
+                    .filter(|(_, &bound)| bound == button)
+                    .map(|(pad_button, _)| pad_button.string())
+                    .collect();
+                names.sort();
+                names
+            };
+
+            write_section(&mut text, &format!("player{}.keyboard", port + 1), keys);
+            write_section(
+                &mut text,
+                &format!("player{}.gamepad", port + 1),
+                pad_buttons,
+            );
+        }
+
+        if let Some(device) = &self.audio_device {
+            text += &format!("[audio]\ndevice = \"{device}\"\n\n");
+        }
+
+        if self.unfocused != Unfocused::default()
+            || self.borders != Borders::default()
+            || self.filter != filter::DEFAULT
+        {
+            text += &format!(
+                "[window]\nunfocused = \"{}\"\nborders = \"{}\"\nfilter = \"{}\"\n\n",
+                self.unfocused.name(),
+                self.borders.name(),
+                self.filter
+            );
+        }
+
+        if !self.colorblind.is_empty() {
+            let mut games: Vec<_> = self.colorblind.iter().collect();
+            games.sort_by_key(|(game, _)| (*game != "default", *game));
+            text += "[colorblind]\n";
+            for (game, filter) in games {
+                let name = filter.map_or("none", ColorBlindness::name);
+                text += &format!("\"{game}\" = \"{name}\"\n");
+            }
+            text += "\n";
+        }
+
+        if self.unmapped != UnmappedPolicy::default() {
+            text += &format!("[debug]\nunmapped = \"{}\"\n\n", self.unmapped.name());
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let entries = parse_toml(text)?;
+        let defaults = parse_toml(DEFAULT_CONFIG)?;
+
+        // A section in the file replaces the whole default section, so
+        // rebinding a button doesn't leave the default binding behind.
+        let defaults = defaults
+            .iter()
+            .filter(|default| !entries.iter().any(|e| e.section == default.section));
+
+        let mut config = Config {
+            keys: HashMap::new(),
+            gamepad: Default::default(),
+            audio_device: None,
+            unfocused: Unfocused::default(),
+            borders: Borders::default(),
+            filter: filter::DEFAULT.to_string(),
+            colorblind: HashMap::new(),
+            unmapped: UnmappedPolicy::default(),
+            fallback: false,
+        };
+        for entry in defaults.chain(&entries) {
+            config.bind(entry)?;
+        }
+        Ok(config)
+    }
+
+    fn bind(&mut self, entry: &Entry) -> Result<(), String> {
+        let error = |message: String| format!("line {}: {message}", entry.line);
+
+        if entry.section == "audio" {
+            return match (entry.key.as_str(), entry.values.as_slice()) {
+                ("device", [device]) => {
+                    self.audio_device = Some(device.clone());
+                    Ok(())
+                }
+                ("device", _) => Err(error("expected a single audio device".to_string())),
+                (key, _) => Err(error(format!("unknown audio setting {key}"))),
+            };
+        }
+
+        if entry.section == "window" {
+            return match (entry.key.as_str(), entry.values.as_slice()) {
+                ("unfocused", [name]) => {
+                    self.unfocused = Unfocused::from_name(name)
+                        .ok_or_else(|| error(format!("unknown unfocused behavior {name}")))?;
+                    Ok(())
+                }
+                ("unfocused", _) => Err(error("expected a single behavior".to_string())),
+                ("borders", [name]) => {
+                    self.borders = Borders::from_name(name)
+                        .ok_or_else(|| error(format!("unknown borders {name}")))?;
+                    Ok(())
+                }
+                ("borders", _) => Err(error("expected a single border color".to_string())),
+                ("filter", [name]) => {
+                    if !filter::names().any(|filter| filter == name) {
+                        return Err(error(format!("unknown video filter {name}")));
+                    }
+                    self.filter = name.clone();
+                    Ok(())
+                }
+                ("filter", _) => Err(error("expected a single video filter".to_string())),
+                (key, _) => Err(error(format!("unknown window setting {key}"))),
+            };
+        }
+
+        if entry.section == "debug" {
+            return match (entry.key.as_str(), entry.values.as_slice()) {
+                ("unmapped", [name]) => {
+                    self.unmapped = name.parse().map_err(error)?;
+                    Ok(())
+                }
+                ("unmapped", _) => Err(error("expected a single policy".to_string())),
+                (key, _) => Err(error(format!("unknown debug setting {key}"))),
+            };
+        }
+
+        if entry.section == "colorblind" {
+            let [name] = entry.values.as_slice() else {
+                return Err(error("expected a single filter".to_string()));
+            };
+            let filter = match name.as_str() {
+                "none" => None,
+                _ => Some(
+                    ColorBlindness::from_name(name)
+                        .ok_or_else(|| error(format!("unknown color blindness {name}")))?,
+                ),
+            };
+            let game = match entry.key.as_str() {
+                "default" => "default".to_string(),
+                crc => {
+                    u32::from_str_radix(crc, 16)
+                        .map_err(|_| error(format!("invalid ROM CRC32 {crc}")))?;
+                    crc.to_ascii_uppercase()
+                }
+            };
+            self.colorblind.insert(game, filter);
+            return Ok(());
+        }
+
+        let (player, device) = entry
+            .section
+            .strip_prefix("player")
+            .and_then(|s| s.split_once('.'))
+            .ok_or_else(|| error(format!("unknown section [{}]", entry.section)))?;
+        let port = match player.parse::<usize>() {
+            Ok(player @ 1..=PLAYERS) => player - 1,
+            _ => return Err(error(format!("unknown player {player}"))),
+        };
+
+        let button = Button::from_name(&entry.key)
+            .ok_or_else(|| error(format!("unknown NES button {}", entry.key)))?;
+
+        for value in &entry.values {
+            match device {
+                "keyboard" => {
+                    let key = Keycode::from_name(value)
+                        .ok_or_else(|| error(format!("unknown key {value}")))?;
+                    self.keys.insert(key, (port, button));
+                }
+                "gamepad" => {
+                    let pad_button = PadButton::from_string(value)
+                        .ok_or_else(|| error(format!("unknown gamepad button {value}")))?;
+                    self.gamepad[port].insert(pad_button, button);
+                }
+                _ => return Err(error(format!("unknown device {device}"))),
+            }
+        }
+        Ok(())
+    }
+
+    // Makes `key` the only key of a NES button, taking it from any other
+    // button it was bound to
+    pub fn rebind_key(&mut self, port: usize, button: Button, key: Keycode) {
+        self.keys.retain(|_, binding| *binding != (port, button));
+        self.keys.insert(key, (port, button));
+    }
+
+    // Same as `rebind_key` for the gamepad of a controller port
+    pub fn rebind_gamepad(&mut self, port: usize, button: Button, pad_button: PadButton) {
+        let bindings = &mut self.gamepad[port];
+        bindings.retain(|_, bound| *bound != button);
+        bindings.insert(pad_button, button);
+    }
+
+    // The controller port and button bound to a key
+    pub fn key(&self, keycode: Keycode) -> Option<(usize, Button)> {
+        self.keys.get(&keycode).copied()
+    }
+
+    pub fn gamepad_button(&self, port: usize, button: PadButton) -> Option<Button> {
+        self.gamepad.get(port)?.get(&button).copied()
+    }
+
+    // The color blindness filter for the game with this ROM CRC32, see
+    // `patch::rom_crc32`
+    pub fn colorblind(&self, rom_crc: u32) -> Option<ColorBlindness> {
+        let game = self.colorblind.get(&format!("{rom_crc:08X}"));
+        *game.or_else(|| self.colorblind.get("default"))?
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::parse("").unwrap()
+    }
+}
+
+// Just enough TOML for the config file: `[section]` headers, `#` comments and
+// `key = "value"` or `key = ["value", ...]` pairs.
+fn parse_toml(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = vec![];
+    let mut section = String::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |message: &str| format!("line {line_number}: {message}");
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, rest) = header
+                .split_once(']')
+                .ok_or_else(|| error("unterminated section header"))?;
+            if !is_comment(rest) {
+                return Err(error("unexpected text after section header"));
+            }
+            section = name.trim().to_string();
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected `key = value`"))?;
+        let key = key.trim().trim_matches('"').to_string();
+
+        let value = value.trim();
+        let (values, rest) = if let Some(mut list) = value.strip_prefix('[') {
+            let mut values = vec![];
+            loop {
+                list = list.trim_start();
+                if let Some(rest) = list.strip_prefix(']') {
+                    break (values, rest);
+                }
+                let (value, rest) = parse_string(list).ok_or_else(|| error("expected a string"))?;
+                values.push(value);
+
+                list = rest.trim_start();
+                list = list.strip_prefix(',').unwrap_or(list);
+            }
+        } else {
+            let (value, rest) = parse_string(value).ok_or_else(|| error("expected a string"))?;
+            (vec![value], rest)
+        };
+        if !is_comment(rest) {
+            return Err(error("unexpected text after value"));
+        }
+
+        entries.push(Entry {
+            line: line_number,
+            section: section.clone(),
+            key,
+            values,
+        });
+    }
+
+    Ok(entries)
+}
+
+// Writes a section with a `button = "value"` or `button = [...]` line for
+// every NES button that has bindings, or nothing if none have
+fn write_section(text: &mut String, section: &str, values: impl Fn(Button) -> Vec<String>) {
+    let lines: Vec<String> = Button::ALL
+        .iter()
+        .filter_map(|&button| {
+            let values: Vec<String> = values(button).iter().map(|v| format!("\"{v}\"")).collect();
+            match values.as_slice() {
+                [] => None,
+                [value] => Some(format!("{} = {value}", button.name())),
+                _ => Some(format!("{} = [{}]", button.name(), values.join(", "))),
+            }
+        })
+        .collect();
+
+    if !lines.is_empty() {
+        *text += &format!("[{section}]\n{}\n\n", lines.join("\n"));
+    }
+}
+
+// Splits a leading double quoted string from the rest of the text
+fn parse_string(text: &str) -> Option<(String, &str)> {
+    let text = text.strip_prefix('"')?;
+    let end = text.find('"')?;
+    Some((text[..end].to_string(), &text[end + 1..]))
+}
+
+fn is_comment(text: &str) -> bool {
+    let text = text.trim();
+    text.is_empty() || text.starts_with('#')
+}
@@ -0,0 +1,67 @@
+use std::fmt::Write;
+
+pub const PRG_START: u16 = 0x8000;
+const PRG_SIZE: usize = 0x8000;
+
+// Marks which bytes of the PRG ROM window were executed, either as an opcode
+// or as the operand of one.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    executed: Vec<bool>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            executed: vec![false; PRG_SIZE],
+        }
+    }
+
+    pub fn record(&mut self, pc: u16, len: u16) {
+        for addr in pc..pc.saturating_add(len) {
+            if let Some(offset) = addr.checked_sub(PRG_START) {
+                self.executed[offset as usize] = true;
+            }
+        }
+    }
+
+    pub fn executed_bytes(&self) -> usize {
+        self.executed.iter().filter(|e| **e).count()
+    }
+
+    pub fn percentage(&self) -> f32 {
+        self.executed_bytes() as f32 * 100.0 / PRG_SIZE as f32
+    }
+
+    // Summary line followed by the executed address ranges, one per line.
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{} of {} PRG bytes executed ({:.2}%)\n",
+            self.executed_bytes(),
+            PRG_SIZE,
+            self.percentage()
+        );
+
+        let mut start = None;
+        for (offset, executed) in self.executed.iter().chain([&false]).enumerate() {
+            match (start, executed) {
+                (None, true) => start = Some(offset),
+                (Some(first), false) => {
+                    let first = PRG_START as usize + first;
+                    let last = PRG_START as usize + offset - 1;
+                    writeln!(report, "{first:04X}-{last:04X}").unwrap();
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        report
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
@@ -24,26 +24,110 @@ pub struct Cpu {
     negative_flag: bool,
 
     pub cycles: Wrapping<usize>,
+
+    // The 2A03 has the D flag but no BCD arithmetic, other 6502s have both
+    decimal_mode: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub pc: u16,
+    pub sp: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+}
+
+// What `Cpu::step` ran and where it left the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo {
+    // Address of the instruction
+    pub pc: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub addr_mode: AddressingMode,
+    operand: [u8; 2],
+    pub cycles: usize,
+    pub registers: Registers,
 }
 
-#[derive(Debug, Clone)]
-enum AddressingMode {
-    Implicit,
-    Accumulator,
-    Immediate,
-    ZeroPage,
-    ZeroPageX,
-    ZeroPageY,
-    Relative,
-    Absolute,
-    AbsoluteX,
-    AbsoluteY,
-    Indirect,
-    IndexedIndirect,
-    IndirectIndexed,
-}
-
-#[derive(Debug)]
+impl StepInfo {
+    // The bytes following the opcode
+    pub fn operand(&self) -> &[u8] {
+        &self.operand[..self.size() as usize - 1]
+    }
+
+    // Size of the instruction in bytes
+    pub fn size(&self) -> u16 {
+        self.addr_mode.len()
+    }
+}
+
+impl fmt::Display for StepInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = instruction_text(self.mnemonic, self.addr_mode, self.operand(), self.pc);
+        write!(f, "{text}")
+    }
+}
+
+// Operands are shown in the usual assembler syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implicit,        // CLC
+    Accumulator,     // ASL A
+    Immediate,       // LDA #$44
+    ZeroPage,        // LDA $44
+    ZeroPageX,       // LDA $44,X
+    ZeroPageY,       // LDX $44,Y
+    Relative,        // BNE $C012, an offset from the next instruction
+    Absolute,        // LDA $4400
+    AbsoluteX,       // LDA $4400,X
+    AbsoluteY,       // LDA $4400,Y
+    Indirect,        // JMP ($4400)
+    IndexedIndirect, // LDA ($44,X), pointer at zero page $44 + X
+    IndirectIndexed, // LDA ($44),Y, pointer at zero page $44, plus Y
+}
+
+impl AddressingMode {
+    fn len(&self) -> u16 {
+        use AddressingMode::*;
+        match self {
+            Implicit | Accumulator => 1,
+            Immediate | ZeroPage | ZeroPageX | ZeroPageY | Relative => 2,
+            IndexedIndirect | IndirectIndexed => 2,
+            Absolute | AbsoluteX | AbsoluteY | Indirect => 3,
+        }
+    }
+
+    // `operand` holds the bytes following the opcode, and `pc` is the address
+    // of the instruction, used to resolve branch targets.
+    pub fn format_operand(&self, operand: &[u8], pc: u16) -> String {
+        use AddressingMode::*;
+        let byte = operand.first().copied().unwrap_or(0);
+        let word = u16::from_le_bytes([byte, operand.get(1).copied().unwrap_or(0)]);
+        match self {
+            Implicit => String::new(),
+            Accumulator => "A".to_string(),
+            Immediate => format!("#${byte:02X}"),
+            ZeroPage => format!("${byte:02X}"),
+            ZeroPageX => format!("${byte:02X},X"),
+            ZeroPageY => format!("${byte:02X},Y"),
+            Relative => {
+                let target = pc.wrapping_add(2).wrapping_add_signed(byte as i8 as i16);
+                format!("${target:04X}")
+            }
+            Absolute => format!("${word:04X}"),
+            AbsoluteX => format!("${word:04X},X"),
+            AbsoluteY => format!("${word:04X},Y"),
+            Indirect => format!("(${word:04X})"),
+            IndexedIndirect => format!("(${byte:02X},X)"),
+            IndirectIndexed => format!("(${byte:02X}),Y"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum InstructionKind {
     Nop,
     Lda,
@@ -115,1389 +199,1020 @@ enum InstructionKind {
     Dcp,
 }
 
-#[derive(Debug)]
-struct Instruction {
-    kind: InstructionKind,
-    addr_mode: AddressingMode,
-    cycles: u32,
+impl InstructionKind {
+    fn mnemonic(&self) -> &'static str {
+        use InstructionKind::*;
+        match self {
+            Nop => "NOP",
+            Lda => "LDA",
+            Ldx => "LDX",
+            Ldy => "LDY",
+            Lax => "LAX",
+            Sta => "STA",
+            Stx => "STX",
+            Sty => "STY",
+            Sax => "SAX",
+            Tax => "TAX",
+            Tay => "TAY",
+            Txa => "TXA",
+            Tya => "TYA",
+            Tsx => "TSX",
+            Txs => "TXS",
+            Pha => "PHA",
+            Php => "PHP",
+            Pla => "PLA",
+            Plp => "PLP",
+            And => "AND",
+            Eor => "EOR",
+            Ora => "ORA",
+            Bit => "BIT",
+            Jmp => "JMP",
+            Jsr => "JSR",
+            Rts => "RTS",
+            Bne => "BNE",
+            Beq => "BEQ",
+            Bpl => "BPL",
+            Bcc => "BCC",
+            Bcs => "BCS",
+            Bmi => "BMI",
+            Bvc => "BVC",
+            Bvs => "BVS",
+            Dex => "DEX",
+            Dey => "DEY",
+            Inc => "INC",
+            Incx => "INX",
+            Incy => "INY",
+            Asl => "ASL",
+            AslAddr => "ASL",
+            Slo => "SLO",
+            Lsr => "LSR",
+            LsrAddr => "LSR",
+            Sre => "SRE",
+            Rol => "ROL",
+            RolAddr => "ROL",
+            Rla => "RLA",
+            Ror => "ROR",
+            RorAddr => "ROR",
+            Rra => "RRA",
+            Clc => "CLC",
+            Sec => "SEC",
+            Cld => "CLD",
+            Sed => "SED",
+            Cli => "CLI",
+            Sei => "SEI",
+            Clv => "CLV",
+            Cmp => "CMP",
+            Cpx => "CPX",
+            Cpy => "CPY",
+            Adc => "ADC",
+            Sbc => "SBC",
+            Brk => "BRK",
+            Rti => "RTI",
+            Isc => "ISC",
+            Dec => "DEC",
+            Dcp => "DCP",
+        }
+    }
+
+    const fn handler(self) -> fn(&mut Cpu, u16, &mut Asc) {
+        use InstructionKind::*;
+        match self {
+            Nop => nop,
+            Lda => lda,
+            Ldx => ldx,
+            Ldy => ldy,
+            Lax => lax,
+            Sta => sta,
+            Stx => stx,
+            Sty => sty,
+            Sax => sax,
+            Tax => tax,
+            Tay => tay,
+            Txa => txa,
+            Tya => tya,
+            Tsx => tsx,
+            Txs => txs,
+            Pha => pha,
+            Php => php,
+            Pla => pla,
+            Plp => plp,
+            And => and,
+            Eor => eor,
+            Ora => ora,
+            Bit => bit,
+            Jmp => jmp,
+            Jsr => jsr,
+            Rts => rts,
+            Bne => bne,
+            Beq => beq,
+            Bpl => bpl,
+            Bcc => bcc,
+            Bcs => bcs,
+            Bmi => bmi,
+            Bvc => bvc,
+            Bvs => bvs,
+            Dex => dex,
+            Dey => dey,
+            Inc => inc,
+            Incx => incx,
+            Incy => incy,
+            Asl => asl,
+            AslAddr => asl_addr,
+            Slo => slo,
+            Lsr => lsr,
+            LsrAddr => lsr_addr,
+            Sre => sre,
+            Rol => rol,
+            RolAddr => rol_addr,
+            Rla => rla,
+            Ror => ror,
+            RorAddr => ror_addr,
+            Rra => rra,
+            Clc => clc,
+            Sec => sec,
+            Cld => cld,
+            Sed => sed,
+            Cli => cli,
+            Sei => sei,
+            Clv => clv,
+            Cmp => cmp,
+            Cpx => cpx,
+            Cpy => cpy,
+            Adc => adc,
+            Sbc => sbc,
+            Brk => brk,
+            Rti => rti,
+            Isc => isc,
+            Dec => dec,
+            Dcp => dcp,
+        }
+    }
 }
 
-trait InstructionTrait {
-    fn instr(cpu: &mut Cpu, addr: u16, mem: &mut Asc);
-
-    fn run_with(addr_mode: AddressingMode, cpu: &mut Cpu, mem: &mut Asc) -> Instruction;
+// What each instruction does, given the address of its operand, 0 for the
+// implicit and accumulator ones. They get PC on the last byte of the
+// instruction and move it on.
+fn nop(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.pc += 1;
 }
 
-macro_rules! impl_instr {
-    ($instruction:ident, $instruction_logic:expr, $cycles: expr) => {
-        struct $instruction;
-        impl InstructionTrait for $instruction {
-            fn instr(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
-                $instruction_logic(cpu, addr, mem);
-            }
+fn lda(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    cpu.a = mem.read(addr);
 
-            fn run_with(addr_mode: AddressingMode, cpu: &mut Cpu, mem: &mut Asc) -> Instruction {
-                use AddressingMode::*;
-                let addr = match addr_mode {
-                    Implicit | Accumulator => 0,
-                    Immediate => cpu.imm(mem),
-                    ZeroPage | Relative => cpu.zp(mem),
-                    ZeroPageX => cpu.zpx(mem),
-                    ZeroPageY => cpu.zpy(mem),
-                    Absolute => cpu.abs(mem),
-                    AbsoluteX => cpu.abx(mem),
-                    AbsoluteY => cpu.aby(mem),
-                    Indirect => cpu.ind(mem),
-                    IndirectIndexed => cpu.inx(mem),
-                    IndexedIndirect => cpu.iny(mem),
-                };
+    cpu.zero_flag = cpu.a == 0;
 
-                Self::instr(cpu, addr, mem);
-                let cycles = $cycles(addr_mode.clone());
-                Instruction {
-                    kind: InstructionKind::$instruction,
-                    addr_mode,
-                    cycles,
-                }
-            }
-        }
-    };
-}
-
-impl_instr!(
-    Nop,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            Absolute => 4,
-            AbsoluteX => 4,
-            _ => unimplemented!(),
-        }
-    }
-);
-
-impl_instr!(
-    Lda,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        cpu.a = mem.read(addr);
+    cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
 
-        cpu.zero_flag = cpu.a == 0;
+    cpu.pc += 1;
+}
 
-        cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
+fn ldx(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    cpu.x = mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            Absolute => 4,
-            AbsoluteX => 4,
-            AbsoluteY => 4,
-            IndirectIndexed => 6,
-            IndexedIndirect => 5,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.x == 0;
+    cpu.negative_flag = cpu.x & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Ldx,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        cpu.x = mem.read(addr);
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.x == 0;
-        cpu.negative_flag = cpu.x & NEGATIVE_MASK != 0;
+fn ldy(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    cpu.y = mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageY => 4,
-            Absolute => 4,
-            AbsoluteY => 4,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.y == 0;
+    cpu.negative_flag = cpu.y & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Ldy,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        cpu.y = mem.read(addr);
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.y == 0;
-        cpu.negative_flag = cpu.y & NEGATIVE_MASK != 0;
+fn lax(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let value = mem.read(addr);
+    cpu.x = value;
+    cpu.a = value;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            Absolute => 4,
-            AbsoluteX => 4,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.a == 0;
+    cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Lax,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let value = mem.read(addr);
-        cpu.x = value;
-        cpu.a = value;
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.a == 0;
-        cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
+fn sta(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    mem.write(addr, cpu.a);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 3,
-            ZeroPageY => 4,
-            Absolute => 4,
-            AbsoluteY => 4,
-            IndexedIndirect => 6,
-            IndirectIndexed => 5,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Sta,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        mem.write(addr, cpu.a);
+fn stx(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    mem.write(addr, cpu.x);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            Absolute => 4,
-            AbsoluteX => 5,
-            AbsoluteY => 5,
-            IndexedIndirect => 6,
-            IndirectIndexed => 6,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Stx,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        mem.write(addr, cpu.x);
+fn sty(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    mem.write(addr, cpu.y);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 3,
-            ZeroPageY => 4,
-            Absolute => 4,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Sty,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        mem.write(addr, cpu.y);
+fn sax(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    mem.write(addr, cpu.a & cpu.x);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            Absolute => 4,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Sax,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        mem.write(addr, cpu.a & cpu.x);
+fn tax(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.x = cpu.a;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 3,
-            ZeroPageY => 4,
-            Absolute => 4,
-            IndirectIndexed => 6,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.x == 0;
+    cpu.negative_flag = cpu.x & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Tax,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.x = cpu.a;
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.x == 0;
-        cpu.negative_flag = cpu.x & NEGATIVE_MASK != 0;
+fn tay(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.y = cpu.a;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.y == 0;
+    cpu.negative_flag = cpu.y & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Tay,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.y = cpu.a;
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.y == 0;
-        cpu.negative_flag = cpu.y & NEGATIVE_MASK != 0;
+fn txa(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.a = cpu.x;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.a == 0;
+    cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Txa,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.a = cpu.x;
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.a == 0;
-        cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
+fn tya(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.a = cpu.y;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.a == 0;
+    cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Tya,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.a = cpu.y;
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.a == 0;
-        cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
+fn tsx(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.x = cpu.sp;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.x == 0;
+    cpu.negative_flag = cpu.x & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Tsx,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.x = cpu.sp;
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.x == 0;
-        cpu.negative_flag = cpu.x & NEGATIVE_MASK != 0;
+fn txs(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.sp = cpu.x;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Txs,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.sp = cpu.x;
+fn pha(cpu: &mut Cpu, _addr: u16, mem: &mut Asc) {
+    cpu.push(cpu.a, mem);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Pha,
-    |cpu: &mut Cpu, _addr: u16, mem: &mut Asc| {
-        cpu.push(cpu.a, mem);
+fn php(cpu: &mut Cpu, _addr: u16, mem: &mut Asc) {
+    // TODO: Find if this is realy correct
+    cpu.reserved_flag = true;
+    cpu.break_cmd_flag = true;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 3,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.push(cpu.status_to_word(), mem);
 
-impl_instr!(
-    Php,
-    |cpu: &mut Cpu, _addr: u16, mem: &mut Asc| {
-        // TODO: Find if this is realy correct
-        cpu.reserved_flag = true;
-        cpu.break_cmd_flag = true;
+    cpu.pc += 1;
+}
 
-        cpu.push(cpu.status_to_word(), mem);
+fn pla(cpu: &mut Cpu, _addr: u16, mem: &mut Asc) {
+    cpu.a = cpu.pop(mem);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 3,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.a == 0;
+    cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Pla,
-    |cpu: &mut Cpu, _addr: u16, mem: &mut Asc| {
-        cpu.a = cpu.pop(mem);
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.a == 0;
-        cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
+fn plp(cpu: &mut Cpu, _addr: u16, mem: &mut Asc) {
+    let status = cpu.pop(mem);
+    cpu.word_to_status(status);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 4,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Plp,
-    |cpu: &mut Cpu, _addr: u16, mem: &mut Asc| {
-        let status = cpu.pop(mem);
-        cpu.word_to_status(status);
+fn and(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    cpu.a &= mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 4,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.a == 0;
+    cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    And,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        cpu.a &= mem.read(addr);
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.a == 0;
-        cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
+fn eor(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    cpu.a ^= mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            ZeroPageY => 4,
-            Absolute => 4,
-            AbsoluteX => 4,
-            AbsoluteY => 4,
-            IndirectIndexed => 6,
-            IndexedIndirect => 5,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.a == 0;
+    cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Eor,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        cpu.a ^= mem.read(addr);
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.a == 0;
-        cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
+fn ora(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    cpu.a |= mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            ZeroPageY => 4,
-            Absolute => 4,
-            AbsoluteX => 4,
-            AbsoluteY => 4,
-            IndexedIndirect => 6,
-            IndirectIndexed => 5,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.a == 0;
+    cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Ora,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        cpu.a |= mem.read(addr);
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.a == 0;
-        cpu.negative_flag = cpu.a & NEGATIVE_MASK != 0;
+fn bit(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let value = mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            ZeroPageY => 4,
-            Absolute => 4,
-            AbsoluteX => 4,
-            AbsoluteY => 4,
-            IndexedIndirect => 6,
-            IndirectIndexed => 5,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.a & value == 0;
+    cpu.overflow_flag = value & 1 << 6 != 0;
+    cpu.negative_flag = value & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Bit,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let value = mem.read(addr);
+    cpu.pc += 1;
+}
 
-        cpu.zero_flag = cpu.a & value == 0;
-        cpu.overflow_flag = value & 1 << 6 != 0;
-        cpu.negative_flag = value & NEGATIVE_MASK != 0;
+fn jmp(cpu: &mut Cpu, addr: u16, _mem: &mut Asc) {
+    cpu.pc = addr;
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 3,
-            Absolute => 4,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn jsr(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    cpu.push_long(cpu.pc, mem);
+    cpu.pc = addr;
+}
 
-impl_instr!(
-    Jmp,
-    |cpu: &mut Cpu, addr: u16, _mem: &mut Asc| {
-        cpu.pc = addr;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Absolute => 3,
-            Indirect => 5,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn rts(cpu: &mut Cpu, _addr: u16, mem: &mut Asc) {
+    let addr = cpu.pop_long(mem);
+    cpu.pc = addr.wrapping_add(1);
+}
 
-impl_instr!(
-    Jsr,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        cpu.push_long(cpu.pc, mem);
-        cpu.pc = addr;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Absolute => 6,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn bne(cpu: &mut Cpu, addr: u16, _mem: &mut Asc) {
+    cpu.branch(!cpu.zero_flag, addr);
+}
 
-impl_instr!(
-    Rts,
-    |cpu: &mut Cpu, _addr: u16, mem: &mut Asc| {
-        let addr = cpu.pop_long(mem);
-        cpu.pc = addr.wrapping_add(1);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 6,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn beq(cpu: &mut Cpu, addr: u16, _mem: &mut Asc) {
+    cpu.branch(cpu.zero_flag, addr);
+}
 
-impl_instr!(
-    Bne,
-    |cpu: &mut Cpu, addr: u16, _mem: &mut Asc| {
-        if !cpu.zero_flag {
-            cpu.pc = cpu.pc.wrapping_add_signed((addr as i8) as i16);
-        }
+fn bpl(cpu: &mut Cpu, addr: u16, _mem: &mut Asc) {
+    cpu.branch(!cpu.negative_flag, addr);
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Relative => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn bcc(cpu: &mut Cpu, addr: u16, _mem: &mut Asc) {
+    cpu.branch(!cpu.carry_flag, addr);
+}
 
-impl_instr!(
-    Beq,
-    |cpu: &mut Cpu, addr: u16, _mem: &mut Asc| {
-        if cpu.zero_flag {
-            cpu.pc = cpu.pc.wrapping_add_signed((addr as i8) as i16);
-        }
+fn bcs(cpu: &mut Cpu, addr: u16, _mem: &mut Asc) {
+    cpu.branch(cpu.carry_flag, addr);
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Relative => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn bmi(cpu: &mut Cpu, addr: u16, _mem: &mut Asc) {
+    cpu.branch(cpu.negative_flag, addr);
+}
 
-impl_instr!(
-    Bpl,
-    |cpu: &mut Cpu, addr: u16, _mem: &mut Asc| {
-        if !cpu.negative_flag {
-            cpu.pc = cpu.pc.wrapping_add_signed((addr as i8) as i16);
-        }
+fn bvc(cpu: &mut Cpu, addr: u16, _mem: &mut Asc) {
+    cpu.branch(!cpu.overflow_flag, addr);
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Relative => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn bvs(cpu: &mut Cpu, addr: u16, _mem: &mut Asc) {
+    cpu.branch(cpu.overflow_flag, addr);
+}
 
-impl_instr!(
-    Bcc,
-    |cpu: &mut Cpu, addr: u16, _mem: &mut Asc| {
-        if !cpu.carry_flag {
-            cpu.pc = cpu.pc.wrapping_add_signed((addr as i8) as i16);
-        }
+fn dex(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.x = cpu.x.wrapping_sub(1);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Relative => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = cpu.x == 0;
+    cpu.negative_flag = (cpu.x >> 7) & 1 == 1;
 
-impl_instr!(
-    Bcs,
-    |cpu: &mut Cpu, addr: u16, _mem: &mut Asc| {
-        if cpu.carry_flag {
-            cpu.pc = cpu.pc.wrapping_add_signed((addr as i8) as i16);
-        }
+    cpu.pc += 1;
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Relative => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn dey(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.y = cpu.y.wrapping_sub(1);
 
-impl_instr!(
-    Bmi,
-    |cpu: &mut Cpu, addr: u16, _mem: &mut Asc| {
-        if cpu.negative_flag {
-            cpu.pc = cpu.pc.wrapping_add_signed((addr as i8) as i16);
-        }
+    cpu.zero_flag = cpu.y == 0;
+    cpu.negative_flag = (cpu.y >> 7) & 1 == 1;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Relative => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Bvc,
-    |cpu: &mut Cpu, addr: u16, _mem: &mut Asc| {
-        if !cpu.overflow_flag {
-            cpu.pc = cpu.pc.wrapping_add_signed((addr as i8) as i16);
-        }
+fn inc(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let mut value = mem.read(addr);
+    value = value.wrapping_add(1);
+    mem.write(addr, value);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Relative => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = value == 0;
+    cpu.negative_flag = value & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Bvs,
-    |cpu: &mut Cpu, addr: u16, _mem: &mut Asc| {
-        if cpu.overflow_flag {
-            cpu.pc = cpu.pc.wrapping_add_signed((addr as i8) as i16);
-        }
+    cpu.pc = cpu.pc.wrapping_add(1);
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Relative => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn incx(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.x = cpu.x.wrapping_add(1);
 
-impl_instr!(
-    Dex,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.x = cpu.x.wrapping_sub(1);
+    cpu.zero_flag = cpu.x == 0;
+    cpu.negative_flag = (cpu.x >> 7) & 1 == 1;
 
-        cpu.zero_flag = cpu.x == 0;
-        cpu.negative_flag = (cpu.x >> 7) & 1 == 1;
+    cpu.pc += 1;
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn incy(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.y = cpu.y.wrapping_add(1);
 
-impl_instr!(
-    Dey,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.y = cpu.y.wrapping_sub(1);
+    cpu.zero_flag = cpu.y == 0;
+    cpu.negative_flag = (cpu.y >> 7) & 1 == 1;
 
-        cpu.zero_flag = cpu.y == 0;
-        cpu.negative_flag = (cpu.y >> 7) & 1 == 1;
+    cpu.pc += 1;
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn asl(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.a = cpu.shift_left(cpu.a);
+}
 
-impl_instr!(
-    Inc,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let mut value = mem.read(addr);
-        value = value.wrapping_add(1);
-        mem.write(addr, value);
+fn asl_addr(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let mut value = mem.read(addr);
+    value = cpu.shift_left(value);
+    mem.write(addr, value);
+}
 
-        cpu.zero_flag = value == 0;
-        cpu.negative_flag = value & NEGATIVE_MASK != 0;
+fn slo(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    asl_addr(cpu, addr, mem);
+    cpu.pc = cpu.pc.wrapping_sub(1);
+    ora(cpu, addr, mem);
+}
 
-        cpu.pc = cpu.pc.wrapping_add(1);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn lsr(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.a = cpu.shift_right(cpu.a);
+}
 
-impl_instr!(
-    Incx,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.x = cpu.x.wrapping_add(1);
+fn lsr_addr(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let mut value = mem.read(addr);
+    value = cpu.shift_right(value);
+    mem.write(addr, value);
+}
 
-        cpu.zero_flag = cpu.x == 0;
-        cpu.negative_flag = (cpu.x >> 7) & 1 == 1;
+fn sre(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    lsr_addr(cpu, addr, mem);
+    cpu.pc = cpu.pc.wrapping_sub(1);
+    eor(cpu, addr, mem);
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn rol(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.a = cpu.rotate_left(cpu.a);
+}
 
-impl_instr!(
-    Incy,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.y = cpu.y.wrapping_add(1);
+fn rol_addr(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let mut value = mem.read(addr);
+    value = cpu.rotate_left(value);
+    mem.write(addr, value);
+}
 
-        cpu.zero_flag = cpu.y == 0;
-        cpu.negative_flag = (cpu.y >> 7) & 1 == 1;
+fn rla(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    rol_addr(cpu, addr, mem);
+    cpu.pc = cpu.pc.wrapping_sub(1);
+    and(cpu, addr, mem);
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn ror(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.a = cpu.rotate_right(cpu.a);
+}
 
-impl_instr!(
-    Asl,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.a = cpu.shift_left(cpu.a);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Accumulator => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn ror_addr(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let mut value = mem.read(addr);
+    value = cpu.rotate_right(value);
+    mem.write(addr, value);
+}
 
-impl_instr!(
-    AslAddr,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let mut value = mem.read(addr);
-        value = cpu.shift_left(value);
-        mem.write(addr, value);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn rra(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    ror_addr(cpu, addr, mem);
+    cpu.pc = cpu.pc.wrapping_sub(1);
+    adc(cpu, addr, mem);
+}
 
-impl_instr!(
-    Slo,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        AslAddr::instr(cpu, addr, mem);
-        cpu.pc = cpu.pc.wrapping_sub(1);
-        Ora::instr(cpu, addr, mem);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            AbsoluteY => 7,
-            IndexedIndirect => 8,
-            IndirectIndexed => 8,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn clc(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.carry_flag = false;
 
-impl_instr!(
-    Lsr,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.a = cpu.shift_right(cpu.a);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Accumulator => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    LsrAddr,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let mut value = mem.read(addr);
-        value = cpu.shift_right(value);
-        mem.write(addr, value);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn sec(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.carry_flag = true;
 
-impl_instr!(
-    Sre,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        LsrAddr::instr(cpu, addr, mem);
-        cpu.pc = cpu.pc.wrapping_sub(1);
-        Eor::instr(cpu, addr, mem);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            AbsoluteY => 7,
-            IndexedIndirect => 8,
-            IndirectIndexed => 8,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Rol,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.a = cpu.rotate_left(cpu.a);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Accumulator => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn cld(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.decimal_flag = false;
 
-impl_instr!(
-    RolAddr,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let mut value = mem.read(addr);
-        value = cpu.rotate_left(value);
-        mem.write(addr, value);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Rla,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        RolAddr::instr(cpu, addr, mem);
-        cpu.pc = cpu.pc.wrapping_sub(1);
-        And::instr(cpu, addr, mem);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            AbsoluteY => 7,
-            IndexedIndirect => 8,
-            IndirectIndexed => 8,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn sed(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.decimal_flag = true;
 
-impl_instr!(
-    Ror,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.a = cpu.rotate_right(cpu.a);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Accumulator => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    RorAddr,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let mut value = mem.read(addr);
-        value = cpu.rotate_right(value);
-        mem.write(addr, value);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn cli(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.interrupt_flag = false;
 
-impl_instr!(
-    Rra,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        RorAddr::instr(cpu, addr, mem);
-        cpu.pc = cpu.pc.wrapping_sub(1);
-        Adc::instr(cpu, addr, mem);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            AbsoluteY => 7,
-            IndexedIndirect => 8,
-            IndirectIndexed => 8,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Clc,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.carry_flag = false;
+fn sei(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.interrupt_flag = true;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Sec,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.carry_flag = true;
+fn clv(cpu: &mut Cpu, _addr: u16, _mem: &mut Asc) {
+    cpu.overflow_flag = false;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Cld,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.decimal_flag = false;
+fn cmp(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let value = mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    let res = cpu.a.wrapping_sub(value);
 
-impl_instr!(
-    Sed,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.decimal_flag = true;
+    cpu.carry_flag = cpu.a >= value;
+    cpu.zero_flag = cpu.a == value;
+    cpu.negative_flag = res & NEGATIVE_MASK != 0;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Cli,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.interrupt_flag = false;
+fn cpx(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let value = mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    let res = cpu.x.wrapping_sub(value);
 
-impl_instr!(
-    Sei,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.interrupt_flag = true;
+    cpu.carry_flag = cpu.x >= value;
+    cpu.zero_flag = cpu.x == value;
+    cpu.negative_flag = res & NEGATIVE_MASK != 0;
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.pc += 1;
+}
 
-impl_instr!(
-    Clv,
-    |cpu: &mut Cpu, _addr: u16, _mem: &mut Asc| {
-        cpu.overflow_flag = false;
+fn cpy(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let value = mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 2,
-            _ => unimplemented!(),
-        }
-    }
-);
+    let res = cpu.y.wrapping_sub(value);
 
-impl_instr!(
-    Cmp,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let value = mem.read(addr);
+    cpu.carry_flag = cpu.y >= value;
+    cpu.zero_flag = cpu.y == value;
+    cpu.negative_flag = res & NEGATIVE_MASK != 0;
 
-        let res = cpu.a.wrapping_sub(value);
+    cpu.pc += 1;
+}
 
-        cpu.carry_flag = cpu.a >= value;
-        cpu.zero_flag = cpu.a == value;
-        cpu.negative_flag = res & NEGATIVE_MASK != 0;
+fn adc(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let value = mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            Absolute => 4,
-            AbsoluteX => 4,
-            AbsoluteY => 4,
-            IndexedIndirect => 6,
-            IndirectIndexed => 5,
-            _ => unimplemented!(),
-        }
+    if cpu.decimal_mode && cpu.decimal_flag {
+        cpu.add_decimal(value);
+    } else {
+        cpu.add_with_carry(value);
     }
-);
 
-impl_instr!(
-    Cpx,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let value = mem.read(addr);
-
-        let res = cpu.x.wrapping_sub(value);
+    cpu.pc += 1;
+}
 
-        cpu.carry_flag = cpu.x >= value;
-        cpu.zero_flag = cpu.x == value;
-        cpu.negative_flag = res & NEGATIVE_MASK != 0;
+fn sbc(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let value = mem.read(addr);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            Absolute => 4,
-            _ => unimplemented!(),
-        }
+    if cpu.decimal_mode && cpu.decimal_flag {
+        cpu.subtract_decimal(value);
+    } else {
+        cpu.add_with_carry(!value);
     }
-);
 
-impl_instr!(
-    Cpy,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let value = mem.read(addr);
-
-        let res = cpu.y.wrapping_sub(value);
-
-        cpu.carry_flag = cpu.y >= value;
-        cpu.zero_flag = cpu.y == value;
-        cpu.negative_flag = res & NEGATIVE_MASK != 0;
+    cpu.pc += 1;
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            Absolute => 4,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn brk(cpu: &mut Cpu, _addr: u16, mem: &mut Asc) {
+    cpu.push_long(cpu.pc + 2, mem);
+    cpu.break_cmd_flag = true;
+    cpu.reserved_flag = true;
+    cpu.push(cpu.status_to_word(), mem);
 
-impl_instr!(
-    Adc,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let value = mem.read(addr);
+    let mut addr: u16 = mem.read(0xfffe) as u16;
+    addr |= (mem.read(0xffff) as u16) << 8;
 
-        cpu.add_with_carry(value);
+    cpu.pc = addr;
+    cpu.interrupt_flag = true;
+}
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            Absolute => 4,
-            AbsoluteX => 4,
-            AbsoluteY => 4,
-            IndexedIndirect => 6,
-            IndirectIndexed => 5,
-            _ => unimplemented!(),
-        }
-    }
-);
+fn rti(cpu: &mut Cpu, _addr: u16, mem: &mut Asc) {
+    let word = cpu.pop(mem);
+    cpu.word_to_status(word);
+    cpu.pc = cpu.pop_long(mem);
+}
 
-impl_instr!(
-    Sbc,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let value = mem.read(addr);
+fn isc(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    inc(cpu, addr, mem);
+    cpu.pc = cpu.pc.wrapping_sub(1);
+    sbc(cpu, addr, mem);
+}
 
-        cpu.add_with_carry(!value);
+fn dec(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    let mut value = mem.read(addr);
+    value = value.wrapping_sub(1);
+    mem.write(addr, value);
 
-        cpu.pc += 1;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Immediate => 2,
-            ZeroPage => 3,
-            ZeroPageX => 4,
-            Absolute => 4,
-            AbsoluteX => 4,
-            AbsoluteY => 4,
-            IndexedIndirect => 6,
-            IndirectIndexed => 5,
-            _ => unimplemented!(),
-        }
-    }
-);
+    cpu.zero_flag = value == 0;
+    cpu.negative_flag = value & NEGATIVE_MASK != 0;
 
-impl_instr!(
-    Brk,
-    |cpu: &mut Cpu, _addr: u16, mem: &mut Asc| {
-        cpu.push_long(cpu.pc + 2, mem);
-        cpu.break_cmd_flag = true;
-        cpu.reserved_flag = true;
-        cpu.push(cpu.status_to_word(), mem);
+    cpu.pc = cpu.pc.wrapping_add(1);
+}
 
-        let mut addr: u16 = mem.read(0xfffe) as u16;
-        addr |= (mem.read(0xffff) as u16) << 8;
+fn dcp(cpu: &mut Cpu, addr: u16, mem: &mut Asc) {
+    dec(cpu, addr, mem);
+    cpu.pc = cpu.pc.wrapping_sub(1);
+    cmp(cpu, addr, mem);
+}
 
-        cpu.pc = addr;
-        cpu.interrupt_flag = true;
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 7,
-            _ => unimplemented!(),
-        }
-    }
-);
+// Every opcode the CPU implements: the instruction, how it gets its operand
+// and the cycles it takes, plus one when `page_cross` is set and indexing
+// the operand address crosses a page. Branches add their own extra cycles.
+#[derive(Debug, Clone, Copy)]
+struct Opcode {
+    opcode: u8,
+    kind: InstructionKind,
+    addr_mode: AddressingMode,
+    cycles: u8,
+    page_cross: bool,
+    run: fn(&mut Cpu, u16, &mut Asc),
+}
 
-impl_instr!(
-    Rti,
-    |cpu: &mut Cpu, _addr: u16, mem: &mut Asc| {
-        let word = cpu.pop(mem);
-        cpu.word_to_status(word);
-        cpu.pc = cpu.pop_long(mem);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            Implicit => 6,
-            _ => unimplemented!(),
+impl Opcode {
+    const fn page_cross(self) -> Opcode {
+        Opcode {
+            page_cross: true,
+            ..self
         }
     }
-);
+}
 
-impl_instr!(
-    Isc,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        Inc::instr(cpu, addr, mem);
-        cpu.pc = cpu.pc.wrapping_sub(1);
-        Sbc::instr(cpu, addr, mem);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            AbsoluteY => 7,
-            IndexedIndirect => 8,
-            IndirectIndexed => 8,
-            _ => unimplemented!(),
-        }
+const fn op(opcode: u8, kind: InstructionKind, addr_mode: AddressingMode, cycles: u8) -> Opcode {
+    Opcode {
+        opcode,
+        kind,
+        addr_mode,
+        cycles,
+        page_cross: false,
+        run: kind.handler(),
     }
-);
+}
 
-impl_instr!(
-    Dec,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        let mut value = mem.read(addr);
-        value = value.wrapping_sub(1);
-        mem.write(addr, value);
+#[rustfmt::skip]
+const OPCODE_LIST: &[Opcode] = {
+    use AddressingMode::*;
+    use InstructionKind::*;
+    &[
+        op(0xEA, Nop, Implicit, 2),
+        op(0x1A, Nop, Implicit, 2),
+        op(0x3A, Nop, Implicit, 2),
+        op(0x5A, Nop, Implicit, 2),
+        op(0x7A, Nop, Implicit, 2),
+        op(0xDA, Nop, Implicit, 2),
+        op(0xFA, Nop, Implicit, 2),
+        op(0x80, Nop, Immediate, 2),
+        op(0x82, Nop, Immediate, 2),
+        op(0x89, Nop, Immediate, 2),
+        op(0xC2, Nop, Immediate, 2),
+        op(0xE2, Nop, Immediate, 2),
+        op(0x04, Nop, ZeroPage, 3),
+        op(0x44, Nop, ZeroPage, 3),
+        op(0x64, Nop, ZeroPage, 3),
+        op(0x14, Nop, ZeroPageX, 4),
+        op(0x34, Nop, ZeroPageX, 4),
+        op(0x54, Nop, ZeroPageX, 4),
+        op(0x74, Nop, ZeroPageX, 4),
+        op(0xD4, Nop, ZeroPageX, 4),
+        op(0xF4, Nop, ZeroPageX, 4),
+        op(0x0C, Nop, Absolute, 4),
+        op(0x1C, Nop, AbsoluteX, 4).page_cross(),
+        op(0x3C, Nop, AbsoluteX, 4).page_cross(),
+        op(0x5C, Nop, AbsoluteX, 4).page_cross(),
+        op(0x7C, Nop, AbsoluteX, 4).page_cross(),
+        op(0xDC, Nop, AbsoluteX, 4).page_cross(),
+        op(0xFC, Nop, AbsoluteX, 4).page_cross(),
+
+        op(0xA9, Lda, Immediate, 2),
+        op(0xA5, Lda, ZeroPage, 3),
+        op(0xB5, Lda, ZeroPageX, 4),
+        op(0xAD, Lda, Absolute, 4),
+        op(0xBD, Lda, AbsoluteX, 4).page_cross(),
+        op(0xB9, Lda, AbsoluteY, 4).page_cross(),
+        op(0xA1, Lda, IndexedIndirect, 6),
+        op(0xB1, Lda, IndirectIndexed, 5).page_cross(),
+
+        op(0xA2, Ldx, Immediate, 2),
+        op(0xA6, Ldx, ZeroPage, 3),
+        op(0xB6, Ldx, ZeroPageY, 4),
+        op(0xAE, Ldx, Absolute, 4),
+        op(0xBE, Ldx, AbsoluteY, 4).page_cross(),
+
+        op(0xA0, Ldy, Immediate, 2),
+        op(0xA4, Ldy, ZeroPage, 3),
+        op(0xB4, Ldy, ZeroPageX, 4),
+        op(0xAC, Ldy, Absolute, 4),
+        op(0xBC, Ldy, AbsoluteX, 4).page_cross(),
+
+        op(0xA7, Lax, ZeroPage, 3),
+        op(0xB7, Lax, ZeroPageY, 4),
+        op(0xAF, Lax, Absolute, 4),
+        op(0xBF, Lax, AbsoluteY, 4).page_cross(),
+        op(0xA3, Lax, IndexedIndirect, 6),
+        op(0xB3, Lax, IndirectIndexed, 5).page_cross(),
+
+        op(0x85, Sta, ZeroPage, 3),
+        op(0x95, Sta, ZeroPageX, 4),
+        op(0x8D, Sta, Absolute, 4),
+        op(0x9D, Sta, AbsoluteX, 5),
+        op(0x99, Sta, AbsoluteY, 5),
+        op(0x81, Sta, IndexedIndirect, 6),
+        op(0x91, Sta, IndirectIndexed, 6),
+
+        op(0x86, Stx, ZeroPage, 3),
+        op(0x96, Stx, ZeroPageY, 4),
+        op(0x8E, Stx, Absolute, 4),
+
+        op(0x84, Sty, ZeroPage, 3),
+        op(0x94, Sty, ZeroPageX, 4),
+        op(0x8C, Sty, Absolute, 4),
+
+        op(0x87, Sax, ZeroPage, 3),
+        op(0x97, Sax, ZeroPageY, 4),
+        op(0x8F, Sax, Absolute, 4),
+        op(0x83, Sax, IndexedIndirect, 6),
+
+        op(0xAA, Tax, Implicit, 2),
+
+        op(0xA8, Tay, Implicit, 2),
+
+        op(0x8A, Txa, Implicit, 2),
+
+        op(0x98, Tya, Implicit, 2),
+
+        op(0xBA, Tsx, Implicit, 2),
+
+        op(0x9A, Txs, Implicit, 2),
+
+        op(0x48, Pha, Implicit, 3),
+
+        op(0x08, Php, Implicit, 3),
+
+        op(0x68, Pla, Implicit, 4),
+
+        op(0x28, Plp, Implicit, 4),
+
+        op(0x29, And, Immediate, 2),
+        op(0x25, And, ZeroPage, 3),
+        op(0x35, And, ZeroPageX, 4),
+        op(0x2D, And, Absolute, 4),
+        op(0x3D, And, AbsoluteX, 4).page_cross(),
+        op(0x39, And, AbsoluteY, 4).page_cross(),
+        op(0x21, And, IndexedIndirect, 6),
+        op(0x31, And, IndirectIndexed, 5).page_cross(),
+
+        op(0x49, Eor, Immediate, 2),
+        op(0x45, Eor, ZeroPage, 3),
+        op(0x55, Eor, ZeroPageX, 4),
+        op(0x4D, Eor, Absolute, 4),
+        op(0x5D, Eor, AbsoluteX, 4).page_cross(),
+        op(0x59, Eor, AbsoluteY, 4).page_cross(),
+        op(0x41, Eor, IndexedIndirect, 6),
+        op(0x51, Eor, IndirectIndexed, 5).page_cross(),
+
+        op(0x09, Ora, Immediate, 2),
+        op(0x05, Ora, ZeroPage, 3),
+        op(0x15, Ora, ZeroPageX, 4),
+        op(0x0D, Ora, Absolute, 4),
+        op(0x1D, Ora, AbsoluteX, 4).page_cross(),
+        op(0x19, Ora, AbsoluteY, 4).page_cross(),
+        op(0x01, Ora, IndexedIndirect, 6),
+        op(0x11, Ora, IndirectIndexed, 5).page_cross(),
+
+        op(0x24, Bit, ZeroPage, 3),
+        op(0x2C, Bit, Absolute, 4),
+
+        op(0x4C, Jmp, Absolute, 3),
+        op(0x6C, Jmp, Indirect, 5),
+
+        op(0x20, Jsr, Absolute, 6),
+
+        op(0x60, Rts, Implicit, 6),
+
+        op(0xD0, Bne, Relative, 2),
+        op(0xF0, Beq, Relative, 2),
+        op(0x10, Bpl, Relative, 2),
+        op(0x90, Bcc, Relative, 2),
+        op(0xB0, Bcs, Relative, 2),
+        op(0x30, Bmi, Relative, 2),
+        op(0x50, Bvc, Relative, 2),
+        op(0x70, Bvs, Relative, 2),
+
+        op(0xCA, Dex, Implicit, 2),
+        op(0x88, Dey, Implicit, 2),
+
+        op(0xE8, Incx, Implicit, 2),
+        op(0xC8, Incy, Implicit, 2),
+
+        op(0x0A, Asl, Accumulator, 2),
+        op(0x06, AslAddr, ZeroPage, 5),
+        op(0x16, AslAddr, ZeroPageX, 6),
+        op(0x0E, AslAddr, Absolute, 6),
+        op(0x1E, AslAddr, AbsoluteX, 7),
+
+        op(0x07, Slo, ZeroPage, 5),
+        op(0x17, Slo, ZeroPageX, 6),
+        op(0x0F, Slo, Absolute, 6),
+        op(0x1F, Slo, AbsoluteX, 7),
+        op(0x1B, Slo, AbsoluteY, 7),
+        op(0x03, Slo, IndexedIndirect, 8),
+        op(0x13, Slo, IndirectIndexed, 8),
+
+        op(0x4A, Lsr, Accumulator, 2),
+        op(0x46, LsrAddr, ZeroPage, 5),
+        op(0x56, LsrAddr, ZeroPageX, 6),
+        op(0x4E, LsrAddr, Absolute, 6),
+        op(0x5E, LsrAddr, AbsoluteX, 7),
+
+        op(0x47, Sre, ZeroPage, 5),
+        op(0x57, Sre, ZeroPageX, 6),
+        op(0x4F, Sre, Absolute, 6),
+        op(0x5F, Sre, AbsoluteX, 7),
+        op(0x5B, Sre, AbsoluteY, 7),
+        op(0x43, Sre, IndexedIndirect, 8),
+        op(0x53, Sre, IndirectIndexed, 8),
+
+        op(0x2A, Rol, Accumulator, 2),
+        op(0x26, RolAddr, ZeroPage, 5),
+        op(0x36, RolAddr, ZeroPageX, 6),
+        op(0x2E, RolAddr, Absolute, 6),
+        op(0x3E, RolAddr, AbsoluteX, 7),
+
+        op(0x27, Rla, ZeroPage, 5),
+        op(0x37, Rla, ZeroPageX, 6),
+        op(0x2F, Rla, Absolute, 6),
+        op(0x3F, Rla, AbsoluteX, 7),
+        op(0x3B, Rla, AbsoluteY, 7),
+        op(0x23, Rla, IndexedIndirect, 8),
+        op(0x33, Rla, IndirectIndexed, 8),
+
+        op(0x6A, Ror, Accumulator, 2),
+        op(0x66, RorAddr, ZeroPage, 5),
+        op(0x76, RorAddr, ZeroPageX, 6),
+        op(0x6E, RorAddr, Absolute, 6),
+        op(0x7E, RorAddr, AbsoluteX, 7),
+
+        op(0x67, Rra, ZeroPage, 5),
+        op(0x77, Rra, ZeroPageX, 6),
+        op(0x6F, Rra, Absolute, 6),
+        op(0x7F, Rra, AbsoluteX, 7),
+        op(0x7B, Rra, AbsoluteY, 7),
+        op(0x63, Rra, IndexedIndirect, 8),
+        op(0x73, Rra, IndirectIndexed, 8),
+
+        op(0x18, Clc, Implicit, 2),
+        op(0x38, Sec, Implicit, 2),
+
+        op(0xD8, Cld, Implicit, 2),
+        op(0xF8, Sed, Implicit, 2),
+
+        op(0x58, Cli, Implicit, 2),
+        op(0x78, Sei, Implicit, 2),
+
+        op(0xB8, Clv, Implicit, 2),
+
+        op(0xC9, Cmp, Immediate, 2),
+        op(0xC5, Cmp, ZeroPage, 3),
+        op(0xD5, Cmp, ZeroPageX, 4),
+        op(0xCD, Cmp, Absolute, 4),
+        op(0xDD, Cmp, AbsoluteX, 4).page_cross(),
+        op(0xD9, Cmp, AbsoluteY, 4).page_cross(),
+        op(0xC1, Cmp, IndexedIndirect, 6),
+        op(0xD1, Cmp, IndirectIndexed, 5).page_cross(),
+
+        op(0xE0, Cpx, Immediate, 2),
+        op(0xE4, Cpx, ZeroPage, 3),
+        op(0xEC, Cpx, Absolute, 4),
+
+        op(0xC0, Cpy, Immediate, 2),
+        op(0xC4, Cpy, ZeroPage, 3),
+        op(0xCC, Cpy, Absolute, 4),
+
+        op(0x69, Adc, Immediate, 2),
+        op(0x65, Adc, ZeroPage, 3),
+        op(0x75, Adc, ZeroPageX, 4),
+        op(0x6D, Adc, Absolute, 4),
+        op(0x7D, Adc, AbsoluteX, 4).page_cross(),
+        op(0x79, Adc, AbsoluteY, 4).page_cross(),
+        op(0x61, Adc, IndexedIndirect, 6),
+        op(0x71, Adc, IndirectIndexed, 5).page_cross(),
+
+        op(0xE9, Sbc, Immediate, 2),
+        op(0xEB, Sbc, Immediate, 2),
+        op(0xE5, Sbc, ZeroPage, 3),
+        op(0xF5, Sbc, ZeroPageX, 4),
+        op(0xED, Sbc, Absolute, 4),
+        op(0xFD, Sbc, AbsoluteX, 4).page_cross(),
+        op(0xF9, Sbc, AbsoluteY, 4).page_cross(),
+        op(0xE1, Sbc, IndexedIndirect, 6),
+        op(0xF1, Sbc, IndirectIndexed, 5).page_cross(),
+
+        op(0x00, Brk, Implicit, 7),
+
+        op(0x40, Rti, Implicit, 6),
+
+        op(0xE6, Inc, ZeroPage, 5),
+        op(0xF6, Inc, ZeroPageX, 6),
+        op(0xEE, Inc, Absolute, 6),
+        op(0xFE, Inc, AbsoluteX, 7),
+
+        op(0xE7, Isc, ZeroPage, 5),
+        op(0xF7, Isc, ZeroPageX, 6),
+        op(0xEF, Isc, Absolute, 6),
+        op(0xFF, Isc, AbsoluteX, 7),
+        op(0xFB, Isc, AbsoluteY, 7),
+        op(0xE3, Isc, IndexedIndirect, 8),
+        op(0xF3, Isc, IndirectIndexed, 8),
+
+        op(0xC6, Dec, ZeroPage, 5),
+        op(0xD6, Dec, ZeroPageX, 6),
+        op(0xCE, Dec, Absolute, 6),
+        op(0xDE, Dec, AbsoluteX, 7),
+
+        op(0xC7, Dcp, ZeroPage, 5),
+        op(0xD7, Dcp, ZeroPageX, 6),
+        op(0xCF, Dcp, Absolute, 6),
+        op(0xDF, Dcp, AbsoluteX, 7),
+        op(0xDB, Dcp, AbsoluteY, 7),
+        op(0xC3, Dcp, IndexedIndirect, 8),
+        op(0xD3, Dcp, IndirectIndexed, 8),
+    ]
+};
+
+// OPCODE_LIST indexed by opcode
+const OPCODES: [Option<Opcode>; 256] = {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < OPCODE_LIST.len() {
+        table[OPCODE_LIST[i].opcode as usize] = Some(OPCODE_LIST[i]);
+        i += 1;
+    }
+    table
+};
+
+// The instruction and addressing mode of an opcode, None for the opcodes
+// the CPU doesn't implement.
+fn decode(opcode: u8) -> Option<(InstructionKind, AddressingMode)> {
+    OPCODES[opcode as usize].map(|op| (op.kind, op.addr_mode))
+}
 
-        cpu.zero_flag = value == 0;
-        cpu.negative_flag = value & NEGATIVE_MASK != 0;
+// Adds an index register to a base address, telling whether the result is
+// on another page
+fn indexed(base: u16, index: u8) -> (u16, bool) {
+    let addr = base.wrapping_add(index as u16);
+    (addr, addr & 0xff00 != base & 0xff00)
+}
 
-        cpu.pc = cpu.pc.wrapping_add(1);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            _ => unimplemented!(),
-        }
-    }
-);
+// Disassembles the instruction at `pc` without side effects on the bus,
+// returning it with its size in bytes, e.g. `("LDA ($44),Y", 2)`.
+pub fn disassemble(mem: &Asc, pc: u16) -> (String, u16) {
+    let opcode = mem.peek(pc).unwrap_or(0);
+    let Some((kind, addr_mode)) = decode(opcode) else {
+        return (format!(".byte ${opcode:02X}"), 1);
+    };
 
-impl_instr!(
-    Dcp,
-    |cpu: &mut Cpu, addr: u16, mem: &mut Asc| {
-        Dec::instr(cpu, addr, mem);
-        cpu.pc = cpu.pc.wrapping_sub(1);
-        Cmp::instr(cpu, addr, mem);
-    },
-    |addr_mode: AddressingMode| {
-        use AddressingMode::*;
-        match addr_mode {
-            ZeroPage => 5,
-            ZeroPageX => 6,
-            Absolute => 6,
-            AbsoluteX => 7,
-            AbsoluteY => 7,
-            IndexedIndirect => 8,
-            IndirectIndexed => 8,
-            _ => unimplemented!(),
-        }
+    let operand: Vec<u8> = (1..addr_mode.len())
+        .map(|i| mem.peek(pc.wrapping_add(i)).unwrap_or(0))
+        .collect();
+    let text = instruction_text(kind.mnemonic(), addr_mode, &operand, pc);
+    (text, addr_mode.len())
+}
+
+fn instruction_text(mnemonic: &str, addr_mode: AddressingMode, operand: &[u8], pc: u16) -> String {
+    match addr_mode.format_operand(operand, pc) {
+        operand if operand.is_empty() => mnemonic.to_string(),
+        operand => format!("{mnemonic} {operand}"),
     }
-);
+}
+
+// Whether the CPU can run the opcode instead of panicking on it
+pub fn is_known_opcode(opcode: u8) -> bool {
+    decode(opcode).is_some()
+}
 
 impl fmt::Display for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -1524,10 +1239,28 @@ impl Cpu {
         }
     }
 
+    // Makes ADC and SBC honor the D flag, for machines with a regular 6502
+    pub fn set_decimal_mode(&mut self, enabled: bool) {
+        self.decimal_mode = enabled;
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            sp: self.sp,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            status: self.status_to_word(),
+        }
+    }
+
     pub fn reset(&mut self, ram: &mut Asc) {
         let mut reset_addr = ram.read(0xfffc) as u16;
         reset_addr |= (ram.read(0xfffd) as u16) << 8;
         self.pc = reset_addr;
+        // IRQs stay masked until the program is ready for them
+        self.interrupt_flag = true;
     }
 
     pub fn nmi(&mut self, ram: &mut Asc) {
@@ -1540,305 +1273,89 @@ impl Cpu {
         self.pc = nmi_addr;
     }
 
-    pub fn read_instruction(&mut self, ram: &mut Asc) {
-        let opcode = ram.read(self.pc.into());
-        self.run_instruction(opcode, ram);
-    }
-
-    fn run_instruction(&mut self, opcode: u8, mem: &mut Asc) -> Instruction {
-        let instr = match opcode {
-            0xEA => Nop::run_with(AddressingMode::Implicit, self, mem),
-            0x1A => Nop::run_with(AddressingMode::Implicit, self, mem),
-            0x3A => Nop::run_with(AddressingMode::Implicit, self, mem),
-            0x5A => Nop::run_with(AddressingMode::Implicit, self, mem),
-            0x7A => Nop::run_with(AddressingMode::Implicit, self, mem),
-            0xDA => Nop::run_with(AddressingMode::Implicit, self, mem),
-            0xFA => Nop::run_with(AddressingMode::Implicit, self, mem),
-            0x80 => Nop::run_with(AddressingMode::Immediate, self, mem),
-            0x82 => Nop::run_with(AddressingMode::Immediate, self, mem),
-            0x89 => Nop::run_with(AddressingMode::Immediate, self, mem),
-            0xC2 => Nop::run_with(AddressingMode::Immediate, self, mem),
-            0xE2 => Nop::run_with(AddressingMode::Immediate, self, mem),
-            0x04 => Nop::run_with(AddressingMode::ZeroPage, self, mem),
-            0x44 => Nop::run_with(AddressingMode::ZeroPage, self, mem),
-            0x64 => Nop::run_with(AddressingMode::ZeroPage, self, mem),
-            0x14 => Nop::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x34 => Nop::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x54 => Nop::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x74 => Nop::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xD4 => Nop::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xF4 => Nop::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x0C => Nop::run_with(AddressingMode::Absolute, self, mem),
-            0x1C => Nop::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x3C => Nop::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x5C => Nop::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x7C => Nop::run_with(AddressingMode::AbsoluteX, self, mem),
-            0xDC => Nop::run_with(AddressingMode::AbsoluteX, self, mem),
-            0xFC => Nop::run_with(AddressingMode::AbsoluteX, self, mem),
-
-            0xA9 => Lda::run_with(AddressingMode::Immediate, self, mem),
-            0xA5 => Lda::run_with(AddressingMode::ZeroPage, self, mem),
-            0xB5 => Lda::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xAD => Lda::run_with(AddressingMode::Absolute, self, mem),
-            0xBD => Lda::run_with(AddressingMode::AbsoluteX, self, mem),
-            0xB9 => Lda::run_with(AddressingMode::AbsoluteY, self, mem),
-            0xA1 => Lda::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0xB1 => Lda::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0xA2 => Ldx::run_with(AddressingMode::Immediate, self, mem),
-            0xA6 => Ldx::run_with(AddressingMode::ZeroPage, self, mem),
-            0xB6 => Ldx::run_with(AddressingMode::ZeroPageY, self, mem),
-            0xAE => Ldx::run_with(AddressingMode::Absolute, self, mem),
-            0xBE => Ldx::run_with(AddressingMode::AbsoluteY, self, mem),
-
-            0xA0 => Ldy::run_with(AddressingMode::Immediate, self, mem),
-            0xA4 => Ldy::run_with(AddressingMode::ZeroPage, self, mem),
-            0xB4 => Ldy::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xAC => Ldy::run_with(AddressingMode::Absolute, self, mem),
-            0xBC => Ldy::run_with(AddressingMode::AbsoluteX, self, mem),
-
-            0xA7 => Lax::run_with(AddressingMode::ZeroPage, self, mem),
-            0xB7 => Lax::run_with(AddressingMode::ZeroPageY, self, mem),
-            0xAF => Lax::run_with(AddressingMode::Absolute, self, mem),
-            0xBF => Lax::run_with(AddressingMode::AbsoluteY, self, mem),
-            0xA3 => Lax::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0xB3 => Lax::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x85 => Sta::run_with(AddressingMode::ZeroPage, self, mem),
-            0x95 => Sta::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x8D => Sta::run_with(AddressingMode::Absolute, self, mem),
-            0x9D => Sta::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x99 => Sta::run_with(AddressingMode::AbsoluteY, self, mem),
-            0x81 => Sta::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0x91 => Sta::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x86 => Stx::run_with(AddressingMode::ZeroPage, self, mem),
-            0x96 => Stx::run_with(AddressingMode::ZeroPageY, self, mem),
-            0x8E => Stx::run_with(AddressingMode::Absolute, self, mem),
-
-            0x84 => Sty::run_with(AddressingMode::ZeroPage, self, mem),
-            0x94 => Sty::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x8C => Sty::run_with(AddressingMode::Absolute, self, mem),
-
-            0x87 => Sax::run_with(AddressingMode::ZeroPage, self, mem),
-            0x97 => Sax::run_with(AddressingMode::ZeroPageY, self, mem),
-            0x8F => Sax::run_with(AddressingMode::Absolute, self, mem),
-            0x83 => Sax::run_with(AddressingMode::IndirectIndexed, self, mem),
-
-            0xAA => Tax::run_with(AddressingMode::Implicit, self, mem),
-
-            0xA8 => Tay::run_with(AddressingMode::Implicit, self, mem),
-
-            0x8A => Txa::run_with(AddressingMode::Implicit, self, mem),
-
-            0x98 => Tya::run_with(AddressingMode::Implicit, self, mem),
-
-            0xBA => Tsx::run_with(AddressingMode::Implicit, self, mem),
-
-            0x9A => Txs::run_with(AddressingMode::Implicit, self, mem),
-
-            0x48 => Pha::run_with(AddressingMode::Implicit, self, mem),
-
-            0x08 => Php::run_with(AddressingMode::Implicit, self, mem),
-
-            0x68 => Pla::run_with(AddressingMode::Implicit, self, mem),
-
-            0x28 => Plp::run_with(AddressingMode::Implicit, self, mem),
-
-            0x29 => And::run_with(AddressingMode::Immediate, self, mem),
-            0x25 => And::run_with(AddressingMode::ZeroPage, self, mem),
-            0x35 => And::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x2D => And::run_with(AddressingMode::Absolute, self, mem),
-            0x3D => And::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x39 => And::run_with(AddressingMode::AbsoluteY, self, mem),
-            0x21 => And::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0x31 => And::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x49 => Eor::run_with(AddressingMode::Immediate, self, mem),
-            0x45 => Eor::run_with(AddressingMode::ZeroPage, self, mem),
-            0x55 => Eor::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x4D => Eor::run_with(AddressingMode::Absolute, self, mem),
-            0x5D => Eor::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x59 => Eor::run_with(AddressingMode::AbsoluteY, self, mem),
-            0x41 => Eor::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0x51 => Eor::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x09 => Ora::run_with(AddressingMode::Immediate, self, mem),
-            0x05 => Ora::run_with(AddressingMode::ZeroPage, self, mem),
-            0x15 => Ora::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x0D => Ora::run_with(AddressingMode::Absolute, self, mem),
-            0x1D => Ora::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x19 => Ora::run_with(AddressingMode::AbsoluteY, self, mem),
-            0x01 => Ora::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0x11 => Ora::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x24 => Bit::run_with(AddressingMode::ZeroPage, self, mem),
-            0x2C => Bit::run_with(AddressingMode::Absolute, self, mem),
-
-            0x4C => Jmp::run_with(AddressingMode::Absolute, self, mem),
-            0x6C => Jmp::run_with(AddressingMode::Indirect, self, mem),
-
-            0x20 => Jsr::run_with(AddressingMode::Absolute, self, mem),
-
-            0x60 => Rts::run_with(AddressingMode::Implicit, self, mem),
-
-            0xD0 => Bne::run_with(AddressingMode::Relative, self, mem),
-            0xF0 => Beq::run_with(AddressingMode::Relative, self, mem),
-            0x10 => Bpl::run_with(AddressingMode::Relative, self, mem),
-            0x90 => Bcc::run_with(AddressingMode::Relative, self, mem),
-            0xB0 => Bcs::run_with(AddressingMode::Relative, self, mem),
-            0x30 => Bmi::run_with(AddressingMode::Relative, self, mem),
-            0x50 => Bvc::run_with(AddressingMode::Relative, self, mem),
-            0x70 => Bvs::run_with(AddressingMode::Relative, self, mem),
-
-            0xCA => Dex::run_with(AddressingMode::Implicit, self, mem),
-            0x88 => Dey::run_with(AddressingMode::Implicit, self, mem),
-
-            0xE8 => Incx::run_with(AddressingMode::Implicit, self, mem),
-            0xC8 => Incy::run_with(AddressingMode::Implicit, self, mem),
-
-            0x0A => Asl::run_with(AddressingMode::Accumulator, self, mem),
-            0x06 => AslAddr::run_with(AddressingMode::ZeroPage, self, mem),
-            0x16 => AslAddr::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x0E => AslAddr::run_with(AddressingMode::Absolute, self, mem),
-            0x1E => AslAddr::run_with(AddressingMode::AbsoluteX, self, mem),
-
-            0x07 => Slo::run_with(AddressingMode::ZeroPage, self, mem),
-            0x17 => Slo::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x0F => Slo::run_with(AddressingMode::Absolute, self, mem),
-            0x1F => Slo::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x1B => Slo::run_with(AddressingMode::AbsoluteY, self, mem),
-            0x03 => Slo::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0x13 => Slo::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x4A => Lsr::run_with(AddressingMode::Accumulator, self, mem),
-            0x46 => LsrAddr::run_with(AddressingMode::ZeroPage, self, mem),
-            0x56 => LsrAddr::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x4E => LsrAddr::run_with(AddressingMode::Absolute, self, mem),
-            0x5E => LsrAddr::run_with(AddressingMode::AbsoluteX, self, mem),
-
-            0x47 => Sre::run_with(AddressingMode::ZeroPage, self, mem),
-            0x57 => Sre::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x4F => Sre::run_with(AddressingMode::Absolute, self, mem),
-            0x5F => Sre::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x5B => Sre::run_with(AddressingMode::AbsoluteY, self, mem),
-            0x43 => Sre::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0x53 => Sre::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x2A => Rol::run_with(AddressingMode::Accumulator, self, mem),
-            0x26 => RolAddr::run_with(AddressingMode::ZeroPage, self, mem),
-            0x36 => RolAddr::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x2E => RolAddr::run_with(AddressingMode::Absolute, self, mem),
-            0x3E => RolAddr::run_with(AddressingMode::AbsoluteX, self, mem),
-
-            0x27 => Rla::run_with(AddressingMode::ZeroPage, self, mem),
-            0x37 => Rla::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x2F => Rla::run_with(AddressingMode::Absolute, self, mem),
-            0x3F => Rla::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x3B => Rla::run_with(AddressingMode::AbsoluteY, self, mem),
-            0x23 => Rla::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0x33 => Rla::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x6A => Ror::run_with(AddressingMode::Accumulator, self, mem),
-            0x66 => RorAddr::run_with(AddressingMode::ZeroPage, self, mem),
-            0x76 => RorAddr::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x6E => RorAddr::run_with(AddressingMode::Absolute, self, mem),
-            0x7E => RorAddr::run_with(AddressingMode::AbsoluteX, self, mem),
-
-            0x67 => Rra::run_with(AddressingMode::ZeroPage, self, mem),
-            0x77 => Rra::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x6F => Rra::run_with(AddressingMode::Absolute, self, mem),
-            0x7F => Rra::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x7B => Rra::run_with(AddressingMode::AbsoluteY, self, mem),
-            0x63 => Rra::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0x73 => Rra::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x18 => Clc::run_with(AddressingMode::Implicit, self, mem),
-            0x38 => Sec::run_with(AddressingMode::Implicit, self, mem),
-
-            0xD8 => Cld::run_with(AddressingMode::Implicit, self, mem),
-            0xF8 => Sed::run_with(AddressingMode::Implicit, self, mem),
-
-            0x58 => Cli::run_with(AddressingMode::Implicit, self, mem),
-            0x78 => Sei::run_with(AddressingMode::Implicit, self, mem),
-
-            0xB8 => Clv::run_with(AddressingMode::Implicit, self, mem),
-
-            0xC9 => Cmp::run_with(AddressingMode::Immediate, self, mem),
-            0xC5 => Cmp::run_with(AddressingMode::ZeroPage, self, mem),
-            0xD5 => Cmp::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xCD => Cmp::run_with(AddressingMode::Absolute, self, mem),
-            0xDD => Cmp::run_with(AddressingMode::AbsoluteX, self, mem),
-            0xD9 => Cmp::run_with(AddressingMode::AbsoluteY, self, mem),
-            0xC1 => Cmp::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0xD1 => Cmp::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0xE0 => Cpx::run_with(AddressingMode::Immediate, self, mem),
-            0xE4 => Cpx::run_with(AddressingMode::ZeroPage, self, mem),
-            0xEC => Cpx::run_with(AddressingMode::Absolute, self, mem),
-
-            0xC0 => Cpy::run_with(AddressingMode::Immediate, self, mem),
-            0xC4 => Cpy::run_with(AddressingMode::ZeroPage, self, mem),
-            0xCC => Cpy::run_with(AddressingMode::Absolute, self, mem),
-
-            0x69 => Adc::run_with(AddressingMode::Immediate, self, mem),
-            0x65 => Adc::run_with(AddressingMode::ZeroPage, self, mem),
-            0x75 => Adc::run_with(AddressingMode::ZeroPageX, self, mem),
-            0x6D => Adc::run_with(AddressingMode::Absolute, self, mem),
-            0x7D => Adc::run_with(AddressingMode::AbsoluteX, self, mem),
-            0x79 => Adc::run_with(AddressingMode::AbsoluteY, self, mem),
-            0x61 => Adc::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0x71 => Adc::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0xE9 => Sbc::run_with(AddressingMode::Immediate, self, mem),
-            0xEB => Sbc::run_with(AddressingMode::Immediate, self, mem),
-            0xE5 => Sbc::run_with(AddressingMode::ZeroPage, self, mem),
-            0xF5 => Sbc::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xED => Sbc::run_with(AddressingMode::Absolute, self, mem),
-            0xFD => Sbc::run_with(AddressingMode::AbsoluteX, self, mem),
-            0xF9 => Sbc::run_with(AddressingMode::AbsoluteY, self, mem),
-            0xE1 => Sbc::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0xF1 => Sbc::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0x00 => Brk::run_with(AddressingMode::Implicit, self, mem),
-
-            0x40 => Rti::run_with(AddressingMode::Implicit, self, mem),
-
-            0xE6 => Inc::run_with(AddressingMode::ZeroPage, self, mem),
-            0xF6 => Inc::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xEE => Inc::run_with(AddressingMode::Absolute, self, mem),
-            0xFE => Inc::run_with(AddressingMode::AbsoluteX, self, mem),
-
-            0xE7 => Isc::run_with(AddressingMode::ZeroPage, self, mem),
-            0xF7 => Isc::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xEF => Isc::run_with(AddressingMode::Absolute, self, mem),
-            0xFF => Isc::run_with(AddressingMode::AbsoluteX, self, mem),
-            0xFB => Isc::run_with(AddressingMode::AbsoluteY, self, mem),
-            0xE3 => Isc::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0xF3 => Isc::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            0xC6 => Dec::run_with(AddressingMode::ZeroPage, self, mem),
-            0xD6 => Dec::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xCE => Dec::run_with(AddressingMode::Absolute, self, mem),
-            0xDE => Dec::run_with(AddressingMode::AbsoluteX, self, mem),
-
-            0xC7 => Dcp::run_with(AddressingMode::ZeroPage, self, mem),
-            0xD7 => Dcp::run_with(AddressingMode::ZeroPageX, self, mem),
-            0xCF => Dcp::run_with(AddressingMode::Absolute, self, mem),
-            0xDF => Dcp::run_with(AddressingMode::AbsoluteX, self, mem),
-            0xDB => Dcp::run_with(AddressingMode::AbsoluteY, self, mem),
-            0xC3 => Dcp::run_with(AddressingMode::IndirectIndexed, self, mem),
-            0xD3 => Dcp::run_with(AddressingMode::IndexedIndirect, self, mem),
-
-            _ => unimplemented!(
+    // Services an IRQ unless the I flag masks it, returns whether it did.
+    // Unlike BRK the pushed status has the B flag clear, which is how a
+    // handler tells them apart.
+    pub fn irq(&mut self, ram: &mut Asc) -> bool {
+        if self.interrupt_flag {
+            return false;
+        }
+
+        let mut irq_addr = ram.read(0xfffe) as u16;
+        irq_addr |= (ram.read(0xffff) as u16) << 8;
+
+        self.push_long(self.pc, ram);
+        self.push(self.status_to_word() & !(1 << 4) | (1 << 5), ram);
+
+        self.pc = irq_addr;
+        self.interrupt_flag = true;
+        self.cycles += 7;
+        true
+    }
+
+    // Runs the instruction at PC. The operand bytes are peeked before it
+    // runs, so they are the ones it ran with even if it overwrites them.
+    pub fn step(&mut self, ram: &mut Asc) -> StepInfo {
+        let pc = self.pc;
+        let start = self.cycles;
+        let opcode = ram.read(pc);
+
+        // Taken before the instruction runs, it may write over them. Only
+        // the bytes the addressing mode uses, this runs on every instruction.
+        let mut operand = [0; 2];
+        if let Some(op) = OPCODES[opcode as usize] {
+            for i in 1..op.addr_mode.len() {
+                operand[i as usize - 1] = Asc::peek(ram, pc.wrapping_add(i)).unwrap_or(0);
+            }
+        }
+
+        let op = self.run_instruction(opcode, ram);
+
+        StepInfo {
+            pc,
+            opcode,
+            mnemonic: op.kind.mnemonic(),
+            addr_mode: op.addr_mode,
+            operand,
+            cycles: (self.cycles - start).0,
+            registers: self.registers(),
+        }
+    }
+
+    fn run_instruction(&mut self, opcode: u8, mem: &mut Asc) -> Opcode {
+        let Some(op) = OPCODES[opcode as usize] else {
+            unimplemented!(
                 "Reached an unknown instruction with opcode {:#X} at address {:#X}\n",
                 opcode,
                 self.pc
-            ),
+            )
         };
 
-        self.cycles += Wrapping(instr.cycles as usize);
-        return instr;
+        let (addr, page_crossed) = self.operand_addr(op.addr_mode, mem);
+        (op.run)(self, addr, mem);
+
+        self.cycles += (op.cycles + (op.page_cross && page_crossed) as u8) as usize;
+        op
+    }
+
+    // Where the operand of an instruction is, and whether indexing crossed
+    // a page to get there
+    fn operand_addr(&mut self, addr_mode: AddressingMode, mem: &mut Asc) -> (u16, bool) {
+        use AddressingMode::*;
+        match addr_mode {
+            Implicit | Accumulator => (0, false),
+            Immediate => (self.imm(mem), false),
+            ZeroPage => (self.zp(mem), false),
+            Relative => (self.rel(mem), false),
+            ZeroPageX => (self.zpx(mem), false),
+            ZeroPageY => (self.zpy(mem), false),
+            Absolute => (self.abs(mem), false),
+            AbsoluteX => self.abx(mem),
+            AbsoluteY => self.aby(mem),
+            Indirect => (self.ind(mem), false),
+            IndexedIndirect => (self.inx(mem), false),
+            IndirectIndexed => self.iny(mem),
+        }
     }
 
     fn imm(&mut self, _: &mut Asc) -> u16 {
@@ -1851,6 +1368,13 @@ impl Cpu {
         ram.read(self.pc) as u16
     }
 
+    // The branch target, relative to the instruction after the branch
+    fn rel(&mut self, ram: &mut Asc) -> u16 {
+        self.pc += 1;
+        let offset = ram.read(self.pc) as i8;
+        self.pc.wrapping_add(1).wrapping_add_signed(offset as i16)
+    }
+
     fn zpx(&mut self, ram: &mut Asc) -> u16 {
         self.pc += 1;
         (ram.read(self.pc) as u16).wrapping_add(self.x as u16) & 0xff
@@ -1868,34 +1392,28 @@ impl Cpu {
         (ram.read(self.pc) as u16) << 8 | addr as u16
     }
 
-    fn abx(&mut self, ram: &mut Asc) -> u16 {
-        self.pc += 1;
-        let mut addr = ram.read(self.pc) as u16;
-        self.pc += 1;
-        addr |= (ram.read(self.pc) as u16) << 8;
-        addr.wrapping_add(self.x as u16)
+    fn abx(&mut self, ram: &mut Asc) -> (u16, bool) {
+        let base = self.abs(ram);
+        indexed(base, self.x)
     }
 
-    fn aby(&mut self, ram: &mut Asc) -> u16 {
-        self.pc += 1;
-        let mut addr = ram.read(self.pc) as u16;
-        self.pc += 1;
-        addr |= (ram.read(self.pc) as u16) << 8;
-        addr.wrapping_add(self.y as u16)
+    fn aby(&mut self, ram: &mut Asc) -> (u16, bool) {
+        let base = self.abs(ram);
+        indexed(base, self.y)
     }
 
     fn inx(&mut self, ram: &mut Asc) -> u16 {
         self.pc += 1;
         let mut addr: u16 = ram.read(self.pc) as u16;
         addr = (addr.wrapping_add(self.x as u16) & 0xff) as u16;
-        (ram.read(addr + 1) as u16) << 8 | ram.read(addr.into()) as u16
+        (ram.read((addr + 1) & 0xff) as u16) << 8 | ram.read(addr.into()) as u16
     }
 
-    fn iny(&mut self, ram: &mut Asc) -> u16 {
+    fn iny(&mut self, ram: &mut Asc) -> (u16, bool) {
         self.pc += 1;
         let addr: u16 = ram.read(self.pc) as u16;
-        let addr = (ram.read(addr.wrapping_add(1)) as u16) << 8 | ram.read(addr) as u16;
-        addr.wrapping_add(self.y as u16)
+        let addr = (ram.read((addr + 1) & 0xff) as u16) << 8 | ram.read(addr) as u16;
+        indexed(addr, self.y)
     }
 
     fn ind(&mut self, ram: &mut Asc) -> u16 {
@@ -1903,7 +1421,23 @@ impl Cpu {
         let addr = ram.read(self.pc);
         self.pc += 1;
         let addr = (ram.read(self.pc) as u16) << 8 | addr as u16;
-        (ram.read(addr + 1) as u16) << 8 | ram.read(addr.into()) as u16
+        // The high byte is fetched without carrying into the next page
+        let high = (addr & 0xff00) | (addr.wrapping_add(1) & 0xff);
+        (ram.read(high) as u16) << 8 | ram.read(addr) as u16
+    }
+
+    // A taken branch costs one more cycle than the base 2, and another one
+    // when the target is on a different page than the next instruction.
+    fn branch(&mut self, taken: bool, target: u16) {
+        let next = self.pc.wrapping_add(1);
+        if !taken {
+            self.pc = next;
+            return;
+        }
+
+        let page_crossed = next & 0xff00 != target & 0xff00;
+        self.cycles += 1 + page_crossed as usize;
+        self.pc = target;
     }
 
     fn push(&mut self, value: u8, ram: &mut Asc) {
@@ -2010,4 +1544,1156 @@ impl Cpu {
         self.zero_flag = self.a == 0;
         self.negative_flag = self.a & NEGATIVE_MASK != 0;
     }
+
+    // NMOS 6502 BCD addition. N and V come from the result before the high
+    // digit is adjusted, and Z from the binary sum.
+    // Based on http://www.6502.org/tutorials/decimal_mode.html
+    fn add_decimal(&mut self, value: u8) {
+        let (a, value, carry) = (self.a as u16, value as u16, self.carry_flag as u16);
+
+        let mut low = (a & 0x0f) + (value & 0x0f) + carry;
+        if low >= 0x0a {
+            low = ((low + 0x06) & 0x0f) + 0x10;
+        }
+        let mut result = (a & 0xf0) + (value & 0xf0) + low;
+
+        self.zero_flag = (a + value + carry) as u8 == 0;
+        self.negative_flag = result & 0x80 != 0;
+        self.overflow_flag = !(a ^ value) & (a ^ result) & 0x80 != 0;
+
+        if result >= 0xa0 {
+            result += 0x60;
+        }
+        self.carry_flag = result >= 0x100;
+        self.a = result as u8;
+    }
+
+    // NMOS 6502 BCD subtraction, the flags are the ones of the binary
+    // subtraction.
+    fn subtract_decimal(&mut self, value: u8) {
+        let (a, value, borrow) = (self.a as i16, value as i16, !self.carry_flag as i16);
+
+        let mut low = (a & 0x0f) - (value & 0x0f) - borrow;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0f) - 0x10;
+        }
+        let mut result = (a & 0xf0) - (value & 0xf0) + low;
+        if result < 0 {
+            result -= 0x60;
+        }
+
+        self.add_with_carry(!value as u8);
+        self.a = result as u8;
+    }
+}
+
+#[cfg(all(test, feature = "harte"))]
+mod harte;
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    const PROGRAM_START: u16 = 0x8000;
+
+    const CARRY: u8 = 1 << 0;
+    const ZERO: u8 = 1 << 1;
+    const INTERRUPT: u8 = 1 << 2;
+    const DECIMAL: u8 = 1 << 3;
+    const BREAK: u8 = 1 << 4;
+    const RESERVED: u8 = 1 << 5;
+    const OVERFLOW: u8 = 1 << 6;
+    const NEGATIVE: u8 = 1 << 7;
+
+    // 64KB of plain memory, so instructions can be tested without a cartridge
+    // or any of the NES devices.
+    pub(super) struct TestBus {
+        pub(super) memory: Vec<u8>,
+    }
+
+    impl MemoryMapped for TestBus {
+        fn write(&mut self, addr: u16, value: u8) {
+            self.memory[addr as usize] = value;
+        }
+
+        fn read(&mut self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+    }
+
+    fn setup(program: &[u8]) -> (Cpu, Asc) {
+        let mut bus = TestBus {
+            memory: vec![0; 0x10000],
+        };
+        let start = PROGRAM_START as usize;
+        bus.memory[start..start + program.len()].copy_from_slice(program);
+
+        let mut asc = Asc::new();
+        asc.register_device_range(0..=0xffff, Rc::new(RefCell::new(bus)));
+
+        let mut cpu = Cpu::new();
+        cpu.pc = PROGRAM_START;
+        (cpu, asc)
+    }
+
+    // Runs one instruction and returns the cycles it took
+    fn step(cpu: &mut Cpu, asc: &mut Asc) -> usize {
+        cpu.step(asc).cycles
+    }
+
+    fn status(cpu: &Cpu) -> u8 {
+        cpu.registers().status
+    }
+
+    #[test]
+    fn cycles_and_length_of_every_opcode() {
+        #[rustfmt::skip]
+        let table: &[(u8, u16, usize)] = &[
+            // LDA, LDX, LDY, LAX
+            (0xA9, 2, 2), (0xA5, 2, 3), (0xB5, 2, 4), (0xAD, 3, 4),
+            (0xBD, 3, 4), (0xB9, 3, 4), (0xA1, 2, 6), (0xB1, 2, 5),
+            (0xA2, 2, 2), (0xA6, 2, 3), (0xB6, 2, 4), (0xAE, 3, 4), (0xBE, 3, 4),
+            (0xA0, 2, 2), (0xA4, 2, 3), (0xB4, 2, 4), (0xAC, 3, 4), (0xBC, 3, 4),
+            (0xA7, 2, 3), (0xB7, 2, 4), (0xAF, 3, 4), (0xBF, 3, 4),
+            (0xA3, 2, 6), (0xB3, 2, 5),
+            // STA, STX, STY, SAX
+            (0x85, 2, 3), (0x95, 2, 4), (0x8D, 3, 4), (0x9D, 3, 5),
+            (0x99, 3, 5), (0x81, 2, 6), (0x91, 2, 6),
+            (0x86, 2, 3), (0x96, 2, 4), (0x8E, 3, 4),
+            (0x84, 2, 3), (0x94, 2, 4), (0x8C, 3, 4),
+            (0x87, 2, 3), (0x97, 2, 4), (0x8F, 3, 4), (0x83, 2, 6),
+            // Transfers and stack
+            (0xAA, 1, 2), (0xA8, 1, 2), (0x8A, 1, 2), (0x98, 1, 2),
+            (0xBA, 1, 2), (0x9A, 1, 2),
+            (0x48, 1, 3), (0x08, 1, 3), (0x68, 1, 4), (0x28, 1, 4),
+            // AND, EOR, ORA
+            (0x29, 2, 2), (0x25, 2, 3), (0x35, 2, 4), (0x2D, 3, 4),
+            (0x3D, 3, 4), (0x39, 3, 4), (0x21, 2, 6), (0x31, 2, 5),
+            (0x49, 2, 2), (0x45, 2, 3), (0x55, 2, 4), (0x4D, 3, 4),
+            (0x5D, 3, 4), (0x59, 3, 4), (0x41, 2, 6), (0x51, 2, 5),
+            (0x09, 2, 2), (0x05, 2, 3), (0x15, 2, 4), (0x0D, 3, 4),
+            (0x1D, 3, 4), (0x19, 3, 4), (0x01, 2, 6), (0x11, 2, 5),
+            (0x24, 2, 3), (0x2C, 3, 4),
+            // ADC, SBC
+            (0x69, 2, 2), (0x65, 2, 3), (0x75, 2, 4), (0x6D, 3, 4),
+            (0x7D, 3, 4), (0x79, 3, 4), (0x61, 2, 6), (0x71, 2, 5),
+            (0xE9, 2, 2), (0xEB, 2, 2), (0xE5, 2, 3), (0xF5, 2, 4), (0xED, 3, 4),
+            (0xFD, 3, 4), (0xF9, 3, 4), (0xE1, 2, 6), (0xF1, 2, 5),
+            // CMP, CPX, CPY
+            (0xC9, 2, 2), (0xC5, 2, 3), (0xD5, 2, 4), (0xCD, 3, 4),
+            (0xDD, 3, 4), (0xD9, 3, 4), (0xC1, 2, 6), (0xD1, 2, 5),
+            (0xE0, 2, 2), (0xE4, 2, 3), (0xEC, 3, 4),
+            (0xC0, 2, 2), (0xC4, 2, 3), (0xCC, 3, 4),
+            // Increments and decrements
+            (0xE6, 2, 5), (0xF6, 2, 6), (0xEE, 3, 6), (0xFE, 3, 7),
+            (0xC6, 2, 5), (0xD6, 2, 6), (0xCE, 3, 6), (0xDE, 3, 7),
+            (0xE8, 1, 2), (0xC8, 1, 2), (0xCA, 1, 2), (0x88, 1, 2),
+            // Shifts and rotates
+            (0x0A, 1, 2), (0x06, 2, 5), (0x16, 2, 6), (0x0E, 3, 6), (0x1E, 3, 7),
+            (0x4A, 1, 2), (0x46, 2, 5), (0x56, 2, 6), (0x4E, 3, 6), (0x5E, 3, 7),
+            (0x2A, 1, 2), (0x26, 2, 5), (0x36, 2, 6), (0x2E, 3, 6), (0x3E, 3, 7),
+            (0x6A, 1, 2), (0x66, 2, 5), (0x76, 2, 6), (0x6E, 3, 6), (0x7E, 3, 7),
+            // Read-modify-write combinations
+            (0x07, 2, 5), (0x17, 2, 6), (0x0F, 3, 6), (0x1F, 3, 7),
+            (0x1B, 3, 7), (0x03, 2, 8), (0x13, 2, 8),
+            (0x47, 2, 5), (0x57, 2, 6), (0x4F, 3, 6), (0x5F, 3, 7),
+            (0x5B, 3, 7), (0x43, 2, 8), (0x53, 2, 8),
+            (0x27, 2, 5), (0x37, 2, 6), (0x2F, 3, 6), (0x3F, 3, 7),
+            (0x3B, 3, 7), (0x23, 2, 8), (0x33, 2, 8),
+            (0x67, 2, 5), (0x77, 2, 6), (0x6F, 3, 6), (0x7F, 3, 7),
+            (0x7B, 3, 7), (0x63, 2, 8), (0x73, 2, 8),
+            (0xE7, 2, 5), (0xF7, 2, 6), (0xEF, 3, 6), (0xFF, 3, 7),
+            (0xFB, 3, 7), (0xE3, 2, 8), (0xF3, 2, 8),
+            (0xC7, 2, 5), (0xD7, 2, 6), (0xCF, 3, 6), (0xDF, 3, 7),
+            (0xDB, 3, 7), (0xC3, 2, 8), (0xD3, 2, 8),
+            // Flags
+            (0x18, 1, 2), (0x38, 1, 2), (0xD8, 1, 2), (0xF8, 1, 2),
+            (0x58, 1, 2), (0x78, 1, 2), (0xB8, 1, 2),
+            // NOPs
+            (0xEA, 1, 2), (0x1A, 1, 2), (0x3A, 1, 2), (0x5A, 1, 2),
+            (0x7A, 1, 2), (0xDA, 1, 2), (0xFA, 1, 2),
+            (0x80, 2, 2), (0x82, 2, 2), (0x89, 2, 2), (0xC2, 2, 2), (0xE2, 2, 2),
+            (0x04, 2, 3), (0x44, 2, 3), (0x64, 2, 3),
+            (0x14, 2, 4), (0x34, 2, 4), (0x54, 2, 4), (0x74, 2, 4),
+            (0xD4, 2, 4), (0xF4, 2, 4),
+            (0x0C, 3, 4), (0x1C, 3, 4), (0x3C, 3, 4), (0x5C, 3, 4),
+            (0x7C, 3, 4), (0xDC, 3, 4), (0xFC, 3, 4),
+        ];
+
+        for &(opcode, len, cycles) in table {
+            let (mut cpu, mut asc) = setup(&[opcode, 0x00, 0x02]);
+            let info = cpu.step(&mut asc);
+
+            assert_eq!(info.size(), len, "size of {opcode:#04x}");
+            assert_eq!(cpu.pc, PROGRAM_START + len, "pc after {opcode:#04x}");
+            assert_eq!(info.cycles, cycles, "cycles of {opcode:#04x}");
+        }
+    }
+
+    #[test]
+    fn indexed_reads_across_pages_take_an_extra_cycle() {
+        // LDA $02FF,X; LDA ($10),Y; STA $02FF,X
+        let (mut cpu, mut asc) = setup(&[0xBD, 0xFF, 0x02, 0xB1, 0x10, 0x9D, 0xFF, 0x02]);
+        asc.write(0x0010, 0xFF);
+        asc.write(0x0011, 0x02);
+        cpu.x = 1;
+        cpu.y = 1;
+
+        assert_eq!(step(&mut cpu, &mut asc), 5);
+        assert_eq!(step(&mut cpu, &mut asc), 6);
+        // Writes always take the extra cycle, it is part of their base count
+        assert_eq!(step(&mut cpu, &mut asc), 5);
+    }
+
+    #[test]
+    fn lda_immediate_sets_zero_and_negative() {
+        let (mut cpu, mut asc) = setup(&[0xA9, 0x00, 0xA9, 0x80, 0xA9, 0x42]);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), ZERO);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x80);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), NEGATIVE);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), 0);
+    }
+
+    #[test]
+    fn lda_addressing_modes() {
+        // LDA $10 / LDA $10,X / LDA $1234 / LDA $1234,X / LDA $1234,Y
+        let (mut cpu, mut asc) = setup(&[
+            0xA5, 0x10, 0xB5, 0x10, 0xAD, 0x34, 0x12, 0xBD, 0x34, 0x12, 0xB9, 0x34, 0x12,
+        ]);
+        cpu.x = 0x02;
+        cpu.y = 0x03;
+        asc.write(0x0010, 0x11);
+        asc.write(0x0012, 0x22);
+        asc.write(0x1234, 0x33);
+        asc.write(0x1236, 0x44);
+        asc.write(0x1237, 0x55);
+
+        for expected in [0x11, 0x22, 0x33, 0x44, 0x55] {
+            step(&mut cpu, &mut asc);
+            assert_eq!(cpu.a, expected);
+        }
+    }
+
+    #[test]
+    fn zero_page_x_wraps_inside_zero_page() {
+        let (mut cpu, mut asc) = setup(&[0xB5, 0xF0]);
+        cpu.x = 0x20;
+        asc.write(0x0010, 0x99);
+        asc.write(0x0110, 0x11);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x99);
+    }
+
+    #[test]
+    fn ldx_zero_page_y_and_ldy_absolute_x() {
+        let (mut cpu, mut asc) = setup(&[0xB6, 0x10, 0xBC, 0x00, 0x03]);
+        cpu.x = 0x05;
+        cpu.y = 0x04;
+        asc.write(0x0014, 0x7f);
+        // X was just loaded with $7F
+        asc.write(0x037f, 0x82);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.x, 0x7f);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.y, 0x82);
+        assert_eq!(status(&cpu) & NEGATIVE, NEGATIVE);
+    }
+
+    #[test]
+    fn indexed_indirect_reads_pointer_from_zero_page() {
+        // LDA ($20,X)
+        let (mut cpu, mut asc) = setup(&[0xA1, 0x20]);
+        cpu.x = 0x04;
+        asc.write(0x0024, 0x00);
+        asc.write(0x0025, 0x03);
+        asc.write(0x0300, 0x5a);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x5a);
+    }
+
+    #[test]
+    fn indexed_indirect_pointer_wraps_in_zero_page() {
+        // LDA ($FF,X), the pointer high byte comes from $00
+        let (mut cpu, mut asc) = setup(&[0xA1, 0xFF]);
+        asc.write(0x00ff, 0x00);
+        asc.write(0x0000, 0x04);
+        asc.write(0x0100, 0x05);
+        asc.write(0x0400, 0xaa);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0xaa);
+    }
+
+    #[test]
+    fn indirect_indexed_adds_y_to_pointer() {
+        // LDA ($20),Y
+        let (mut cpu, mut asc) = setup(&[0xB1, 0x20]);
+        cpu.y = 0x10;
+        asc.write(0x0020, 0xf8);
+        asc.write(0x0021, 0x02);
+        asc.write(0x0308, 0x3c);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x3c);
+    }
+
+    #[test]
+    fn indirect_indexed_pointer_wraps_in_zero_page() {
+        // LDA ($FF),Y
+        let (mut cpu, mut asc) = setup(&[0xB1, 0xFF]);
+        cpu.y = 0x01;
+        asc.write(0x00ff, 0x00);
+        asc.write(0x0000, 0x04);
+        asc.write(0x0100, 0x05);
+        asc.write(0x0401, 0xbb);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0xbb);
+    }
+
+    #[test]
+    fn lax_loads_a_and_x() {
+        let (mut cpu, mut asc) = setup(&[0xA7, 0x10]);
+        asc.write(0x0010, 0x80);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!((cpu.a, cpu.x), (0x80, 0x80));
+        assert_eq!(status(&cpu) & NEGATIVE, NEGATIVE);
+    }
+
+    #[test]
+    fn stores() {
+        // STA $10 / STX $11 / STY $1234 / STA $1234,X / STA ($20),Y
+        let (mut cpu, mut asc) = setup(&[
+            0x85, 0x10, 0x86, 0x11, 0x8C, 0x34, 0x12, 0x9D, 0x34, 0x12, 0x91, 0x20,
+        ]);
+        cpu.a = 0xa1;
+        cpu.x = 0x02;
+        cpu.y = 0x03;
+        asc.write(0x0020, 0x00);
+        asc.write(0x0021, 0x05);
+
+        for _ in 0..5 {
+            step(&mut cpu, &mut asc);
+        }
+
+        assert_eq!(asc.read(0x0010), 0xa1);
+        assert_eq!(asc.read(0x0011), 0x02);
+        assert_eq!(asc.read(0x1234), 0x03);
+        assert_eq!(asc.read(0x1236), 0xa1);
+        assert_eq!(asc.read(0x0503), 0xa1);
+    }
+
+    #[test]
+    fn stores_leave_flags_alone() {
+        let (mut cpu, mut asc) = setup(&[0x85, 0x10]);
+        cpu.a = 0x00;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), 0);
+    }
+
+    #[test]
+    fn sax_stores_a_and_x() {
+        let (mut cpu, mut asc) = setup(&[0x87, 0x10]);
+        cpu.a = 0b1100_1010;
+        cpu.x = 0b1010_0110;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0010), 0b1000_0010);
+    }
+
+    #[test]
+    fn register_transfers() {
+        // TAX / TAY / TXA / TYA
+        let (mut cpu, mut asc) = setup(&[0xAA, 0xA8, 0x8A, 0x98]);
+        cpu.a = 0x80;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.x, 0x80);
+        assert_eq!(status(&cpu) & NEGATIVE, NEGATIVE);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.y, 0x80);
+
+        cpu.x = 0x00;
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), ZERO);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x80);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), NEGATIVE);
+    }
+
+    #[test]
+    fn stack_pointer_transfers() {
+        // TSX / TXS
+        let (mut cpu, mut asc) = setup(&[0xBA, 0x9A]);
+        cpu.sp = 0xf0;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.x, 0xf0);
+        assert_eq!(status(&cpu) & NEGATIVE, NEGATIVE);
+
+        // TXS doesn't touch the flags
+        cpu.x = 0x00;
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.sp, 0x00);
+        assert_eq!(status(&cpu) & ZERO, 0);
+    }
+
+    #[test]
+    fn pha_and_pla() {
+        // PHA / LDA #$00 / PLA
+        let (mut cpu, mut asc) = setup(&[0x48, 0xA9, 0x00, 0x68]);
+        cpu.a = 0x85;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.sp, 0xfe);
+        assert_eq!(asc.read(0x01ff), 0x85);
+
+        step(&mut cpu, &mut asc);
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x85);
+        assert_eq!(cpu.sp, 0xff);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), NEGATIVE);
+    }
+
+    #[test]
+    fn php_pushes_break_and_reserved_bits() {
+        let (mut cpu, mut asc) = setup(&[0x08]);
+        cpu.carry_flag = true;
+        cpu.negative_flag = true;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x01ff), CARRY | NEGATIVE | BREAK | RESERVED);
+    }
+
+    #[test]
+    fn plp_restores_flags() {
+        let (mut cpu, mut asc) = setup(&[0x28]);
+        cpu.sp = 0xfe;
+        asc.write(0x01ff, CARRY | DECIMAL | OVERFLOW);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu), CARRY | DECIMAL | OVERFLOW);
+        assert_eq!(cpu.sp, 0xff);
+    }
+
+    #[test]
+    fn stack_pointer_wraps() {
+        let (mut cpu, mut asc) = setup(&[0x48, 0x68]);
+        cpu.sp = 0x00;
+        cpu.a = 0x12;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0100), 0x12);
+        assert_eq!(cpu.sp, 0xff);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.sp, 0x00);
+    }
+
+    #[test]
+    fn logical_operations() {
+        // AND #$0F / ORA #$80 / EOR #$FF
+        let (mut cpu, mut asc) = setup(&[0x29, 0x0F, 0x09, 0x80, 0x49, 0xFF]);
+        cpu.a = 0x3c;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x0c);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), 0);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x8c);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), NEGATIVE);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x73);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), 0);
+    }
+
+    #[test]
+    fn and_sets_zero() {
+        let (mut cpu, mut asc) = setup(&[0x29, 0xF0]);
+        cpu.a = 0x0f;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(status(&cpu) & ZERO, ZERO);
+    }
+
+    #[test]
+    fn bit_copies_high_bits_and_tests_a() {
+        let (mut cpu, mut asc) = setup(&[0x24, 0x10, 0x2C, 0x00, 0x03]);
+        cpu.a = 0x01;
+        asc.write(0x0010, 0xc0);
+        asc.write(0x0300, 0x01);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x01);
+        assert_eq!(
+            status(&cpu) & (ZERO | OVERFLOW | NEGATIVE),
+            ZERO | OVERFLOW | NEGATIVE
+        );
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu) & (ZERO | OVERFLOW | NEGATIVE), 0);
+    }
+
+    #[test]
+    fn adc_adds_carry() {
+        let (mut cpu, mut asc) = setup(&[0x69, 0x10]);
+        cpu.a = 0x20;
+        cpu.carry_flag = true;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x31);
+        assert_eq!(status(&cpu) & (CARRY | ZERO | OVERFLOW | NEGATIVE), 0);
+    }
+
+    #[test]
+    fn adc_sets_carry_and_zero() {
+        let (mut cpu, mut asc) = setup(&[0x69, 0x01]);
+        cpu.a = 0xff;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(status(&cpu) & (CARRY | ZERO | OVERFLOW), CARRY | ZERO);
+    }
+
+    #[test]
+    fn adc_sets_overflow() {
+        let (mut cpu, mut asc) = setup(&[0x69, 0x50, 0x69, 0x90]);
+        cpu.a = 0x50;
+
+        // Positive + positive = negative
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0xa0);
+        assert_eq!(
+            status(&cpu) & (CARRY | OVERFLOW | NEGATIVE),
+            OVERFLOW | NEGATIVE
+        );
+
+        // Negative + negative = positive
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x30);
+        assert_eq!(
+            status(&cpu) & (CARRY | OVERFLOW | NEGATIVE),
+            CARRY | OVERFLOW
+        );
+    }
+
+    #[test]
+    fn sbc_borrows_when_carry_is_clear() {
+        let (mut cpu, mut asc) = setup(&[0xE9, 0x10, 0xE9, 0x10]);
+        cpu.a = 0x50;
+        cpu.carry_flag = true;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x40);
+        assert_eq!(status(&cpu) & CARRY, CARRY);
+
+        cpu.carry_flag = false;
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x2f);
+        assert_eq!(status(&cpu) & CARRY, CARRY);
+    }
+
+    #[test]
+    fn sbc_clears_carry_on_underflow_and_sets_overflow() {
+        let (mut cpu, mut asc) = setup(&[0xE9, 0x01, 0xEB, 0x01]);
+        cpu.a = 0x00;
+        cpu.carry_flag = true;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0xff);
+        assert_eq!(status(&cpu) & (CARRY | OVERFLOW | NEGATIVE), NEGATIVE);
+
+        // The unofficial $EB behaves like $E9
+        cpu.a = 0x80;
+        cpu.carry_flag = true;
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x7f);
+        assert_eq!(
+            status(&cpu) & (CARRY | OVERFLOW | NEGATIVE),
+            CARRY | OVERFLOW
+        );
+    }
+
+    #[test]
+    fn decimal_flag_is_ignored_by_default() {
+        let (mut cpu, mut asc) = setup(&[0x69, 0x01]);
+        cpu.decimal_flag = true;
+        cpu.a = 0x09;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x0a);
+    }
+
+    #[test]
+    fn adc_in_decimal_mode() {
+        let (mut cpu, mut asc) = setup(&[0x69, 0x34, 0x69, 0x46]);
+        cpu.set_decimal_mode(true);
+        cpu.decimal_flag = true;
+        cpu.a = 0x12;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x46);
+        assert_eq!(status(&cpu) & CARRY, 0);
+
+        // 46 + 46 + 0 = 92
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x92);
+        assert_eq!(status(&cpu) & CARRY, 0);
+    }
+
+    #[test]
+    fn adc_in_decimal_mode_carries() {
+        let (mut cpu, mut asc) = setup(&[0x69, 0x46]);
+        cpu.set_decimal_mode(true);
+        cpu.decimal_flag = true;
+        cpu.carry_flag = true;
+        cpu.a = 0x58;
+
+        // 58 + 46 + 1 = 105
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x05);
+        assert_eq!(status(&cpu) & CARRY, CARRY);
+    }
+
+    #[test]
+    fn sbc_in_decimal_mode() {
+        let (mut cpu, mut asc) = setup(&[0xE9, 0x12, 0xE9, 0x21]);
+        cpu.set_decimal_mode(true);
+        cpu.decimal_flag = true;
+        cpu.carry_flag = true;
+        cpu.a = 0x46;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x34);
+        assert_eq!(status(&cpu) & CARRY, CARRY);
+
+        // 34 - 21 - 0 = 13, then 13 - 21 borrows
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x13);
+        cpu.pc = PROGRAM_START + 2;
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x92);
+        assert_eq!(status(&cpu) & CARRY, 0);
+    }
+
+    #[test]
+    fn compares() {
+        // CMP #$40 / CPX #$40 / CPY #$40
+        let (mut cpu, mut asc) = setup(&[0xC9, 0x40, 0xE0, 0x40, 0xC0, 0x40]);
+        cpu.a = 0x40;
+        cpu.x = 0x50;
+        cpu.y = 0x30;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu) & (CARRY | ZERO | NEGATIVE), CARRY | ZERO);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu) & (CARRY | ZERO | NEGATIVE), CARRY);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu) & (CARRY | ZERO | NEGATIVE), NEGATIVE);
+        assert_eq!((cpu.a, cpu.x, cpu.y), (0x40, 0x50, 0x30));
+    }
+
+    #[test]
+    fn inc_and_dec_memory() {
+        // INC $10 / DEC $11 / INC $1234,X
+        let (mut cpu, mut asc) = setup(&[0xE6, 0x10, 0xC6, 0x11, 0xFE, 0x34, 0x12]);
+        cpu.x = 0x01;
+        asc.write(0x0010, 0xff);
+        asc.write(0x0011, 0x00);
+        asc.write(0x1235, 0x7f);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0010), 0x00);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), ZERO);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0011), 0xff);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), NEGATIVE);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x1235), 0x80);
+    }
+
+    #[test]
+    fn inc_and_dec_registers() {
+        // INX / INY / DEX / DEY
+        let (mut cpu, mut asc) = setup(&[0xE8, 0xC8, 0xCA, 0x88]);
+        cpu.x = 0xff;
+        cpu.y = 0x7f;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.x, 0x00);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), ZERO);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.y, 0x80);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), NEGATIVE);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.x, 0xff);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.y, 0x7f);
+        assert_eq!(status(&cpu) & (ZERO | NEGATIVE), 0);
+    }
+
+    #[test]
+    fn shifts_on_the_accumulator() {
+        // ASL A / LSR A
+        let (mut cpu, mut asc) = setup(&[0x0A, 0x4A]);
+        cpu.a = 0x81;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x02);
+        assert_eq!(status(&cpu) & (CARRY | ZERO | NEGATIVE), CARRY);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x01);
+        assert_eq!(status(&cpu) & (CARRY | ZERO | NEGATIVE), 0);
+    }
+
+    #[test]
+    fn rotates_through_carry() {
+        // ROL A / ROR A / ROR A
+        let (mut cpu, mut asc) = setup(&[0x2A, 0x6A, 0x6A]);
+        cpu.a = 0x80;
+        cpu.carry_flag = false;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(status(&cpu) & (CARRY | ZERO), CARRY | ZERO);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x80);
+        assert_eq!(status(&cpu) & (CARRY | NEGATIVE), NEGATIVE);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.a, 0x40);
+        assert_eq!(status(&cpu) & (CARRY | NEGATIVE), 0);
+    }
+
+    #[test]
+    fn shifts_and_rotates_on_memory() {
+        // ASL $10 / LSR $11 / ROL $12 / ROR $13
+        let (mut cpu, mut asc) = setup(&[0x06, 0x10, 0x46, 0x11, 0x26, 0x12, 0x66, 0x13]);
+        asc.write(0x0010, 0x40);
+        asc.write(0x0011, 0x03);
+        asc.write(0x0012, 0x80);
+        asc.write(0x0013, 0x02);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0010), 0x80);
+        assert_eq!(status(&cpu) & (CARRY | NEGATIVE), NEGATIVE);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0011), 0x01);
+        assert_eq!(status(&cpu) & CARRY, CARRY);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0012), 0x01);
+        assert_eq!(status(&cpu) & CARRY, CARRY);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0013), 0x81);
+        assert_eq!(status(&cpu) & (CARRY | NEGATIVE), NEGATIVE);
+    }
+
+    #[test]
+    fn flag_instructions() {
+        // SEC / SED / SEI / CLC / CLD / CLI / CLV
+        let (mut cpu, mut asc) = setup(&[0x38, 0xF8, 0x78, 0x18, 0xD8, 0x58, 0xB8]);
+
+        step(&mut cpu, &mut asc);
+        step(&mut cpu, &mut asc);
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu), CARRY | DECIMAL | INTERRUPT);
+
+        cpu.overflow_flag = true;
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu), DECIMAL | INTERRUPT | OVERFLOW);
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu), INTERRUPT | OVERFLOW);
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu), OVERFLOW);
+        step(&mut cpu, &mut asc);
+        assert_eq!(status(&cpu), 0);
+    }
+
+    #[test]
+    fn jmp_absolute() {
+        let (mut cpu, mut asc) = setup(&[0x4C, 0x34, 0x12]);
+
+        assert_eq!(step(&mut cpu, &mut asc), 3);
+        assert_eq!(cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn jmp_indirect() {
+        let (mut cpu, mut asc) = setup(&[0x6C, 0x00, 0x03]);
+        asc.write(0x0300, 0x78);
+        asc.write(0x0301, 0x56);
+
+        assert_eq!(step(&mut cpu, &mut asc), 5);
+        assert_eq!(cpu.pc, 0x5678);
+    }
+
+    #[test]
+    fn jmp_indirect_does_not_cross_pages() {
+        // The high byte comes from $0300 instead of $0400
+        let (mut cpu, mut asc) = setup(&[0x6C, 0xFF, 0x03]);
+        asc.write(0x03ff, 0x78);
+        asc.write(0x0300, 0x56);
+        asc.write(0x0400, 0x99);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.pc, 0x5678);
+    }
+
+    #[test]
+    fn jsr_and_rts() {
+        // JSR $8010 ... $8010: RTS
+        let mut program = vec![0x20, 0x10, 0x80];
+        program.resize(0x10, 0xEA);
+        program.push(0x60);
+        let (mut cpu, mut asc) = setup(&program);
+
+        assert_eq!(step(&mut cpu, &mut asc), 6);
+        assert_eq!(cpu.pc, 0x8010);
+        assert_eq!(cpu.sp, 0xfd);
+        // The return address points at the last byte of the JSR
+        assert_eq!(asc.read(0x01ff), 0x80);
+        assert_eq!(asc.read(0x01fe), 0x02);
+
+        assert_eq!(step(&mut cpu, &mut asc), 6);
+        assert_eq!(cpu.pc, 0x8003);
+        assert_eq!(cpu.sp, 0xff);
+    }
+
+    #[test]
+    fn brk_and_rti() {
+        let (mut cpu, mut asc) = setup(&[0x00]);
+        asc.write(0xfffe, 0x00);
+        asc.write(0xffff, 0x90);
+        asc.write(0x9000, 0x40);
+        cpu.carry_flag = true;
+
+        assert_eq!(step(&mut cpu, &mut asc), 7);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(cpu.sp, 0xfc);
+        assert_eq!(asc.read(0x01ff), 0x80);
+        assert_eq!(asc.read(0x01fe), 0x02);
+        assert_eq!(asc.read(0x01fd), CARRY | BREAK | RESERVED);
+        assert_eq!(status(&cpu) & INTERRUPT, INTERRUPT);
+
+        assert_eq!(step(&mut cpu, &mut asc), 6);
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(cpu.sp, 0xff);
+        assert_eq!(status(&cpu) & (CARRY | INTERRUPT), CARRY);
+    }
+
+    #[test]
+    fn branches_follow_their_flag() {
+        type SetFlag = fn(&mut Cpu, bool);
+
+        // (opcode, flag setter, taken when the flag is set)
+        let branches: [(u8, SetFlag, bool); 8] = [
+            (0x10, |cpu, set| cpu.negative_flag = set, false),
+            (0x30, |cpu, set| cpu.negative_flag = set, true),
+            (0x50, |cpu, set| cpu.overflow_flag = set, false),
+            (0x70, |cpu, set| cpu.overflow_flag = set, true),
+            (0x90, |cpu, set| cpu.carry_flag = set, false),
+            (0xB0, |cpu, set| cpu.carry_flag = set, true),
+            (0xD0, |cpu, set| cpu.zero_flag = set, false),
+            (0xF0, |cpu, set| cpu.zero_flag = set, true),
+        ];
+
+        for (opcode, set_flag, taken_when_set) in branches {
+            for flag in [false, true] {
+                let (mut cpu, mut asc) = setup(&[opcode, 0x10]);
+                set_flag(&mut cpu, flag);
+
+                step(&mut cpu, &mut asc);
+                let expected = if flag == taken_when_set {
+                    PROGRAM_START + 2 + 0x10
+                } else {
+                    PROGRAM_START + 2
+                };
+                assert_eq!(cpu.pc, expected, "{opcode:#04x} with flag {flag}");
+            }
+        }
+    }
+
+    #[test]
+    fn branch_not_taken_takes_two_cycles() {
+        let (mut cpu, mut asc) = setup(&[0xD0, 0x10]);
+        cpu.zero_flag = true;
+
+        assert_eq!(step(&mut cpu, &mut asc), 2);
+    }
+
+    #[test]
+    fn taken_branch_takes_an_extra_cycle() {
+        let (mut cpu, mut asc) = setup(&[0xD0, 0x10]);
+
+        assert_eq!(step(&mut cpu, &mut asc), 3);
+        assert_eq!(cpu.pc, PROGRAM_START + 0x12);
+    }
+
+    #[test]
+    fn branch_across_pages_takes_two_extra_cycles() {
+        // BNE -4 from $8000 lands on $7FFE
+        let (mut cpu, mut asc) = setup(&[0xD0, 0xFC]);
+
+        assert_eq!(step(&mut cpu, &mut asc), 4);
+        assert_eq!(cpu.pc, 0x7ffe);
+    }
+
+    #[test]
+    fn branch_page_cross_is_measured_from_the_next_instruction() {
+        // The branch sits at $80FE, so the next instruction is already on
+        // page $81, like the target.
+        let mut program = vec![0xEA; 0xfe];
+        program.extend([0xD0, 0x01]);
+        let (mut cpu, mut asc) = setup(&program);
+        cpu.pc = PROGRAM_START + 0xfe;
+
+        assert_eq!(step(&mut cpu, &mut asc), 3);
+        assert_eq!(cpu.pc, 0x8101);
+    }
+
+    #[test]
+    fn branch_backwards() {
+        // BNE -4
+        let (mut cpu, mut asc) = setup(&[0xEA, 0xEA, 0xD0, 0xFC]);
+        cpu.pc = PROGRAM_START + 2;
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(cpu.pc, PROGRAM_START);
+    }
+
+    #[test]
+    fn slo_shifts_memory_then_ors() {
+        let (mut cpu, mut asc) = setup(&[0x07, 0x10]);
+        cpu.a = 0x01;
+        asc.write(0x0010, 0x81);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0010), 0x02);
+        assert_eq!(cpu.a, 0x03);
+        assert_eq!(status(&cpu) & CARRY, CARRY);
+    }
+
+    #[test]
+    fn sre_shifts_memory_then_eors() {
+        let (mut cpu, mut asc) = setup(&[0x47, 0x10]);
+        cpu.a = 0xff;
+        asc.write(0x0010, 0x03);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0010), 0x01);
+        assert_eq!(cpu.a, 0xfe);
+        assert_eq!(status(&cpu) & (CARRY | NEGATIVE), CARRY | NEGATIVE);
+    }
+
+    #[test]
+    fn rla_rotates_memory_then_ands() {
+        let (mut cpu, mut asc) = setup(&[0x27, 0x10]);
+        cpu.a = 0x0f;
+        cpu.carry_flag = true;
+        asc.write(0x0010, 0x84);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0010), 0x09);
+        assert_eq!(cpu.a, 0x09);
+        assert_eq!(status(&cpu) & CARRY, CARRY);
+    }
+
+    #[test]
+    fn rra_rotates_memory_then_adds() {
+        let (mut cpu, mut asc) = setup(&[0x67, 0x10]);
+        cpu.a = 0x10;
+        asc.write(0x0010, 0x03);
+
+        // The bit rotated out becomes the carry of the addition
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0010), 0x01);
+        assert_eq!(cpu.a, 0x12);
+    }
+
+    #[test]
+    fn isc_increments_memory_then_subtracts() {
+        let (mut cpu, mut asc) = setup(&[0xE7, 0x10]);
+        cpu.a = 0x10;
+        cpu.carry_flag = true;
+        asc.write(0x0010, 0x04);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0010), 0x05);
+        assert_eq!(cpu.a, 0x0b);
+        assert_eq!(status(&cpu) & CARRY, CARRY);
+    }
+
+    #[test]
+    fn dcp_decrements_memory_then_compares() {
+        let (mut cpu, mut asc) = setup(&[0xC7, 0x10]);
+        cpu.a = 0x04;
+        asc.write(0x0010, 0x05);
+
+        step(&mut cpu, &mut asc);
+        assert_eq!(asc.read(0x0010), 0x04);
+        assert_eq!(cpu.a, 0x04);
+        assert_eq!(status(&cpu) & (CARRY | ZERO), CARRY | ZERO);
+    }
+
+    #[test]
+    fn nops_do_not_touch_state() {
+        let (mut cpu, mut asc) = setup(&[0xEA, 0x04, 0x10, 0x0C, 0x00, 0x03]);
+        asc.write(0x0010, 0x80);
+        cpu.a = 0x12;
+
+        for _ in 0..3 {
+            step(&mut cpu, &mut asc);
+        }
+
+        assert_eq!(cpu.registers().a, 0x12);
+        assert_eq!(status(&cpu), 0);
+        assert_eq!(asc.read(0x0010), 0x80);
+        assert_eq!(cpu.pc, PROGRAM_START + 6);
+    }
+
+    #[test]
+    fn nmi_pushes_pc_and_status() {
+        let (mut cpu, mut asc) = setup(&[]);
+        asc.write(0xfffa, 0x00);
+        asc.write(0xfffb, 0xc0);
+        cpu.zero_flag = true;
+
+        cpu.nmi(&mut asc);
+        assert_eq!(cpu.pc, 0xc000);
+        assert_eq!(asc.read(0x01ff), 0x80);
+        assert_eq!(asc.read(0x01fe), 0x00);
+        assert_eq!(asc.read(0x01fd), ZERO);
+    }
+
+    #[test]
+    fn irq_pushes_pc_and_status() {
+        let (mut cpu, mut asc) = setup(&[]);
+        asc.write(0xfffe, 0x00);
+        asc.write(0xffff, 0xd0);
+        cpu.carry_flag = true;
+
+        assert!(cpu.irq(&mut asc));
+        assert_eq!(cpu.pc, 0xd000);
+        assert_eq!(asc.read(0x01ff), 0x80);
+        assert_eq!(asc.read(0x01fe), 0x00);
+        assert_eq!(asc.read(0x01fd), CARRY | RESERVED);
+        assert_eq!(status(&cpu) & INTERRUPT, INTERRUPT);
+    }
+
+    #[test]
+    fn irq_is_masked_by_interrupt_flag() {
+        let (mut cpu, mut asc) = setup(&[]);
+        cpu.interrupt_flag = true;
+
+        assert!(!cpu.irq(&mut asc));
+        assert_eq!(cpu.pc, PROGRAM_START);
+        assert_eq!(cpu.sp, 0xff);
+    }
+
+    #[test]
+    fn step_reports_the_instruction_it_ran() {
+        let (mut cpu, mut asc) = setup(&[0xBD, 0xFF, 0x01]);
+        cpu.x = 1;
+
+        let info = cpu.step(&mut asc);
+
+        assert_eq!(info.pc, PROGRAM_START);
+        assert_eq!(info.opcode, 0xBD);
+        assert_eq!(info.mnemonic, "LDA");
+        assert_eq!(info.operand(), &[0xFF, 0x01]);
+        assert_eq!(info.cycles, 5);
+        assert_eq!(info.registers, cpu.registers());
+        assert_eq!(info.to_string(), "LDA $01FF,X");
+    }
+
+    #[test]
+    fn disassembles_every_addressing_mode() {
+        #[rustfmt::skip]
+        let (_, asc) = setup(&[
+            0x18,
+            0x0A,
+            0xA9, 0x44,
+            0xA5, 0x44,
+            0xB5, 0x44,
+            0xB6, 0x44,
+            0xD0, 0xFE,
+            0xAD, 0x00, 0x44,
+            0xBD, 0x00, 0x44,
+            0xB9, 0x00, 0x44,
+            0x6C, 0x00, 0x44,
+            0xA1, 0x44,
+            0xB1, 0x44,
+            0x02,
+        ]);
+
+        let expected = [
+            "CLC",
+            "ASL A",
+            "LDA #$44",
+            "LDA $44",
+            "LDA $44,X",
+            "LDX $44,Y",
+            "BNE $800A",
+            "LDA $4400",
+            "LDA $4400,X",
+            "LDA $4400,Y",
+            "JMP ($4400)",
+            "LDA ($44,X)",
+            "LDA ($44),Y",
+            ".byte $02",
+        ];
+
+        let mut pc = PROGRAM_START;
+        for expected in expected {
+            let (text, len) = disassemble(&asc, pc);
+            assert_eq!(text, expected);
+            pc += len;
+        }
+    }
+
+    #[test]
+    fn reset_jumps_to_reset_vector() {
+        let (mut cpu, mut asc) = setup(&[]);
+        asc.write(0xfffc, 0x34);
+        asc.write(0xfffd, 0x12);
+
+        cpu.reset(&mut asc);
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(status(&cpu) & INTERRUPT, INTERRUPT);
+    }
 }
@@ -0,0 +1,300 @@
+// Runs Tom Harte's single step tests, 10000 random initial states per opcode
+// with the expected state after running one instruction. The NES flavor of
+// the vectors (no decimal mode) lives in the `nes6502/v1` directory of
+// https://github.com/SingleStepTests/65x02, point `HARTE_TESTS` at it:
+//
+//   HARTE_TESTS=65x02/nes6502/v1 cargo test --features harte single_step
+//
+// The vectors of a regular 6502 (the `6502/v1` directory) also cover decimal
+// mode, set `HARTE_DECIMAL=1` to run them with BCD arithmetic enabled.
+//
+// The CPU doesn't model individual bus cycles, so only the cycle count of the
+// instruction is compared, not the sequence of accesses.
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::Path;
+use std::rc::Rc;
+
+use super::tests::TestBus;
+use super::*;
+
+// The B and unused bits don't exist inside the CPU
+const STATUS_COMPARE_MASK: u8 = 0b1100_1111;
+
+#[derive(Debug)]
+enum Json {
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(format!("trailing data at byte {}", parser.pos));
+        }
+        Ok(value)
+    }
+
+    fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value)
+                .unwrap_or_else(|| panic!("missing field {key}")),
+            _ => panic!("{key} looked up on a non object"),
+        }
+    }
+
+    fn number(&self) -> u64 {
+        match self {
+            Json::Number(n) => *n as u64,
+            _ => panic!("expected a number, found {self:?}"),
+        }
+    }
+
+    fn array(&self) -> &[Json] {
+        match self {
+            Json::Array(values) => values,
+            _ => panic!("expected an array, found {self:?}"),
+        }
+    }
+
+    fn string(&self) -> &str {
+        match self {
+            Json::String(s) => s,
+            _ => panic!("expected a string, found {self:?}"),
+        }
+    }
+}
+
+// Just enough JSON for the test vectors, which have no booleans or nulls
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() != Some(byte) {
+            return Err(format!("expected '{}' at byte {}", byte as char, self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(_) => self.number(),
+            None => Err("unexpected end of file".to_string()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut fields = vec![];
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            let name = self.string()?;
+            self.expect(b':')?;
+            fields.push((name, self.value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                _ => break,
+            }
+        }
+        self.expect(b'}')?;
+        Ok(Json::Object(fields))
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut values = vec![];
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                _ => break,
+            }
+        }
+        self.expect(b']')?;
+        Ok(Json::Array(values))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut s = String::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.pos) else {
+                return Err("unterminated string".to_string());
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => return Ok(s),
+                b'\\' => {
+                    let escaped = self.bytes.get(self.pos).copied();
+                    self.pos += 1;
+                    match escaped {
+                        Some(b'n') => s.push('\n'),
+                        Some(b't') => s.push('\t'),
+                        Some(c @ (b'"' | b'\\' | b'/')) => s.push(c as char),
+                        _ => return Err(format!("unsupported escape at byte {}", self.pos)),
+                    }
+                }
+                _ => s.push(byte as char),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self.pos < self.bytes.len()
+            && matches!(
+                self.bytes[self.pos],
+                b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'
+            )
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| format!("invalid number at byte {start}"))
+    }
+}
+
+// Checks one vector, returning what differed from the expected state
+fn run_test(test: &Json) -> Result<(), String> {
+    let initial = test.get("initial");
+    let expected = test.get("final");
+
+    let mut bus = TestBus {
+        memory: vec![0; 0x10000],
+    };
+    for entry in initial.get("ram").array() {
+        let entry = entry.array();
+        bus.memory[entry[0].number() as usize] = entry[1].number() as u8;
+    }
+
+    let mut asc = Asc::new();
+    asc.register_device_range(0..=0xffff, Rc::new(RefCell::new(bus)));
+
+    let mut cpu = Cpu::new();
+    cpu.set_decimal_mode(env::var_os("HARTE_DECIMAL").is_some());
+    cpu.pc = initial.get("pc").number() as u16;
+    cpu.sp = initial.get("s").number() as u8;
+    cpu.a = initial.get("a").number() as u8;
+    cpu.x = initial.get("x").number() as u8;
+    cpu.y = initial.get("y").number() as u8;
+    cpu.word_to_status(initial.get("p").number() as u8);
+
+    cpu.step(&mut asc);
+    let cycles = cpu.cycles.0;
+
+    let mut errors = vec![];
+    let regs = cpu.registers();
+    let mut compare = |name: &str, found: u64, expected: u64| {
+        if found != expected {
+            errors.push(format!("{name} is {found:#x}, expected {expected:#x}"));
+        }
+    };
+    compare("pc", regs.pc as u64, expected.get("pc").number());
+    compare("s", regs.sp as u64, expected.get("s").number());
+    compare("a", regs.a as u64, expected.get("a").number());
+    compare("x", regs.x as u64, expected.get("x").number());
+    compare("y", regs.y as u64, expected.get("y").number());
+    compare(
+        "p",
+        (regs.status & STATUS_COMPARE_MASK) as u64,
+        expected.get("p").number() & STATUS_COMPARE_MASK as u64,
+    );
+    for entry in expected.get("ram").array() {
+        let entry = entry.array();
+        let addr = entry[0].number() as u16;
+        let found = asc.peek(addr).unwrap();
+        compare(&format!("[{addr:#06x}]"), found as u64, entry[1].number());
+    }
+    compare(
+        "cycles",
+        cycles as u64,
+        test.get("cycles").array().len() as u64,
+    );
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+
+#[test]
+fn single_step() {
+    let dir = env::var("HARTE_TESTS")
+        .expect("HARTE_TESTS must point to the nes6502/v1 directory of the single step tests");
+
+    let mut failures = vec![];
+    for opcode in 0..=0xffu8 {
+        let path = Path::new(&dir).join(format!("{opcode:02x}.json"));
+        let text = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("could not read {}: {e}", path.display()));
+        let tests = Json::parse(&text)
+            .unwrap_or_else(|e| panic!("could not parse {}: {e}", path.display()));
+
+        // Opcodes the CPU doesn't implement panic on the first vector
+        let result = panic::catch_unwind(|| {
+            let tests = tests.array();
+            let mut failed = tests
+                .iter()
+                .filter_map(|test| run_test(test).err().map(|e| (test.get("name").string(), e)));
+            let first = failed.next();
+            first.map(|first| (first, failed.count() + 1, tests.len()))
+        });
+
+        match result {
+            Ok(None) => {}
+            Ok(Some(((name, error), failed, total))) => failures.push(format!(
+                "{opcode:02x}: {failed}/{total} failed, first \"{name}\": {error}"
+            )),
+            Err(_) => failures.push(format!("{opcode:02x}: not implemented")),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} opcodes failed:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
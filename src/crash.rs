@@ -0,0 +1,69 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::machine::Machine;
+use crate::patch;
+
+// What the emulator was running, for the report
+pub struct CrashContext<'a> {
+    pub rom_file: &'a str,
+    pub patch_files: &'a [String],
+}
+
+// Writes everything needed to look into a crash to a new `crash-<time>`
+// directory: a report with the reason, command line, ROM hash and CPU
+// state, the last instructions in the reference trace format, dumps of the
+// RAM, PRG RAM, VRAM and OAM, the PPU state and the config file in use.
+// There is no save state, the emulator can't make them yet.
+pub fn write_bundle(
+    nes: &mut Machine,
+    reason: &str,
+    context: &CrashContext,
+) -> io::Result<PathBuf> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs())
+        .unwrap_or_default();
+    let dir = PathBuf::from(format!("crash-{time}"));
+    fs::create_dir_all(&dir)?;
+
+    let rom_crc = match fs::read(context.rom_file) {
+        Ok(rom) => format!("{:08X}", patch::rom_crc32(&rom)),
+        Err(e) => format!("unknown ({e})"),
+    };
+    let regs = nes.cpu().registers();
+
+    let mut report = String::new();
+    report += &format!("reason: {reason}\n");
+    report += &format!("command: {}\n", env::args().collect::<Vec<_>>().join(" "));
+    report += &format!("version: {}\n", env!("CARGO_PKG_VERSION"));
+    report += &format!("ROM: {} (CRC32 {rom_crc})\n", context.rom_file);
+    for patch_file in context.patch_files {
+        report += &format!("patch: {patch_file}\n");
+    }
+    report += &format!("frame: {}\n", nes.frame());
+    report += &format!(
+        "registers: PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}\n",
+        regs.pc, regs.a, regs.x, regs.y, regs.status, regs.sp
+    );
+    report += &format!("state hash: {:016x}\n", nes.state_hash());
+    report += "save state: not included, not supported yet\n";
+    fs::write(dir.join("report.txt"), report)?;
+
+    fs::write(dir.join("trace.log"), nes.trace_log())?;
+    fs::write(dir.join("ram.bin"), nes.ram())?;
+    fs::write(dir.join("sram.bin"), nes.sram())?;
+    fs::write(dir.join("vram.bin"), nes.vram())?;
+    fs::write(dir.join("oam.bin"), nes.oam())?;
+    fs::write(dir.join("ppu_state.bin"), nes.ppu_state())?;
+
+    if let Some(path) = Config::path().filter(|path| path.exists()) {
+        fs::copy(path, dir.join("config.toml"))?;
+    }
+
+    Ok(dir)
+}
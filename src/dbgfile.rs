@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Line types of the ld65 debug info, only assembler source is shown
+const LINE_TYPE_ASM: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine<'a> {
+    pub file: &'a str,
+    pub line: usize,
+    // Text of the line, if the source file could be read
+    pub text: Option<&'a str>,
+}
+
+impl fmt::Display for SourceLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)?;
+        if let Some(text) = self.text {
+            write!(f, ": {}", text.trim())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct SourceFile {
+    name: String,
+    lines: Vec<String>,
+}
+
+// Maps CPU addresses to assembler source lines with the debug info written
+// by `ld65 --dbgfile` (or `cl65 -g --dbgfile`). Segments are assumed to be
+// mapped at their run address, which holds for games without bank switching.
+// Based on https://cc65.github.io/doc/debugging.html
+#[derive(Debug, Default)]
+pub struct DebugInfo {
+    files: Vec<SourceFile>,
+    lines: HashMap<u16, (usize, usize)>,
+}
+
+impl DebugInfo {
+    // Source files are looked up relative to the debug file
+    pub fn load(path: &Path) -> Result<DebugInfo, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut info = DebugInfo::parse(&text)?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for file in &mut info.files {
+            if let Ok(source) = fs::read_to_string(dir.join(&file.name)) {
+                file.lines = source.lines().map(str::to_string).collect();
+            }
+        }
+        Ok(info)
+    }
+
+    pub fn parse(text: &str) -> Result<DebugInfo, String> {
+        let mut files = HashMap::new();
+        let mut segments = HashMap::new();
+        let mut spans = HashMap::new();
+        let mut lines = vec![];
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {message}", i + 1);
+
+            let Some((kind, fields)) = line.split_once('\t') else {
+                continue;
+            };
+            let fields = parse_fields(fields);
+            let field = |name: &str| fields.get(name).map(String::as_str);
+            let number = |name: &str| {
+                let value = field(name).ok_or_else(|| error(&format!("missing {name}")))?;
+                parse_number(value).ok_or_else(|| error(&format!("invalid {name} {value}")))
+            };
+
+            match kind {
+                "file" => {
+                    let name = field("name").ok_or_else(|| error("missing name"))?;
+                    files.insert(number("id")?, name.to_string());
+                }
+                "seg" => {
+                    segments.insert(number("id")?, number("start")?);
+                }
+                "span" => {
+                    spans.insert(
+                        number("id")?,
+                        (number("seg")?, number("start")?, number("size")?),
+                    );
+                }
+                "line" => {
+                    let line_type = field("type").map_or(Some(LINE_TYPE_ASM), parse_number);
+                    if line_type != Some(LINE_TYPE_ASM) {
+                        continue;
+                    }
+                    // Lines that didn't generate code have no spans
+                    let Some(span_ids) = field("span") else {
+                        continue;
+                    };
+                    for span in span_ids.split('+') {
+                        let span = parse_number(span).ok_or_else(|| error("invalid span"))?;
+                        lines.push((number("file")?, number("line")?, span));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut ids: Vec<u32> = files.keys().copied().collect();
+        ids.sort();
+        let file_index: HashMap<u32, usize> =
+            ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+
+        let mut info = DebugInfo {
+            files: ids
+                .iter()
+                .map(|id| SourceFile {
+                    name: files[id].clone(),
+                    lines: vec![],
+                })
+                .collect(),
+            lines: HashMap::new(),
+        };
+
+        for (file, line, span) in lines {
+            let (Some(&file), Some(&(segment, start, size))) =
+                (file_index.get(&file), spans.get(&span))
+            else {
+                return Err(format!("line of unknown file {file} or span {span}"));
+            };
+            let segment_start = segments
+                .get(&segment)
+                .ok_or_else(|| format!("span of unknown segment {segment}"))?;
+
+            for offset in 0..size {
+                let addr = (segment_start + start + offset) as u16;
+                info.lines.insert(addr, (file, line as usize));
+            }
+        }
+
+        Ok(info)
+    }
+
+    // The source line that generated the byte at `addr`
+    pub fn source_line(&self, addr: u16) -> Option<SourceLine<'_>> {
+        let &(file, line) = self.lines.get(&addr)?;
+        let file = &self.files[file];
+        Some(SourceLine {
+            file: &file.name,
+            line,
+            text: file.lines.get(line.wrapping_sub(1)).map(String::as_str),
+        })
+    }
+}
+
+// Splits `key=value,key="quoted, value"` pairs
+fn parse_fields(text: &str) -> HashMap<&str, String> {
+    let mut fields = HashMap::new();
+    let mut rest = text;
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, next) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let next = quoted[end..].trim_start_matches('"');
+            (&quoted[..end], next.strip_prefix(',').unwrap_or(next))
+        } else {
+            value.split_once(',').unwrap_or((value, ""))
+        };
+        fields.insert(key.trim(), value.to_string());
+        rest = next;
+    }
+    fields
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
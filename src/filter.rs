@@ -0,0 +1,263 @@
+use crate::ppu_core::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// An image of 24 bit RGB pixels, row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+impl Frame {
+    // A frame the size the PPU renders
+    pub fn screen(rgb: Vec<u8>) -> Frame {
+        Frame {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            rgb,
+        }
+    }
+
+    // Coordinates out of the frame get the closest pixel on its edge
+    pub fn pixel(&self, x: isize, y: isize) -> [u8; 3] {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        let i = (y * self.width + x) * 3;
+        [self.rgb[i], self.rgb[i + 1], self.rgb[i + 2]]
+    }
+}
+
+// Turns the finished frame into the image drawn on the window, the last step
+// before it is uploaded. The output can be bigger than the input, the screen
+// stretches whatever it gets over the picture area.
+pub trait VideoFilter {
+    fn apply(&mut self, frame: Frame) -> Frame;
+}
+
+type NewFilter = fn() -> Box<dyn VideoFilter>;
+
+// The filters `[window] filter` and `--filter` can pick. Adding one only
+// takes an entry here.
+const FILTERS: &[(&str, NewFilter)] = &[
+    ("nearest", || Box::new(Nearest)),
+    ("scale2x", || Box::new(Scale2x)),
+    ("scale3x", || Box::new(Scale3x)),
+    ("ntsc", || Box::new(Ntsc)),
+    ("crt", || Box::new(Crt)),
+];
+
+pub const DEFAULT: &str = "nearest";
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    FILTERS.iter().map(|(name, _)| *name)
+}
+
+pub fn new(name: &str) -> Option<Box<dyn VideoFilter>> {
+    FILTERS
+        .iter()
+        .find(|(filter, _)| *filter == name)
+        .map(|(_, new)| new())
+}
+
+// Sharp square pixels, the frame as the PPU rendered it
+pub struct Nearest;
+
+impl VideoFilter for Nearest {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        frame
+    }
+}
+
+// Pixel art upscalers: every pixel becomes 2x2 (or 3x3) pixels, which take
+// the color of a neighbor where two of them meet on a diagonal edge, so
+// slopes come out smooth instead of blocky. Flat areas and lone pixels are
+// left alone.
+// Based on https://www.scale2x.it/algorithm
+pub struct Scale2x;
+
+pub struct Scale3x;
+
+// A pixel and its eight neighbors, row by row
+fn neighborhood(frame: &Frame, x: usize, y: usize) -> [[u8; 3]; 9] {
+    let (x, y) = (x as isize, y as isize);
+    #[rustfmt::skip]
+    let offsets = [
+        (-1, -1), (0, -1), (1, -1),
+        (-1, 0), (0, 0), (1, 0),
+        (-1, 1), (0, 1), (1, 1),
+    ];
+    offsets.map(|(dx, dy)| frame.pixel(x + dx, y + dy))
+}
+
+// Builds a frame `scale` times as big, where `expand` turns the neighborhood
+// of every pixel into its `scale * scale` output pixels, row by row
+fn upscale<const N: usize>(
+    frame: &Frame,
+    scale: usize,
+    expand: fn([[u8; 3]; 9]) -> [[u8; 3]; N],
+) -> Frame {
+    let (width, height) = (frame.width * scale, frame.height * scale);
+    let mut rgb = vec![0; width * height * 3];
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let block = expand(neighborhood(frame, x, y));
+            for (i, pixel) in block.iter().enumerate() {
+                let (out_x, out_y) = (x * scale + i % scale, y * scale + i / scale);
+                let offset = (out_y * width + out_x) * 3;
+                rgb[offset..offset + 3].copy_from_slice(pixel);
+            }
+        }
+    }
+    Frame { width, height, rgb }
+}
+
+impl VideoFilter for Scale2x {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        upscale(&frame, 2, |[_, b, _, d, e, f, _, h, _]| {
+            if b == h || d == f {
+                return [e; 4];
+            }
+            [
+                if d == b { d } else { e },
+                if b == f { f } else { e },
+                if d == h { d } else { e },
+                if h == f { f } else { e },
+            ]
+        })
+    }
+}
+
+impl VideoFilter for Scale3x {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        upscale(&frame, 3, |[a, b, c, d, e, f, g, h, i]| {
+            if b == h || d == f {
+                return [e; 9];
+            }
+            [
+                if d == b { d } else { e },
+                if (d == b && e != c) || (b == f && e != a) {
+                    b
+                } else {
+                    e
+                },
+                if b == f { f } else { e },
+                if (d == b && e != g) || (d == h && e != a) {
+                    d
+                } else {
+                    e
+                },
+                e,
+                if (b == f && e != i) || (h == f && e != c) {
+                    f
+                } else {
+                    e
+                },
+                if d == h { d } else { e },
+                if (d == h && e != i) || (h == f && e != g) {
+                    h
+                } else {
+                    e
+                },
+                if h == f { f } else { e },
+            ]
+        })
+    }
+}
+
+// The color bleeding of a composite video signal. Color travels on a much
+// narrower band than brightness, so it smears over several pixels while
+// edges stay fairly sharp.
+pub struct Ntsc;
+
+// Weights of the neighbors of a pixel, from the leftmost
+const LUMA_TAPS: [f32; 3] = [0.25, 0.5, 0.25];
+const CHROMA_TAPS: [f32; 7] = [0.0625, 0.125, 0.1875, 0.25, 0.1875, 0.125, 0.0625];
+
+fn rgb_to_yiq([r, g, b]: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = [r, g, b].map(|c| c as f32);
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        0.596 * r - 0.274 * g - 0.322 * b,
+        0.211 * r - 0.523 * g + 0.312 * b,
+    ]
+}
+
+fn yiq_to_rgb([y, i, q]: [f32; 3]) -> [u8; 3] {
+    [
+        y + 0.956 * i + 0.621 * q,
+        y - 0.272 * i - 0.647 * q,
+        y - 1.106 * i + 1.703 * q,
+    ]
+    .map(|c| c.clamp(0.0, 255.0) as u8)
+}
+
+impl VideoFilter for Ntsc {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        let yiq: Vec<[f32; 3]> = frame
+            .rgb
+            .chunks_exact(3)
+            .map(|pixel| rgb_to_yiq([pixel[0], pixel[1], pixel[2]]))
+            .collect();
+        // Along the row, repeating the pixels on its edges
+        let blur = |x: usize, y: usize, channel: usize, taps: &[f32]| -> f32 {
+            let reach = taps.len() / 2;
+            taps.iter()
+                .enumerate()
+                .map(|(k, weight)| {
+                    let x = (x + k).saturating_sub(reach).min(frame.width - 1);
+                    weight * yiq[y * frame.width + x][channel]
+                })
+                .sum()
+        };
+
+        let mut rgb = Vec::with_capacity(frame.rgb.len());
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                rgb.extend(yiq_to_rgb([
+                    blur(x, y, 0, &LUMA_TAPS),
+                    blur(x, y, 1, &CHROMA_TAPS),
+                    blur(x, y, 2, &CHROMA_TAPS),
+                ]));
+            }
+        }
+        Frame { rgb, ..frame }
+    }
+}
+
+// A TV screen up close: every pixel becomes 3x3, with a darker line between
+// rows like the gaps between scanlines and columns tinted red, green and
+// blue like an aperture grille.
+pub struct Crt;
+
+const CRT_SCALE: usize = 3;
+const SCANLINE_GAP: f32 = 0.55;
+const GRILLE_DIM: f32 = 0.75;
+// Makes up for the brightness the mask takes away
+const CRT_GAIN: f32 = 1.25;
+
+impl VideoFilter for Crt {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        let (width, height) = (frame.width * CRT_SCALE, frame.height * CRT_SCALE);
+        let mut rgb = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            let row = if y % CRT_SCALE == CRT_SCALE - 1 {
+                SCANLINE_GAP
+            } else {
+                1.0
+            };
+            for x in 0..width {
+                let pixel = frame.pixel((x / CRT_SCALE) as isize, (y / CRT_SCALE) as isize);
+                for (channel, value) in pixel.into_iter().enumerate() {
+                    let grille = if channel == x % CRT_SCALE {
+                        1.0
+                    } else {
+                        GRILLE_DIM
+                    };
+                    let value = value as f32 * row * grille * CRT_GAIN;
+                    rgb.push(value.min(255.0) as u8);
+                }
+            }
+        }
+        Frame { width, height, rgb }
+    }
+}
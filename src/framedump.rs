@@ -0,0 +1,64 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::png;
+use crate::ppu_core::{self, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    #[default]
+    Png,
+    // The 24 bit RGB pixels with no header, SCREEN_WIDTH x SCREEN_HEIGHT
+    Raw,
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(DumpFormat::Png),
+            "raw" => Ok(DumpFormat::Raw),
+            _ => Err(format!("unknown frame dump format {s}")),
+        }
+    }
+}
+
+// Writes every n-th frame to `<dir>/frame-<frame>.png` (or `.rgb`), for
+// tools that build videos or datasets out of a run.
+pub struct FrameDump {
+    dir: PathBuf,
+    format: DumpFormat,
+    every: usize,
+}
+
+impl FrameDump {
+    // Creates the directory if needed
+    pub fn new(dir: PathBuf, format: DumpFormat, every: usize) -> io::Result<FrameDump> {
+        fs::create_dir_all(&dir)?;
+        Ok(FrameDump {
+            dir,
+            format,
+            every: every.max(1),
+        })
+    }
+
+    // Writes the frame if it is one of the dumped ones
+    pub fn frame(&self, frame: usize, framebuffer: &[u16]) -> io::Result<()> {
+        if !frame.is_multiple_of(self.every) {
+            return Ok(());
+        }
+
+        let rgb = ppu_core::to_rgb(framebuffer);
+        let (bytes, extension) = match self.format {
+            DumpFormat::Png => (png::encode_rgb(SCREEN_WIDTH, SCREEN_HEIGHT, &rgb), "png"),
+            DumpFormat::Raw => (rgb, "rgb"),
+        };
+        fs::write(
+            self.dir.join(format!("frame-{frame:06}.{extension}")),
+            bytes,
+        )
+    }
+}
@@ -1,4 +1,15 @@
 use glow::HasContext;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::filter::Frame;
+use crate::heatmap::{Heatmap, HEATMAP_SIZE};
+use crate::ppu_core::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const HEATMAP_COLUMNS: u32 = 64;
+pub const HEATMAP_CELL_SIZE: u32 = 8;
 
 pub fn setup() -> (
     sdl2::Sdl,
@@ -31,6 +42,149 @@ pub fn setup() -> (
     }
 }
 
+// The part of a window of the given size the frame is drawn to: as big as
+// it fits keeping its aspect ratio, centered. Returns x, y, width and height,
+// from the bottom left corner like OpenGL viewports.
+pub fn screen_rect(width: u32, height: u32) -> (i32, i32, i32, i32) {
+    let scale = (width as f32 / SCREEN_WIDTH as f32).min(height as f32 / SCREEN_HEIGHT as f32);
+    let (w, h) = (
+        (SCREEN_WIDTH as f32 * scale) as i32,
+        (SCREEN_HEIGHT as f32 * scale) as i32,
+    );
+    ((width as i32 - w) / 2, (height as i32 - h) / 2, w, h)
+}
+
+// Shows the frames rendered by the PPU, uploading each one to a texture
+// stretched over the viewport. The rest of the window is filled with the
+// border color. Video filters can change the frame size, the texture follows.
+pub struct Screen {
+    program: glow::Program,
+    texture: glow::Texture,
+    texture_size: (usize, usize),
+    vao: glow::VertexArray,
+}
+
+impl Screen {
+    pub fn new(gl: &glow::Context) -> Screen {
+        let texture = create_tex(
+            gl,
+            glow::TEXTURE_2D,
+            glow::RGB8 as i32,
+            SCREEN_WIDTH as i32,
+            SCREEN_HEIGHT as i32,
+            glow::RGB,
+            &vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+        );
+
+        // x, y, u, v of a triangle strip covering the window, with the first
+        // row of the frame at the top.
+        #[rustfmt::skip]
+        let quad: [f32; 16] = [
+            -1.0, -1.0, 0.0, 1.0,
+            1.0, -1.0, 1.0, 1.0,
+            -1.0, 1.0, 0.0, 0.0,
+            1.0, 1.0, 1.0, 0.0,
+        ];
+
+        unsafe {
+            let program = create_program(
+                gl,
+                include_str!("../assets/screen.vert"),
+                include_str!("../assets/screen.frag"),
+            );
+
+            let verts = core::slice::from_raw_parts(
+                quad.as_ptr() as *const u8,
+                quad.len() * core::mem::size_of::<f32>(),
+            );
+
+            let vao = gl.create_vertex_array().unwrap();
+            gl.bind_vertex_array(Some(vao));
+
+            let vbo = gl.create_buffer().unwrap();
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(vbo));
+            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, verts, glow::STATIC_DRAW);
+
+            let stride = 4 * core::mem::size_of::<f32>() as i32;
+            let pos_loc = gl.get_attrib_location(program, "pos").unwrap();
+            gl.enable_vertex_attrib_array(pos_loc);
+            gl.vertex_attrib_pointer_f32(pos_loc, 2, glow::FLOAT, false, stride, 0);
+
+            let uv_loc = gl.get_attrib_location(program, "uv_in").unwrap();
+            gl.enable_vertex_attrib_array(uv_loc);
+            gl.vertex_attrib_pointer_f32(
+                uv_loc,
+                2,
+                glow::FLOAT,
+                false,
+                stride,
+                2 * core::mem::size_of::<f32>() as i32,
+            );
+
+            Screen {
+                program,
+                texture,
+                texture_size: (SCREEN_WIDTH, SCREEN_HEIGHT),
+                vao,
+            }
+        }
+    }
+
+    // Fits the frame in a window of the given size
+    pub fn resize(&self, gl: &glow::Context, width: u32, height: u32) {
+        let (x, y, w, h) = screen_rect(width, height);
+        unsafe {
+            gl.viewport(x, y, w, h);
+        }
+    }
+
+    // Draws a frame, with the borders in `border`
+    pub fn draw(&mut self, gl: &glow::Context, frame: &Frame, border: [u8; 3]) {
+        unsafe {
+            let [r, g, b] = border.map(|c| c as f32 / 255.0);
+            gl.clear_color(r, g, b, 1.0);
+            gl.clear(glow::COLOR_BUFFER_BIT);
+
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
+            gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+            if self.texture_size != (frame.width, frame.height) {
+                gl.tex_image_2d(
+                    glow::TEXTURE_2D,
+                    0,
+                    glow::RGB8 as i32,
+                    frame.width as i32,
+                    frame.height as i32,
+                    0,
+                    glow::RGB,
+                    glow::UNSIGNED_BYTE,
+                    Some(&frame.rgb),
+                );
+                self.texture_size = (frame.width, frame.height);
+            } else {
+                gl.tex_sub_image_2d(
+                    glow::TEXTURE_2D,
+                    0,
+                    0,
+                    0,
+                    frame.width as i32,
+                    frame.height as i32,
+                    glow::RGB,
+                    glow::UNSIGNED_BYTE,
+                    glow::PixelUnpackData::Slice(&frame.rgb),
+                );
+            }
+
+            gl.use_program(Some(self.program));
+            let sampler = gl.get_uniform_location(self.program, "screen_sampler");
+            gl.uniform_1_i32(sampler.as_ref(), 0);
+
+            gl.bind_vertex_array(Some(self.vao));
+            gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+        }
+    }
+}
+
 pub unsafe fn create_program(
     gl: &glow::Context,
     vert_shader: &str,
@@ -128,3 +282,218 @@ pub fn create_tex(
         return tex;
     }
 }
+
+// Draws one cell per RAM byte, 64 bytes per row, with writes in red and
+// reads in green on a logarithmic scale.
+pub fn draw_heatmap(canvas: &mut Canvas<Window>, heatmap: &Heatmap) {
+    let heat = |count: u32| ((u32::BITS - count.leading_zeros()) * 24).min(255) as u8;
+
+    for addr in 0..HEATMAP_SIZE {
+        let color = Color::RGB(heat(heatmap.writes()[addr]), heat(heatmap.reads()[addr]), 0);
+        let x = addr as u32 % HEATMAP_COLUMNS * HEATMAP_CELL_SIZE;
+        let y = addr as u32 / HEATMAP_COLUMNS * HEATMAP_CELL_SIZE;
+
+        canvas.set_draw_color(color);
+        canvas
+            .fill_rect(Rect::new(
+                x as i32,
+                y as i32,
+                HEATMAP_CELL_SIZE,
+                HEATMAP_CELL_SIZE,
+            ))
+            .ok();
+    }
+
+    canvas.present();
+}
+
+// Mixes every frame half and half with the one before it, like the
+// persistence of a CRT phosphor. Games that flicker sprites on alternate
+// frames for transparency or shadows look steady instead of blinking.
+#[derive(Debug, Default)]
+pub struct FrameBlender {
+    previous: Vec<u8>,
+}
+
+impl FrameBlender {
+    pub fn new() -> FrameBlender {
+        FrameBlender::default()
+    }
+
+    // Takes a frame of 24 bit RGB pixels and returns what should be shown
+    pub fn blend(&mut self, rgb: Vec<u8>) -> Vec<u8> {
+        let blended = if self.previous.len() == rgb.len() {
+            rgb.iter()
+                .zip(&self.previous)
+                .map(|(&a, &b)| ((a as u16 + b as u16) / 2) as u8)
+                .collect()
+        } else {
+            rgb.clone()
+        };
+        self.previous = rgb;
+        blended
+    }
+
+    // Forgets the last frame, so turning blending back on doesn't show a
+    // stale one
+    pub fn clear(&mut self) {
+        self.previous.clear();
+    }
+}
+
+const SAVE_INDICATOR_SIZE: usize = 6;
+const SAVE_INDICATOR_COLOR: [u8; 3] = [0xe0, 0x20, 0x20];
+
+// Draws a small square on the top right corner of a frame of 24 bit RGB
+// pixels, shown while the game writes to battery-backed RAM
+pub fn draw_save_indicator(rgb: &mut [u8]) {
+    let margin = 4;
+    for y in margin..margin + SAVE_INDICATOR_SIZE {
+        for x in SCREEN_WIDTH - margin - SAVE_INDICATOR_SIZE..SCREEN_WIDTH - margin {
+            let i = (y * SCREEN_WIDTH + x) * 3;
+            rgb[i..i + 3].copy_from_slice(&SAVE_INDICATOR_COLOR);
+        }
+    }
+}
+
+// Magenta, which the NES palette doesn't have at full brightness, so the
+// outline stands out on any background
+const OUTLINE_COLOR: [u8; 3] = [0xff, 0x00, 0xff];
+
+// Draws a 1 pixel outline around the sprites of a frame of 24 bit RGB
+// pixels, on the pixels next to the sprite mask (see `Machine::sprite_mask`)
+// that aren't part of it
+pub fn outline_sprites(rgb: &mut [u8], mask: &[bool]) {
+    let is_sprite = |x: isize, y: isize| {
+        (0..SCREEN_WIDTH as isize).contains(&x)
+            && (0..SCREEN_HEIGHT as isize).contains(&y)
+            && mask[y as usize * SCREEN_WIDTH + x as usize]
+    };
+
+    for y in 0..SCREEN_HEIGHT as isize {
+        for x in 0..SCREEN_WIDTH as isize {
+            let edge = !is_sprite(x, y)
+                && (is_sprite(x - 1, y)
+                    || is_sprite(x + 1, y)
+                    || is_sprite(x, y - 1)
+                    || is_sprite(x, y + 1));
+            if edge {
+                let i = (y as usize * SCREEN_WIDTH + x as usize) * 3;
+                rgb[i..i + 3].copy_from_slice(&OUTLINE_COLOR);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorBlindness {
+    // Missing or weak red (L) cones
+    Protanopia,
+    // Green (M) cones
+    Deuteranopia,
+    // Blue (S) cones
+    Tritanopia,
+}
+
+impl ColorBlindness {
+    pub fn from_name(name: &str) -> Option<ColorBlindness> {
+        match name {
+            "protanopia" => Some(ColorBlindness::Protanopia),
+            "deuteranopia" => Some(ColorBlindness::Deuteranopia),
+            "tritanopia" => Some(ColorBlindness::Tritanopia),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorBlindness::Protanopia => "protanopia",
+            ColorBlindness::Deuteranopia => "deuteranopia",
+            ColorBlindness::Tritanopia => "tritanopia",
+        }
+    }
+
+    // How the colors look with the deficiency, in LMS cone space
+    fn simulation(self) -> Matrix {
+        match self {
+            ColorBlindness::Protanopia => {
+                [[0.0, 2.02344, -2.52581], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+            }
+            ColorBlindness::Deuteranopia => {
+                [[1.0, 0.0, 0.0], [0.494207, 0.0, 1.24827], [0.0, 0.0, 1.0]]
+            }
+            ColorBlindness::Tritanopia => {
+                [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-0.395913, 0.801109, 0.0]]
+            }
+        }
+    }
+}
+
+type Matrix = [[f32; 3]; 3];
+
+const RGB_TO_LMS: Matrix = [
+    [17.8824, 43.5161, 4.11935],
+    [3.45565, 27.1554, 3.86714],
+    [0.0299566, 0.184309, 1.46709],
+];
+
+const LMS_TO_RGB: Matrix = [
+    [0.08094445, -0.1305044, 0.11672107],
+    [-0.010248534, 0.05401933, -0.11361471],
+    [-0.00036529693, -0.0041216147, 0.6935114],
+];
+
+// Where the color information a deficiency loses is moved to, as RGB
+// offsets from the error of each channel
+const ERROR_SHIFT: Matrix = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 3]; 3];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+// Daltonizes frames: the colors a kind of color blindness can't tell apart
+// are shifted towards ones it can, so red/green cues stay visible.
+// Based on Fidaner, Lin and Ozguven, "Analysis of Color Blindness"
+#[derive(Debug, Clone, Copy)]
+pub struct ColorFilter {
+    transform: Matrix,
+}
+
+impl ColorFilter {
+    pub fn new(kind: ColorBlindness) -> ColorFilter {
+        let simulated = multiply(&LMS_TO_RGB, &multiply(&kind.simulation(), &RGB_TO_LMS));
+
+        // out = rgb + shift * (rgb - simulated * rgb)
+        let mut transform = [[0.0; 3]; 3];
+        for (i, row) in transform.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                let error: f32 = (0..3)
+                    .map(|k| {
+                        let k_identity = if k == j { 1.0 } else { 0.0 };
+                        ERROR_SHIFT[i][k] * (k_identity - simulated[k][j])
+                    })
+                    .sum();
+                *value = identity + error;
+            }
+        }
+        ColorFilter { transform }
+    }
+
+    // Filters a frame of 24 bit RGB pixels in place
+    pub fn apply(&self, rgb: &mut [u8]) {
+        for pixel in rgb.chunks_exact_mut(3) {
+            let input = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+            for (value, row) in pixel.iter_mut().zip(&self.transform) {
+                let output: f32 = row.iter().zip(input).map(|(m, v)| m * v).sum();
+                *value = output.clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
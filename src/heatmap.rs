@@ -0,0 +1,54 @@
+use crate::watch::Access;
+
+// Covers the 2KB of internal CPU RAM
+pub const HEATMAP_SIZE: usize = 0x800;
+
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+}
+
+impl Heatmap {
+    pub fn new() -> Heatmap {
+        Heatmap {
+            reads: vec![0; HEATMAP_SIZE],
+            writes: vec![0; HEATMAP_SIZE],
+        }
+    }
+
+    pub fn record(&mut self, addr: u16, access: Access) {
+        let addr = addr as usize;
+        if addr >= HEATMAP_SIZE {
+            return;
+        }
+
+        let counts = match access {
+            Access::Read => &mut self.reads,
+            Access::Write => &mut self.writes,
+        };
+        counts[addr] = counts[addr].saturating_add(1);
+    }
+
+    pub fn reads(&self) -> &[u32] {
+        &self.reads
+    }
+
+    pub fn writes(&self) -> &[u32] {
+        &self.writes
+    }
+
+    // Decays the counts so the map shows recent activity instead of
+    // saturating after a few seconds.
+    pub fn fade(&mut self) {
+        for count in self.reads.iter_mut().chain(self.writes.iter_mut()) {
+            *count -= count.div_ceil(8);
+        }
+    }
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap::new()
+    }
+}
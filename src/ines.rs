@@ -1,19 +1,70 @@
 use std::fs;
 
+use crate::ppu_core::Mirroring;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    PlayChoice10,
+    // NES 2.0 extended console types, e.g. Famiclones and VT0x systems
+    Extended(u8),
+}
+
 #[derive(Debug)]
 pub struct INes {
     pub program: Vec<u8>,
     pub chr_rom: Option<Vec<u8>>,
+    pub prg_ram_size: usize,
+    pub console_type: ConsoleType,
+    pub mirroring: Mirroring,
+    pub mapper: u16,
+    // The PRG RAM keeps its contents with a battery
+    pub battery: bool,
 }
 
 impl INes {
     pub fn parse(path: &str) -> INes {
+        let bytes = fs::read(path).expect("could not read file!");
+        INes::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> INes {
+        const VERTICAL_MASK: u8 = 1;
+        const BATTERY_MASK: u8 = 1 << 1;
         const TRAINER_MASK: u8 = 1 << 2;
+        const FOUR_SCREEN_MASK: u8 = 1 << 3;
+        const NES2_MASK: u8 = 0b11 << 2;
+        const NES2_ID: u8 = 0b10 << 2;
+        const CONSOLE_TYPE_MASK: u8 = 0b11;
 
-        let bytes = fs::read(path).expect("could not read file!");
         assert_eq!(String::from_utf8_lossy(&bytes[0..3]), "NES");
 
         let flags_6 = bytes[6];
+        let flags_7 = bytes[7];
+        let is_nes2 = flags_7 & NES2_MASK == NES2_ID;
+
+        let console_type = match flags_7 & CONSOLE_TYPE_MASK {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::PlayChoice10,
+            _ if is_nes2 => ConsoleType::Extended(bytes[13] & 0xf),
+            _ => ConsoleType::Extended(0),
+        };
+
+        let mirroring = if flags_6 & FOUR_SCREEN_MASK != 0 {
+            Mirroring::FourScreen
+        } else if flags_6 & VERTICAL_MASK != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        // NES 2.0 adds 4 more bits on top of the 8 of iNES
+        let mut mapper = (flags_7 & 0xf0 | flags_6 >> 4) as u16;
+        if is_nes2 {
+            mapper |= ((bytes[8] & 0xf) as u16) << 8;
+        }
 
         let program_size = 16 * (1 << 10) * bytes[4] as usize;
         let program_rom_offset = 16 + 512 * (flags_6 & TRAINER_MASK) as usize;
@@ -21,6 +72,17 @@ impl INes {
         let chr_rom_size = 8 * (1 << 10) * bytes[5] as usize;
         let chr_rom_offset = program_rom_offset + program_size;
 
+        let prg_ram_size = if is_nes2 {
+            // Volatile and battery-backed sizes, both as a shift count of 64
+            // bytes where 0 means there is no such memory.
+            let shift_size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+            shift_size(bytes[10] & 0xf) + shift_size(bytes[10] >> 4)
+        } else {
+            // Byte 8 is in 8KB units and 0 still means 8KB, as most old dumps
+            // left it empty.
+            8 * (1 << 10) * (bytes[8] as usize).max(1)
+        };
+
         INes {
             program: bytes[(program_rom_offset)..(program_rom_offset + program_size)].to_vec(),
             chr_rom: if program_size != 0 {
@@ -28,6 +90,11 @@ impl INes {
             } else {
                 None
             },
+            prg_ram_size,
+            console_type,
+            mirroring,
+            mapper,
+            battery: flags_6 & BATTERY_MASK != 0,
         }
     }
 }
@@ -0,0 +1,249 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::asc::MemoryMapped;
+
+// Data lines of $4016/$4017 the expansion port drives, the joypads only use
+// bit 0
+const EXPANSION_BITS: u8 = 0x1e;
+
+pub type Expansion = Rc<RefCell<dyn ExpansionDevice>>;
+
+// In the order the joypad reports them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    // Names used by the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::A => "a",
+            Button::B => "b",
+            Button::Select => "select",
+            Button::Start => "start",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::Left => "left",
+            Button::Right => "right",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Button> {
+        match name.to_ascii_lowercase().as_str() {
+            "a" => Some(Button::A),
+            "b" => Some(Button::B),
+            "select" => Some(Button::Select),
+            "start" => Some(Button::Start),
+            "up" => Some(Button::Up),
+            "down" => Some(Button::Down),
+            "left" => Some(Button::Left),
+            "right" => Some(Button::Right),
+            _ => None,
+        }
+    }
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+#[derive(Debug, Default)]
+struct Joypad {
+    buttons: u8,
+    shift: u8,
+}
+
+// Standard joypads on both controller ports. Writing 1 to $4016 makes them
+// latch the buttons, then each read of $4016/$4017 shifts out one button of
+// the first/second joypad. A Famicom expansion port peripheral can be
+// plugged next to them, seeing the same writes and answering on the other
+// data lines.
+// Based on https://www.nesdev.org/wiki/Standard_controller
+#[derive(Debug, Default)]
+pub struct Controller {
+    strobe: bool,
+    pads: [Joypad; 2],
+    expansion: Option<Expansion>,
+}
+
+impl Controller {
+    pub fn new() -> Controller {
+        Controller::default()
+    }
+
+    pub fn set_button(&mut self, port: usize, button: Button, pressed: bool) {
+        let pad = &mut self.pads[port];
+        if pressed {
+            pad.buttons |= button.mask();
+        } else {
+            pad.buttons &= !button.mask();
+        }
+    }
+
+    // Replaces the peripheral on the expansion port, None unplugs it
+    pub fn plug(&mut self, expansion: Option<Expansion>) {
+        self.expansion = expansion;
+    }
+
+    fn latch(&mut self) {
+        for pad in &mut self.pads {
+            pad.shift = pad.buttons;
+        }
+    }
+}
+
+impl MemoryMapped for Controller {
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4016 => {
+                if let Some(expansion) = &self.expansion {
+                    expansion.borrow_mut().write(value & 0x7);
+                }
+                self.strobe = value & 1 != 0;
+                if self.strobe {
+                    self.latch();
+                }
+            }
+            _ => panic!("Address {addr:#x} is not registered by the controller"),
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let port = match addr {
+            0x4016 => 0,
+            0x4017 => 1,
+            _ => panic!("Address {addr:#x} is not registered by the controller"),
+        };
+
+        // While strobing the joypad keeps reporting the A button
+        if self.strobe {
+            self.latch();
+        }
+
+        let pad = &mut self.pads[port];
+        let bit = pad.shift & 1;
+        // Official joypads report 1 after the 8 buttons were read
+        pad.shift = (pad.shift >> 1) | 0x80;
+
+        let expansion = match &self.expansion {
+            Some(expansion) => expansion.borrow_mut().read(port) & EXPANSION_BITS,
+            None => 0,
+        };
+
+        // The upper bits are open bus, usually the high byte of the address
+        0x40 | expansion | bit
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        let port = match addr {
+            0x4016 => 0,
+            0x4017 => 1,
+            _ => panic!("Address {addr:#x} is not registered by the controller"),
+        };
+
+        let pad = &self.pads[port];
+        let bit = if self.strobe { pad.buttons } else { pad.shift } & 1;
+        let expansion = match &self.expansion {
+            Some(expansion) => expansion.borrow().peek(port) & EXPANSION_BITS,
+            None => 0,
+        };
+        0x40 | expansion | bit
+    }
+
+    // Nothing to set, the strobe is only a latch signal
+    fn poke(&mut self, _addr: u16, _value: u8) {}
+}
+
+// A peripheral on the Famicom expansion port, like the Arkanoid paddle, the
+// keyboard or the 3D glasses. It gets the OUT lines, bits 0-2 of every write
+// to $4016, where bit 0 is the joypad strobe, and answers reads of both
+// ports on bits 1-4.
+// Based on https://www.nesdev.org/wiki/Expansion_port
+pub trait ExpansionDevice: fmt::Debug {
+    fn write(&mut self, out: u8);
+
+    // Port 0 is $4016 and port 1 $4017. Only bits 1-4 of the result reach
+    // the CPU, and as with the joypads, reading can shift out the next bit.
+    fn read(&mut self, port: usize) -> u8;
+
+    // What `read` would return, without shifting anything out
+    fn peek(&self, port: usize) -> u8;
+}
+
+// The Famicom Arkanoid controller, a knob and a fire button. Strobing it
+// latches the knob position, which $4017 then shifts out inverted, most
+// significant bit first, on bit 1. The button is bit 1 of $4016.
+// Based on https://www.nesdev.org/wiki/Arkanoid_controller
+#[derive(Debug, Default)]
+pub struct ArkanoidPaddle {
+    position: u8,
+    fire: bool,
+    shift: u8,
+}
+
+impl ArkanoidPaddle {
+    // Range of the knob, roughly, it varies between units
+    const MIN: u8 = 0x62;
+    const MAX: u8 = 0xf2;
+
+    pub fn new() -> ArkanoidPaddle {
+        ArkanoidPaddle {
+            position: ArkanoidPaddle::MIN,
+            ..ArkanoidPaddle::default()
+        }
+    }
+
+    // From 0.0, turned fully left, to 1.0
+    pub fn set_position(&mut self, position: f32) {
+        let range = (ArkanoidPaddle::MAX - ArkanoidPaddle::MIN) as f32;
+        self.position = ArkanoidPaddle::MIN + (position.clamp(0.0, 1.0) * range) as u8;
+    }
+
+    pub fn set_fire(&mut self, pressed: bool) {
+        self.fire = pressed;
+    }
+}
+
+impl ExpansionDevice for ArkanoidPaddle {
+    fn write(&mut self, out: u8) {
+        if out & 1 != 0 {
+            self.shift = !self.position;
+        }
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        let value = self.peek(port);
+        if port == 1 {
+            self.shift <<= 1;
+        }
+        value
+    }
+
+    fn peek(&self, port: usize) -> u8 {
+        match port {
+            0 => (self.fire as u8) << 1,
+            _ => (self.shift >> 7) << 1,
+        }
+    }
+}
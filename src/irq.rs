@@ -0,0 +1,66 @@
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqSource {
+    FrameCounter,
+    Dmc,
+    Mapper,
+}
+
+impl IrqSource {
+    const ALL: [IrqSource; 3] = [IrqSource::FrameCounter, IrqSource::Dmc, IrqSource::Mapper];
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// The CPU /IRQ input is level triggered and wired-OR between every device
+// that can pull it low. Each source keeps its line asserted until the game
+// acknowledges it on that device (reading $4015 for the frame counter,
+// writing a mapper register, ...), so the sources are tracked separately and
+// the line stays low while any of them is still pending.
+#[derive(Debug, Clone, Default)]
+pub struct IrqLine {
+    sources: Rc<Cell<u8>>,
+}
+
+impl IrqLine {
+    pub fn new() -> IrqLine {
+        IrqLine::default()
+    }
+
+    pub fn assert(&self, source: IrqSource) {
+        self.sources.set(self.sources.get() | source.mask());
+    }
+
+    pub fn acknowledge(&self, source: IrqSource) {
+        self.sources.set(self.sources.get() & !source.mask());
+    }
+
+    pub fn is_pending(&self, source: IrqSource) -> bool {
+        self.sources.get() & source.mask() != 0
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.sources.get() != 0
+    }
+}
+
+impl fmt::Display for IrqLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pending: Vec<String> = IrqSource::ALL
+            .iter()
+            .filter(|s| self.is_pending(**s))
+            .map(|s| format!("{s:?}"))
+            .collect();
+
+        if pending.is_empty() {
+            write!(f, "IRQ: none")
+        } else {
+            write!(f, "IRQ: {}", pending.join(", "))
+        }
+    }
+}
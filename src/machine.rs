@@ -0,0 +1,674 @@
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::VecDeque;
+use std::num::Wrapping;
+use std::rc::Rc;
+
+use crate::apu::Apu;
+use crate::asc::{AccessCount, Asc, MemoryMapped, SplitDevice, UnmappedPolicy, WriteTracker};
+use crate::coverage::Coverage;
+use crate::cpu::{self, Cpu, Registers};
+use crate::dbgfile::DebugInfo;
+use crate::heatmap::Heatmap;
+use crate::ines::INes;
+use crate::input::{Button, Controller, Expansion};
+use crate::irq::IrqLine;
+use crate::mapper::{self, Cartridge, CartridgeSlot};
+use crate::ppu::{Ppu, ScrollPosition};
+use crate::ppu_core::Mirroring;
+use crate::ram::Ram;
+use crate::sram;
+use crate::system::{System, SystemBuilder};
+use crate::trace::ReferenceTrace;
+use crate::watch::Watchpoint;
+
+const SCANLINES_PER_FRAME: u32 = 262;
+
+// Instructions kept for the trace log of crash reports
+const TRACE_LENGTH: usize = 1000;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+const PPU_CYCLES_PER_SCANLINE: u32 = 341;
+// 2KB at $0000, mirrored up to $1FFF
+const INTERNAL_RAM_SIZE: usize = 0x800;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameEvents {
+    pub nmi: bool,
+    pub irq: bool,
+    // Scanline in which the game triggered OAM DMA
+    pub oam_dma: Option<u32>,
+    // Scanline in which sprite 0 hit the background
+    pub sprite_zero_hit: Option<u32>,
+    pub audio_samples: usize,
+}
+
+// Work done by each part of the machine since it was created, for
+// benchmarks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    pub instructions: u64,
+    // Including interrupts and OAM DMA
+    pub cpu_cycles: u64,
+    pub ppu_dots: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Condition,
+    Halted,
+    FrameLimit,
+}
+
+pub struct Machine {
+    system: System,
+    memory: Rc<RefCell<Ram>>,
+    cartridge: Cartridge,
+    // At $6000-$7FFF, None on boards without PRG RAM
+    prg_ram: Option<Rc<RefCell<Ram>>>,
+    battery: bool,
+    // Set by every write to PRG RAM
+    sram_written: Rc<Cell<bool>>,
+    ppu: Rc<RefCell<Ppu>>,
+    apu: Rc<RefCell<Apu>>,
+    controller: Rc<RefCell<Controller>>,
+    irq: IrqLine,
+    reference: Option<ReferenceTrace>,
+    frozen: Vec<(u16, u8)>,
+    halted: bool,
+    // State before each of the last TRACE_LENGTH instructions
+    recent: VecDeque<Registers>,
+    coverage: Option<Coverage>,
+    debug_info: Option<DebugInfo>,
+    // Kept for the buses of later cartridges
+    fast_path: bool,
+
+    frame: usize,
+    scanline: u32,
+    scanline_start: Wrapping<usize>,
+    scanline_instructions: u32,
+    // CPU cycle the APU has been clocked up to
+    apu_cycles: Wrapping<usize>,
+    events: FrameEvents,
+    counters: Counters,
+}
+
+impl Machine {
+    pub fn new(rom: INes) -> Result<Machine, String> {
+        let irq = IrqLine::new();
+        let prg_ram_size = rom.prg_ram_size;
+        let battery = rom.battery;
+        let cartridge = mapper::new(rom, irq.clone())?;
+
+        let mut machine = Machine {
+            // Replaced by the one of the cartridge
+            system: System {
+                cpu: Cpu::new(),
+                asc: Asc::new(),
+            },
+            memory: Rc::new(RefCell::new(Ram::new(INTERNAL_RAM_SIZE))),
+            cartridge: cartridge.clone(),
+            prg_ram: None,
+            battery,
+            sram_written: Rc::new(Cell::new(false)),
+            ppu: Rc::new(RefCell::new(Ppu::new(cartridge.clone()))),
+            apu: Rc::new(RefCell::new(Apu::new(irq.clone()))),
+            controller: Rc::new(RefCell::new(Controller::new())),
+            irq: irq.clone(),
+            reference: None,
+            frozen: vec![],
+            halted: false,
+            recent: VecDeque::with_capacity(TRACE_LENGTH),
+            coverage: None,
+            debug_info: None,
+            fast_path: true,
+
+            frame: 0,
+            scanline: 0,
+            scanline_start: Wrapping(0),
+            scanline_instructions: 0,
+            apu_cycles: Wrapping(0),
+            events: FrameEvents::default(),
+            counters: Counters::default(),
+        };
+
+        machine.connect(cartridge, irq, prg_ram_size);
+        Ok(machine)
+    }
+
+    // Swaps the cartridge and power cycles the machine. Returns the contents
+    // of the removed cartridge's PRG RAM so the caller can save them, or an
+    // error, leaving the old cartridge in, if the ROM's mapper isn't
+    // supported.
+    pub fn insert_cartridge(&mut self, rom: INes) -> Result<Vec<u8>, String> {
+        let irq = IrqLine::new();
+        let prg_ram_size = rom.prg_ram_size;
+        let battery = rom.battery;
+        let cartridge = mapper::new(rom, irq.clone())?;
+
+        let sram = self.sram();
+        self.battery = battery;
+        self.connect(cartridge, irq, prg_ram_size);
+        Ok(sram)
+    }
+
+    // Wires a cartridge to fresh devices and powers the machine on
+    fn connect(&mut self, cartridge: Cartridge, irq: IrqLine, prg_ram_size: usize) {
+        let mut ppu = Ppu::new(cartridge.clone());
+        for watchpoint in self.ppu.borrow_mut().take_watchpoints() {
+            ppu.add_watchpoint(watchpoint);
+        }
+
+        self.memory = Rc::new(RefCell::new(Ram::new(INTERNAL_RAM_SIZE)));
+        // Only 8KB fit the $6000-$7FFF window, more needs a mapper that
+        // switches banks
+        self.prg_ram = (prg_ram_size > 0).then(|| {
+            let size = prg_ram_size.min(sram::SRAM_SIZE);
+            Rc::new(RefCell::new(Ram::new(size)))
+        });
+        self.sram_written.set(false);
+        self.cartridge = cartridge;
+        self.ppu = Rc::new(RefCell::new(ppu));
+        self.irq = irq;
+        self.apu = Rc::new(RefCell::new(Apu::new(self.irq.clone())));
+
+        // The new system keeps the settings of the old bus
+        let asc = std::mem::replace(&mut self.system.asc, Asc::new());
+        self.system = self.map_devices(SystemBuilder::new(asc)).build();
+
+        self.halted = false;
+        self.recent.clear();
+        self.frame = 0;
+        self.scanline = 0;
+        self.scanline_start = Wrapping(0);
+        self.scanline_instructions = 0;
+        self.events = FrameEvents::default();
+        self.apu_cycles = self.system.cpu.cycles;
+    }
+
+    // The NES memory map
+    fn map_devices(&self, builder: SystemBuilder) -> SystemBuilder {
+        let port_2 = SplitDevice::new(self.controller.clone(), self.apu.clone());
+        let cartridge = Rc::new(RefCell::new(CartridgeSlot::new(self.cartridge.clone())));
+
+        // PRG RAM is left unmapped (open bus) on boards without it
+        let builder = match &self.prg_ram {
+            Some(prg_ram) => {
+                // Smaller chips repeat over the window, their sizes are
+                // powers of two
+                let end = sram::SRAM_START + (prg_ram.borrow().bytes().len() - 1) as u16;
+                let prg_ram = WriteTracker::new(prg_ram.clone(), self.sram_written.clone());
+                let builder =
+                    builder.device(sram::SRAM_START..=end, Rc::new(RefCell::new(prg_ram)));
+                match end {
+                    0x7fff => builder,
+                    _ => builder.mirror(end + 1..=0x7fff, end),
+                }
+            }
+            None => builder,
+        };
+
+        // Based on https://www.nesdev.org/wiki/CPU_memory_map
+        builder
+            .device(0x0000..=0x07ff, self.memory.clone()) // Internal RAM
+            .mirror(0x0800..=0x1fff, 0x07ff)
+            .device(0x2000..=0x2007, self.ppu.clone()) // PPU registers
+            .mirror(0x2008..=0x3fff, 0x2007)
+            .device(0x4000..=0x400f, self.apu.clone()) // Sound channels
+            .device(0x4014..=0x4014, self.ppu.clone()) // OAM DMA
+            .device(0x4015..=0x4015, self.apu.clone()) // APU status
+            .device(0x4016..=0x4016, self.controller.clone()) // Controller strobe and port 1
+            // Controller port 2 on reads, APU frame counter on writes
+            .device(0x4017..=0x4017, Rc::new(RefCell::new(port_2)))
+            .device(0x4020..=0x5fff, cartridge.clone()) // Cartridge space
+            .device(0x8000..=0xffff, cartridge) // PRG ROM and mapper registers
+            // Zero page and the stack are in the internal RAM
+            .fast_path(self.fast_path)
+    }
+
+    pub fn load_sram(&mut self, sram: Vec<u8>) {
+        if let Some(prg_ram) = &self.prg_ram {
+            prg_ram.borrow_mut().load(&sram);
+        }
+    }
+
+    // The $6000-$7FFF window, as save files store it. All zeros without PRG
+    // RAM.
+    pub fn sram(&self) -> Vec<u8> {
+        match &self.prg_ram {
+            Some(prg_ram) => {
+                let prg_ram = prg_ram.borrow();
+                prg_ram
+                    .bytes()
+                    .iter()
+                    .copied()
+                    .cycle()
+                    .take(sram::SRAM_SIZE)
+                    .collect()
+            }
+            None => vec![0; sram::SRAM_SIZE],
+        }
+    }
+
+    // Whether the cartridge keeps the PRG RAM with a battery, so the game
+    // can save to it
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    // Whether the game wrote to PRG RAM since the last call
+    pub fn take_sram_written(&self) -> bool {
+        self.sram_written.take()
+    }
+
+    // Internal RAM at $0000-$07FF
+    pub fn ram(&self) -> Vec<u8> {
+        (0x0000..=0x07ff)
+            .map(|addr| self.system.asc.peek(addr).unwrap_or(0))
+            .collect()
+    }
+
+    pub fn vram(&self) -> Vec<u8> {
+        self.ppu.borrow_mut().vram()
+    }
+
+    pub fn oam(&self) -> Vec<u8> {
+        self.ppu.borrow().oam().to_vec()
+    }
+
+    pub fn ppu_state(&self) -> Vec<u8> {
+        self.ppu.borrow_mut().state_bytes()
+    }
+
+    // The last instructions run, oldest first, in the format read by
+    // `ReferenceTrace`. They are disassembled from the current memory.
+    pub fn trace_log(&self) -> String {
+        let mut log = String::new();
+        for regs in &self.recent {
+            let (instruction, _) = cpu::disassemble(&self.system.asc, regs.pc);
+            log += &format!(
+                "{:04X}  {instruction:<14} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}\n",
+                regs.pc, regs.a, regs.x, regs.y, regs.status, regs.sp
+            );
+        }
+        log
+    }
+
+    pub fn follow_reference(&mut self, reference: ReferenceTrace) {
+        self.reference = Some(reference);
+    }
+
+    // Returns false when emulation must stop because the reference trace
+    // diverged or ran out of lines.
+    fn check_reference(&mut self) -> bool {
+        let Some(reference) = &mut self.reference else {
+            return true;
+        };
+
+        if reference.is_finished() {
+            eprintln!("reached the end of the reference trace without divergences");
+            return false;
+        }
+
+        match reference.check(self.system.cpu.registers()) {
+            Ok(()) => true,
+            Err(divergence) => {
+                let (instruction, _) = cpu::disassemble(&self.system.asc, self.system.cpu.pc);
+                eprintln!("{divergence}");
+                eprintln!("next:     {instruction}");
+                if let Some(line) = self.source_line(self.system.cpu.pc) {
+                    eprintln!("source:   {line}");
+                }
+                false
+            }
+        }
+    }
+
+    pub fn set_debug_info(&mut self, debug_info: DebugInfo) {
+        self.debug_info = Some(debug_info);
+    }
+
+    fn source_line(&self, pc: u16) -> Option<String> {
+        let line = self.debug_info.as_ref()?.source_line(pc)?;
+        Some(line.to_string())
+    }
+
+    // Where messages about the code at `pc` point to, with the source line
+    // when there is debug info for it
+    fn location(&self, pc: u16) -> String {
+        match self.source_line(pc) {
+            Some(line) => format!("PC {pc:#06x} ({line})"),
+            None => format!("PC {pc:#06x}"),
+        }
+    }
+
+    pub fn watch_vram(&mut self, watchpoint: Watchpoint) {
+        self.ppu.borrow_mut().add_watchpoint(watchpoint);
+    }
+
+    fn report_vram_watch_hits(&mut self, pc: u16) {
+        let hits = self.ppu.borrow_mut().take_watch_hits();
+        for hit in hits {
+            eprintln!("[WATCH]: PPU {hit} at {}", self.location(pc));
+        }
+    }
+
+    pub fn freeze(&mut self, addr: u16, value: u8) {
+        self.unfreeze(addr);
+        self.frozen.push((addr, value));
+    }
+
+    pub fn unfreeze(&mut self, addr: u16) {
+        self.frozen.retain(|(a, _)| *a != addr);
+    }
+
+    fn apply_frozen(&mut self) {
+        for &(addr, value) in &self.frozen {
+            self.system.asc.poke(addr, value);
+        }
+    }
+
+    pub fn set_unmapped_policy(&mut self, policy: UnmappedPolicy) {
+        self.system.asc.set_unmapped_policy(policy);
+    }
+
+    pub fn set_fast_path(&mut self, enabled: bool) {
+        self.fast_path = enabled;
+        self.system.asc.set_fast_path(enabled);
+    }
+
+    pub fn enable_bus_stats(&mut self) {
+        self.system.asc.enable_stats();
+    }
+
+    pub fn bus_stats(&self) -> Vec<(String, AccessCount)> {
+        self.system.asc.stats()
+    }
+
+    pub fn enable_heatmap(&mut self) {
+        self.system.asc.enable_heatmap();
+    }
+
+    pub fn heatmap_mut(&mut self) -> Option<&mut Heatmap> {
+        self.system.asc.heatmap_mut()
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.system.cpu
+    }
+
+    pub fn set_button(&mut self, port: usize, button: Button, pressed: bool) {
+        self.controller
+            .borrow_mut()
+            .set_button(port, button, pressed);
+    }
+
+    // Plugs a peripheral into the expansion port, None unplugs it
+    pub fn plug_expansion(&mut self, expansion: Option<Expansion>) {
+        self.controller.borrow_mut().plug(expansion);
+    }
+
+    pub fn irq(&self) -> &IrqLine {
+        &self.irq
+    }
+
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    // Audio generated since the last call, at apu::SAMPLE_RATE
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.borrow_mut().take_samples()
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        self.system.asc.read(addr)
+    }
+
+    // Reads memory without side effects, None where nothing is mapped
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        self.system.asc.peek(addr)
+    }
+
+    // NES color indices of the last frame, SCREEN_WIDTH pixels per row, see
+    // `ppu_core::to_rgb`
+    pub fn framebuffer(&self) -> Ref<'_, [u16]> {
+        Ref::map(self.ppu.borrow(), |ppu| ppu.framebuffer())
+    }
+
+    // Which pixels of the last frame hold a sprite, same layout as the
+    // framebuffer
+    pub fn sprite_mask(&self) -> Ref<'_, [bool]> {
+        Ref::map(self.ppu.borrow(), |ppu| ppu.sprite_mask())
+    }
+
+    // The backdrop color index, what a TV shows in the overscan around the
+    // picture
+    pub fn backdrop(&self) -> u8 {
+        self.ppu.borrow_mut().backdrop()
+    }
+
+    // The whole 512x480 nametable space as NES color indices, regardless of
+    // the part of it the game is showing
+    pub fn nametable_shot(&self) -> Vec<u16> {
+        self.ppu.borrow_mut().render_nametables()
+    }
+
+    // How the nametables are mirrored right now, the mapper may change it
+    pub fn mirroring(&self) -> Mirroring {
+        self.cartridge.borrow().mirroring()
+    }
+
+    // Scroll position the game used for a visible scanline of the last frame
+    pub fn scanline_scroll(&self, scanline: u32) -> Option<ScrollPosition> {
+        self.ppu.borrow().scanline_scroll(scanline)
+    }
+
+    // Hash of the emulated state, two machines that ran the same inputs must
+    // agree on it. Covers the CPU, the internal and PRG RAM, and the PPU
+    // registers, VRAM and OAM.
+    pub fn state_hash(&mut self) -> u64 {
+        let regs = self.system.cpu.registers();
+        let mut hash = fnv1a(FNV_OFFSET_BASIS, &regs.pc.to_le_bytes());
+        hash = fnv1a(hash, &[regs.sp, regs.a, regs.x, regs.y, regs.status]);
+        hash = fnv1a(hash, &(self.system.cpu.cycles.0 as u64).to_le_bytes());
+
+        let ram: Vec<u8> = (0x0000..=0x07ff)
+            .chain(0x6000..=0x7fff)
+            .filter_map(|addr| self.system.asc.peek(addr))
+            .collect();
+        hash = fnv1a(hash, &ram);
+
+        fnv1a(hash, &self.ppu.borrow_mut().state_bytes())
+    }
+
+    pub fn reset(&mut self) {
+        self.system.cpu.reset(&mut self.system.asc);
+    }
+
+    // Runs a single CPU instruction, returns false if the machine halted.
+    fn step(&mut self) -> bool {
+        // The line is level triggered, a source still asserting it after the
+        // handler returns raises the IRQ again
+        if self.irq.is_asserted() && self.system.cpu.irq(&mut self.system.asc) {
+            self.events.irq = true;
+        }
+
+        if !self.check_reference() {
+            self.halted = true;
+            return false;
+        }
+
+        if self.recent.len() == TRACE_LENGTH {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(self.system.cpu.registers());
+
+        let pc = self.system.cpu.pc;
+        let info = self.system.cpu.step(&mut self.system.asc);
+        self.counters.instructions += 1;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, info.size());
+        }
+        self.report_vram_watch_hits(pc);
+        self.apply_frozen();
+        self.clock_apu();
+
+        let oam_dma = self.ppu.borrow_mut().take_oam_dma();
+        if let Some(page) = oam_dma {
+            self.run_oam_dma(page);
+            self.events.oam_dma = Some(self.scanline);
+        }
+
+        if self.system.asc.take_unmapped_break() {
+            eprintln!("stopped on unmapped access at {}", self.location(pc));
+            self.halted = true;
+            return false;
+        }
+
+        true
+    }
+
+    // Copies a page of CPU memory into OAM. The CPU is stalled for the 513
+    // cycles of the transfer, plus one to align with the read cycles when
+    // the write to $4014 ended on an odd cycle.
+    fn run_oam_dma(&mut self, page: u8) {
+        let base = (page as u16) << 8;
+        for offset in 0..=0xff {
+            let value = self.system.asc.read(base | offset);
+            self.ppu.borrow_mut().write_oam_data(value);
+        }
+
+        self.system.cpu.cycles += 513 + self.system.cpu.cycles.0 % 2;
+    }
+
+    // Catches the APU up with the cycles the CPU spent since the last call,
+    // including the ones taken by interrupts.
+    fn clock_apu(&mut self) {
+        let elapsed = (self.system.cpu.cycles - self.apu_cycles).0;
+        self.events.audio_samples += self.apu.borrow_mut().clock(elapsed);
+        self.apu_cycles = self.system.cpu.cycles;
+        self.counters.cpu_cycles += elapsed as u64;
+    }
+
+    // Runs one instruction and, when the CPU used up the time of a scanline
+    // (or 1/3 of its PPU cycles worth of instructions), moves to the next one.
+    // The cycles past the end of the line count towards the next ones, an
+    // OAM DMA stalls the CPU for about 4.5 scanlines that the PPU still runs.
+    fn run_instruction(&mut self) {
+        let elapsed = (self.system.cpu.cycles - self.scanline_start).0;
+        self.ppu.borrow_mut().run_to_dot(elapsed as u32 * 3);
+
+        if !self.step() {
+            return;
+        }
+
+        self.scanline_instructions += 1;
+        let line_cycles = PPU_CYCLES_PER_SCANLINE.div_ceil(3) as usize;
+        while (self.system.cpu.cycles - self.scanline_start).0 >= line_cycles {
+            self.scanline_start += line_cycles;
+            self.end_scanline();
+        }
+        if self.scanline_instructions >= PPU_CYCLES_PER_SCANLINE.div_ceil(3) {
+            self.scanline_start = self.system.cpu.cycles;
+            self.end_scanline();
+        }
+    }
+
+    fn end_scanline(&mut self) {
+        self.ppu.borrow_mut().render_scanline(self.scanline);
+        self.counters.ppu_dots += PPU_CYCLES_PER_SCANLINE as u64;
+        // The flag stays set until the pre-render line
+        if self.events.sprite_zero_hit.is_none() && self.ppu.borrow().sprite_zero_hit() {
+            self.events.sprite_zero_hit = Some(self.scanline);
+        }
+
+        if self.scanline == 0 {
+            self.ppu.borrow_mut().reset_vblank();
+        }
+
+        if self.scanline == SCANLINES_PER_FRAME - 1 {
+            self.ppu.borrow_mut().start_frame();
+        }
+
+        if self.scanline == 241 {
+            self.ppu.borrow_mut().set_vblank();
+        }
+
+        if self.scanline == 241 && self.ppu.borrow().should_nmi() {
+            self.system.cpu.nmi(&mut self.system.asc);
+            self.events.nmi = true;
+        }
+
+        self.scanline += 1;
+        self.scanline_instructions = 0;
+
+        if self.scanline == SCANLINES_PER_FRAME {
+            self.scanline = 0;
+            self.frame += 1;
+        }
+
+        if self.scanline == SCANLINES_PER_FRAME - 1 {
+            self.ppu.borrow_mut().clear_sprite_flags();
+        }
+        self.ppu.borrow_mut().start_scanline(self.scanline);
+    }
+
+    pub fn run_frame(&mut self) -> FrameEvents {
+        let frame = self.frame;
+        while self.frame == frame && !self.halted {
+            self.run_instruction();
+        }
+
+        std::mem::take(&mut self.events)
+    }
+
+    // Runs until `condition` holds, checked before every instruction, giving
+    // up after `max_frames` frames.
+    pub fn run_until(
+        &mut self,
+        mut condition: impl FnMut(&mut Machine) -> bool,
+        max_frames: usize,
+    ) -> StopReason {
+        let last_frame = self.frame + max_frames;
+        loop {
+            if condition(self) {
+                return StopReason::Condition;
+            }
+            if self.halted {
+                return StopReason::Halted;
+            }
+            if self.frame >= last_frame {
+                return StopReason::FrameLimit;
+            }
+
+            self.run_instruction();
+        }
+    }
+}
+
+// FNV-1a, unlike std's hasher it is stable between builds, so hashes taken
+// on different machines can be compared.
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
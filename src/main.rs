@@ -1,148 +1,1337 @@
+pub mod apu;
 pub mod asc;
+pub mod audio;
+pub mod config;
+pub mod coverage;
 pub mod cpu;
+pub mod crash;
+pub mod dbgfile;
+pub mod filter;
+pub mod framedump;
 pub mod gfx;
+pub mod heatmap;
 pub mod ines;
+pub mod input;
+pub mod irq;
+pub mod machine;
+pub mod mapper;
+pub mod monitor;
+pub mod patch;
+pub mod png;
 pub mod ppu;
+pub mod ppu_core;
 pub mod ram;
+pub mod script;
+pub mod selftest;
+pub mod sram;
+pub mod stats;
+pub mod system;
+pub mod trace;
+pub mod watch;
 
 use std::cell::RefCell;
 use std::env;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::rc::Rc;
 use std::thread;
 use std::time;
 
-use glow::HasContext;
+use sdl2::controller::GameController;
 use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 
-use crate::cpu::Cpu;
-use crate::ines::INes;
-use crate::ppu::Ppu;
-use crate::ram::Ram;
-use asc::Asc;
+use crate::config::{Borders, Config, Unfocused};
+use crate::crash::CrashContext;
+use crate::dbgfile::DebugInfo;
+use crate::filter::{Frame, VideoFilter};
+use crate::framedump::{DumpFormat, FrameDump};
+use crate::ines::{ConsoleType, INes};
+use crate::input::{ArkanoidPaddle, Button};
+use crate::machine::Machine;
+use crate::monitor::Monitor;
+use crate::script::InputScript;
+use crate::stats::Stats;
+use crate::system::System;
+use crate::trace::ReferenceTrace;
+use crate::watch::Watchpoint;
 
-struct Machine {
-    cpu: Cpu,
-    ppu: Rc<RefCell<Ppu>>,
-    asc: Asc,
+fn export_sram(nes: &Machine, save_file: &str) {
+    if let Err(e) = sram::export(save_file, &nes.sram()) {
+        eprintln!("[ERROR]: could not write save file {save_file}: {e}");
+    }
 }
 
-impl Machine {
-    fn new(memory: Ram, ppu_memory: Ram) -> Machine {
-        let memory = Rc::new(RefCell::new(memory));
-        let ppu = Rc::new(RefCell::new(Ppu::new(ppu_memory)));
+// Keeps track of the game saving to battery-backed RAM, to show an indicator
+// while it does, write the save file once it is done and make sure closing
+// the emulator doesn't cut a save short. A save is a short burst of writes,
+// games that keep writing use the RAM as work RAM too and only get their
+// save file written.
+struct SaveGuard {
+    // Where the PRG RAM is saved to
+    save_file: Option<String>,
+    // Frames left until the writes count as done, restarted by every write
+    indicator_frames: u32,
+    // Length of the current burst of writes
+    burst_frames: u32,
+    // Set once a burst went on for too long to be a save
+    work_ram: bool,
+    // Writes not in the save file yet
+    unsaved: bool,
+    quit_requested: Option<time::Instant>,
+}
 
-        let mut asc = Asc::new();
-        // TODO: Handle memory mirroring
-        // Based on https://www.nesdev.org/wiki/CPU_memory_map
-        asc.register_device_range(0x0000..=0x07ff, memory.clone()); // Internal RAM
-        asc.register_device_range(0x2000..=0x2007, ppu.clone()); // PPU registers
-        asc.register_device(0x4014, ppu.clone()); // OAM DMA
-        asc.register_device_range(0x4020..=0xffff, memory); // Cartridge space
+impl SaveGuard {
+    // A second, so a save in progress shows for a moment after the last write
+    const INDICATOR_FRAMES: u32 = 60;
+    // Saves take well under this, counting the frames of INDICATOR_FRAMES
+    const MAX_SAVE_FRAMES: u32 = 4 * 60;
+    // Closing again within this time quits anyway
+    const CONFIRM_TIME: time::Duration = time::Duration::from_secs(3);
+    // Closing the window also sends a quit event, which isn't a second close
+    const REPEAT_TIME: time::Duration = time::Duration::from_millis(300);
 
-        Machine {
-            cpu: Cpu::new(),
-            ppu,
-            asc,
+    fn new(save_file: Option<String>) -> SaveGuard {
+        SaveGuard {
+            save_file,
+            indicator_frames: 0,
+            burst_frames: 0,
+            work_ram: false,
+            unsaved: false,
+            quit_requested: None,
         }
     }
 
-    fn power_on(&mut self) {
-        let (sdl, _video, window, gl, _gl_ctx) = gfx::setup();
+    // Called once per emulated frame. The save file is written when the
+    // game stops writing, so a crash or a killed emulator loses at most the
+    // save in progress.
+    fn update(&mut self, nes: &Machine) {
+        self.indicator_frames = self.indicator_frames.saturating_sub(1);
+        if nes.has_battery() && nes.take_sram_written() {
+            if self.indicator_frames == 0 {
+                self.burst_frames = 0;
+            }
+            self.indicator_frames = SaveGuard::INDICATOR_FRAMES;
+            self.unsaved = true;
+        }
 
-        let mut events = sdl.event_pump().unwrap();
+        let writing = self.indicator_frames > 0;
+        if writing {
+            self.burst_frames += 1;
+            if self.burst_frames > SaveGuard::MAX_SAVE_FRAMES {
+                self.work_ram = true;
+            }
+        }
 
-        self.cpu.reset(&mut self.asc);
-        self.ppu.borrow_mut().precal_chars(&gl);
-        self.ppu.borrow_mut().setup_pallet_tex(&gl);
-        unsafe {
-            gl.clear_color(0.1, 0.2, 0.3, 1.0);
+        if let (false, true, Some(save_file)) = (writing, self.unsaved, &self.save_file) {
+            export_sram(nes, save_file);
+            self.unsaved = false;
         }
+    }
+
+    fn is_saving(&self) -> bool {
+        self.indicator_frames > 0 && !self.work_ram
+    }
+
+    // Whether the emulator can close now. Otherwise warns about the save in
+    // progress, and closing again confirms.
+    fn confirm_quit(&mut self) -> bool {
+        if !self.is_saving() {
+            return true;
+        }
+
+        match self.quit_requested.map(|time| time.elapsed()) {
+            Some(elapsed) if elapsed < SaveGuard::REPEAT_TIME => return false,
+            Some(elapsed) if elapsed < SaveGuard::CONFIRM_TIME => return true,
+            _ => {}
+        }
+        eprintln!("[WARN]: the game is writing its save, close again to quit anyway");
+        self.quit_requested = Some(time::Instant::now());
+        false
+    }
+}
+
+// Walks through the buttons of a controller port, binding each one to the
+// next key or gamepad button pressed. Emulation is paused meanwhile.
+struct Remap {
+    port: usize,
+    next: usize,
+    // Restored if the remapping is cancelled
+    previous: Config,
+}
+
+impl Remap {
+    fn prompt(&self) -> String {
+        format!(
+            "press the key or gamepad button for {} (player {}), Esc to cancel",
+            Button::ALL[self.next].name(),
+            self.port + 1
+        )
+    }
+}
+
+fn save_bindings(config: &Config) {
+    let Some(path) = Config::path() else {
+        eprintln!("[ERROR]: could not save the bindings: no home directory");
+        return;
+    };
+    match config.save(&path) {
+        Ok(()) => eprintln!("saved the bindings to {}", path.display()),
+        Err(e) => eprintln!("[ERROR]: could not save the bindings: {e}"),
+    }
+}
+
+fn window_title(speed: f32) -> String {
+    if speed == 1.0 {
+        "emulator".to_string()
+    } else {
+        format!("emulator ({}%)", speed * 100.0)
+    }
+}
+
+// Settings of `run` from the command line and the config file
+struct RunOptions {
+    audio_device: Option<String>,
+    log_oam_dma: bool,
+    blend_frames: bool,
+    sprite_outline: bool,
+    color_filter: Option<gfx::ColorFilter>,
+    video_filter: Box<dyn VideoFilter>,
+    // Where the PRG RAM is saved to, also written on exit
+    save_file: Option<String>,
+    // Plugged into the expansion port, moved by the mouse
+    paddle: Option<Rc<RefCell<ArkanoidPaddle>>>,
+    // Plays player 1 until its last step, see `play_script`
+    script: Option<InputScript>,
+}
+
+fn run(
+    nes: &mut Machine,
+    config: &mut Config,
+    options: RunOptions,
+    mut watch: Option<RomWatch>,
+    mut rumble: Rumble,
+) {
+    let RunOptions {
+        audio_device,
+        log_oam_dma,
+        mut blend_frames,
+        mut sprite_outline,
+        color_filter,
+        mut video_filter,
+        save_file,
+        paddle,
+        script,
+    } = options;
+    let (sdl, video, mut window, gl, _gl_ctx) = gfx::setup();
+
+    let mut events = sdl.event_pump().unwrap();
+
+    let mut audio = audio::AudioOutput::open(&sdl, audio_device.as_deref())
+        .map_err(|e| eprintln!("audio disabled: {e}"))
+        .ok();
+
+    let gamepad_subsystem = sdl
+        .game_controller()
+        .map_err(|e| eprintln!("gamepads disabled: {e}"))
+        .ok();
+    // In the order they were connected, which is also their controller port
+    let mut gamepads: Vec<GameController> = vec![];
+
+    // A software renderer keeps SDL from touching the emulator's GL context
+    let mut heatmap_canvas = nes.heatmap_mut().is_some().then(|| {
+        let rows = heatmap::HEATMAP_SIZE as u32 / gfx::HEATMAP_COLUMNS;
+        video
+            .window(
+                "RAM heatmap",
+                gfx::HEATMAP_COLUMNS * gfx::HEATMAP_CELL_SIZE,
+                rows * gfx::HEATMAP_CELL_SIZE,
+            )
+            .build()
+            .unwrap()
+            .into_canvas()
+            .software()
+            .build()
+            .unwrap()
+    });
 
-        const FPS: f32 = 60.0;
-        let frame_time = time::Duration::from_secs_f32(1.0 / FPS);
+    let mut screen = gfx::Screen::new(&gl);
+    let (width, height) = window.size();
+    screen.resize(&gl, width, height);
+    let mut blender = gfx::FrameBlender::new();
 
-        const SCANLINES_PER_FRAME: u32 = 262;
-        const PPU_CYCLES_PER_SCANLINE: u32 = 341;
+    const FPS: f32 = 60.0;
+    let frame_time = time::Duration::from_secs_f32(1.0 / FPS);
 
-        loop {
-            let start = time::Instant::now();
+    // Emulation speeds cycled through by the slow motion hotkey
+    const SPEEDS: [f32; 3] = [1.0, 0.5, 0.25];
+    let mut speed = 0;
+    // Speed cap of `Unfocused::Throttle`
+    const BACKGROUND_SPEED: f32 = 0.25;
 
-            for scanline in 0..SCANLINES_PER_FRAME {
-                let cycles = self.cpu.cycles;
-                for tick in 0..PPU_CYCLES_PER_SCANLINE {
-                    if tick % 3 == 0 {
-                        self.cpu.read_instruction(&mut self.asc);
+    let mut focused = true;
+
+    let mut remap: Option<Remap> = None;
+    let mut saves = SaveGuard::new(save_file);
+
+    loop {
+        let start = time::Instant::now();
+        let background = if focused {
+            Unfocused::Run
+        } else {
+            config.unfocused
+        };
+
+        if remap.is_none() && background != Unfocused::Pause {
+            if let Some(script) = &script {
+                play_script(nes, script);
+            }
+            let frame_events = nes.run_frame();
+            if let (true, Some(scanline)) = (log_oam_dma, frame_events.oam_dma) {
+                eprintln!("frame {}: OAM DMA on scanline {scanline}", nes.frame());
+            }
+            if nes.is_halted() {
+                return;
+            }
+            rumble.update(nes, gamepads.first_mut());
+            saves.update(nes);
+        }
+
+        // Slow motion makes too few samples to keep the device fed, which
+        // would play as choppy repeats, so it stays silent
+        let samples = nes.take_audio_samples();
+        if let (Some(audio), Unfocused::Run, true) = (&audio, background, SPEEDS[speed] == 1.0) {
+            audio.queue(&samples);
+        }
+
+        let mut rgb = ppu_core::to_rgb(&nes.framebuffer());
+        if let Some(filter) = &color_filter {
+            filter.apply(&mut rgb);
+        }
+        if blend_frames {
+            rgb = blender.blend(rgb);
+        }
+        if sprite_outline {
+            gfx::outline_sprites(&mut rgb, &nes.sprite_mask());
+        }
+        if saves.is_saving() {
+            gfx::draw_save_indicator(&mut rgb);
+        }
+        let mut border = match config.borders {
+            Borders::Backdrop => ppu_core::to_rgb(&[nes.backdrop() as u16]),
+            Borders::Black => vec![0; 3],
+        };
+        if let Some(filter) = &color_filter {
+            filter.apply(&mut border);
+        }
+        let frame = video_filter.apply(Frame::screen(rgb));
+        screen.draw(&gl, &frame, [border[0], border[1], border[2]]);
+        window.gl_swap_window();
+
+        if let (Some(canvas), Some(heatmap)) = (&mut heatmap_canvas, nes.heatmap_mut()) {
+            gfx::draw_heatmap(canvas, heatmap);
+            heatmap.fade();
+        }
+
+        for e in events.poll_iter() {
+            if let Some(current) = &mut remap {
+                let button = Button::ALL[current.next];
+                let bound = match e {
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => {
+                        *config = current.previous.clone();
+                        remap = None;
+                        eprintln!("remapping cancelled");
+                        window.set_title(&window_title(SPEEDS[speed])).ok();
+                        continue;
+                    }
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        repeat: false,
+                        ..
+                    } => {
+                        config.rebind_key(current.port, button, keycode);
+                        true
                     }
+                    Event::ControllerButtonDown {
+                        button: pad_button, ..
+                    } => {
+                        config.rebind_gamepad(current.port, button, pad_button);
+                        true
+                    }
+                    Event::KeyDown { .. }
+                    | Event::KeyUp { .. }
+                    | Event::ControllerButtonUp { .. } => continue,
+                    // Window events are handled as usual
+                    _ => false,
+                };
 
-                    if cycles.0.abs_diff(self.cpu.cycles.0) > (PPU_CYCLES_PER_SCANLINE / 3) as usize
-                    {
-                        break;
+                if bound {
+                    current.next += 1;
+                    if current.next < Button::ALL.len() {
+                        eprintln!("{}", current.prompt());
+                        window.set_title(&current.prompt()).ok();
+                    } else {
+                        remap = None;
+                        window.set_title(&window_title(SPEEDS[speed])).ok();
+                        save_bindings(config);
                     }
+                    continue;
                 }
+            }
 
-                if scanline == 0 {
-                    self.ppu.borrow_mut().reset_vblank();
+            match e {
+                Event::Window {
+                    timestamp: _,
+                    window_id,
+                    win_event,
+                } => match win_event {
+                    WindowEvent::Resized(width, height) if window_id == window.id() => {
+                        screen.resize(&gl, width as u32, height as u32);
+                    }
+                    WindowEvent::Close if window_id != window.id() => heatmap_canvas = None,
+                    WindowEvent::Close if saves.confirm_quit() => return,
+                    // Moving between the emulator windows loses the focus
+                    // on one right before gaining it on the other
+                    WindowEvent::FocusGained => focused = true,
+                    WindowEvent::FocusLost => focused = false,
+                    _ => {}
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => {
+                    speed = (speed + 1) % SPEEDS.len();
+                    eprintln!("emulation speed: {}%", SPEEDS[speed] * 100.0);
+                    window.set_title(&window_title(SPEEDS[speed])).ok();
                 }
-
-                if scanline == 241 {
-                    self.ppu.borrow_mut().set_vblank();
+                Event::KeyDown {
+                    keycode: Some(keycode @ (Keycode::F6 | Keycode::F7)),
+                    repeat: false,
+                    ..
+                } => {
+                    let current = Remap {
+                        port: if keycode == Keycode::F6 { 0 } else { 1 },
+                        next: 0,
+                        previous: config.clone(),
+                    };
+                    // Buttons held now would stay pressed while paused
+                    for port in 0..2 {
+                        for button in Button::ALL {
+                            nes.set_button(port, button, false);
+                        }
+                    }
+                    eprintln!("{}", current.prompt());
+                    window.set_title(&current.prompt()).ok();
+                    remap = Some(current);
                 }
-
-                if scanline == 241 && self.ppu.borrow().should_nmi() {
-                    self.cpu.nmi(&mut self.asc);
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    repeat: false,
+                    ..
+                } => {
+                    blend_frames = !blend_frames;
+                    blender.clear();
+                    let state = if blend_frames { "on" } else { "off" };
+                    eprintln!("frame blending: {state}");
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    repeat: false,
+                    ..
+                } => {
+                    sprite_outline = !sprite_outline;
+                    let state = if sprite_outline { "on" } else { "off" };
+                    eprintln!("sprite outlines: {state}");
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => {
+                    let file_name = format!("map-{}.png", nes.frame());
+                    let rgb = ppu_core::to_rgb(&nes.nametable_shot());
+                    let png = png::encode_rgb(
+                        ppu_core::NAMETABLES_WIDTH,
+                        ppu_core::NAMETABLES_HEIGHT,
+                        &rgb,
+                    );
+                    match fs::write(&file_name, png) {
+                        Ok(()) => eprintln!(
+                            "saved the nametables to {file_name} ({} mirroring)",
+                            nes.mirroring()
+                        ),
+                        Err(e) => eprintln!("[ERROR]: could not write {file_name}: {e}"),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some((port, button)) = config.key(keycode) {
+                        nes.set_button(port, button, true);
+                    }
                 }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some((port, button)) = config.key(keycode) {
+                        nes.set_button(port, button, false);
+                    }
+                }
+                Event::MouseMotion { x, .. } => {
+                    if let Some(paddle) = &paddle {
+                        // Relative to the picture, not the borders
+                        let (width, height) = window.size();
+                        let (left, _, screen_width, _) = gfx::screen_rect(width, height);
+                        paddle
+                            .borrow_mut()
+                            .set_position((x - left) as f32 / screen_width as f32);
+                    }
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                }
+                | Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => {
+                    if let Some(paddle) = &paddle {
+                        let pressed = matches!(e, Event::MouseButtonDown { .. });
+                        paddle.borrow_mut().set_fire(pressed);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(subsystem) = &gamepad_subsystem {
+                        match subsystem.open(which) {
+                            Ok(gamepad) => {
+                                eprintln!("gamepad connected: {}", gamepad.name());
+                                gamepads.push(gamepad);
+                            }
+                            Err(e) => eprintln!("[WARN]: could not open gamepad: {e}"),
+                        }
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    gamepads.retain(|gamepad| gamepad.instance_id() != which);
+                }
+                Event::AudioDeviceAdded {
+                    iscapture: false, ..
+                }
+                | Event::AudioDeviceRemoved {
+                    iscapture: false, ..
+                } => {
+                    if let Some(audio) = &mut audio {
+                        audio.devices_changed();
+                    }
+                }
+                Event::ControllerButtonDown { which, button, .. }
+                | Event::ControllerButtonUp { which, button, .. } => {
+                    let pressed = matches!(e, Event::ControllerButtonDown { .. });
+                    let port = gamepads.iter().position(|g| g.instance_id() == which);
+                    if let Some(port) = port {
+                        if let Some(button) = config.gamepad_button(port, button) {
+                            nes.set_button(port, button, pressed);
+                        }
+                    }
+                }
+                Event::Quit { .. } if saves.confirm_quit() => return,
+                _ => {}
             }
+        }
 
-            self.ppu.borrow_mut().draw(&gl);
-            window.gl_swap_window();
+        if let Some(watch) = &mut watch {
+            watch.poll(nes);
+        }
 
-            for e in events.poll_iter() {
-                match e {
-                    Event::Window {
-                        timestamp: _,
-                        window_id: _,
-                        win_event,
-                    } => match win_event {
-                        WindowEvent::Resized(width, height) => {
-                            unsafe {
-                                gl.viewport(0, 0, width, height);
-                            };
-                        }
-                        _ => {}
-                    },
-                    Event::Quit { .. } => process::exit(0),
-                    _ => {}
+        let elapsed_time = start.elapsed();
+
+        let current_speed = match background {
+            Unfocused::Throttle => SPEEDS[speed].min(BACKGROUND_SPEED),
+            _ => SPEEDS[speed],
+        };
+        thread::sleep(
+            frame_time
+                .div_f32(current_speed)
+                .saturating_sub(elapsed_time),
+        );
+    }
+}
+
+// Sets player 1's buttons for the next frame as the script says. The frame
+// after its last step releases them, from then on the controller is left
+// to the player.
+fn play_script(nes: &mut Machine, script: &InputScript) {
+    let frame = nes.frame();
+    if script.last_frame().is_some_and(|last| frame <= last + 1) {
+        for button in Button::ALL {
+            nes.set_button(0, button, script.is_held(frame, button));
+        }
+    }
+}
+
+// Runs without a window or sound, as fast as possible, until the frame
+// limit or the machine halts. The script, if any, plays player 1.
+fn run_headless(
+    nes: &mut Machine,
+    frames: Option<usize>,
+    dump: Option<FrameDump>,
+    script: Option<&InputScript>,
+) {
+    while frames.is_none_or(|frames| nes.frame() < frames) && !nes.is_halted() {
+        if let Some(script) = script {
+            play_script(nes, script);
+        }
+        nes.run_frame();
+        // Nobody plays them, don't let them pile up
+        nes.take_audio_samples();
+
+        if let Some(dump) = &dump {
+            if let Err(e) = dump.frame(nes.frame(), &nes.framebuffer()) {
+                eprintln!("[ERROR]: could not dump frame {}: {e}", nes.frame());
+                return;
+            }
+        }
+    }
+}
+
+// Frame rate of the NTSC NES, to compare the benchmark speed against
+const NES_FPS: f64 = 60.0988;
+
+// Runs the game headlessly as fast as possible for `seconds` and reports how
+// fast it went, to measure the impact of performance changes
+fn run_bench(nes: &mut Machine, seconds: f64) {
+    let start_frame = nes.frame();
+    let start_counters = nes.counters();
+    let mut samples = 0;
+
+    let start = time::Instant::now();
+    while start.elapsed().as_secs_f64() < seconds && !nes.is_halted() {
+        nes.run_frame();
+        samples += nes.take_audio_samples().len();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let frames = (nes.frame() - start_frame) as f64;
+    let counters = nes.counters();
+    let per_second = |count: u64| count as f64 / elapsed / 1e6;
+    let instructions = counters.instructions - start_counters.instructions;
+    let cpu_cycles = counters.cpu_cycles - start_counters.cpu_cycles;
+    let ppu_dots = counters.ppu_dots - start_counters.ppu_dots;
+
+    println!(
+        "{frames} frames in {elapsed:.2}s: {:.1} frames/s, {:.2}x real time",
+        frames / elapsed,
+        frames / elapsed / NES_FPS
+    );
+    println!(
+        "cpu: {instructions} instructions ({:.2}M/s), {cpu_cycles} cycles ({:.2}M/s)",
+        per_second(instructions),
+        per_second(cpu_cycles)
+    );
+    println!("ppu: {ppu_dots} dots ({:.2}M/s)", per_second(ppu_dots));
+    println!(
+        "apu: {samples} samples ({:.2}M/s)",
+        per_second(samples as u64)
+    );
+}
+
+// Reads an iNES file and applies the patches to it
+fn load_rom(file_name: &str, patch_files: &[String]) -> Result<INes, String> {
+    let mut rom = fs::read(file_name).map_err(|e| format!("could not read {file_name}: {e}"))?;
+
+    // Patches are applied in memory, in the order they were given, and the
+    // ROM file itself is never modified.
+    let original_crc = patch::rom_crc32(&rom);
+    for patch_file in patch_files {
+        let patch =
+            fs::read(patch_file).map_err(|e| format!("could not read patch {patch_file}: {e}"))?;
+        rom = patch::apply(&rom, &patch)
+            .map_err(|e| format!("could not apply patch {patch_file}: {e}"))?;
+        eprintln!("applied patch {patch_file}");
+    }
+
+    if !patch_files.is_empty() {
+        eprintln!("original ROM CRC32: {original_crc:08X}");
+        eprintln!("patched ROM CRC32:  {:08X}", patch::rom_crc32(&rom));
+    }
+
+    if !rom.starts_with(b"NES\x1a") {
+        return Err(format!("{file_name} is not an iNES file"));
+    }
+    let rom = INes::from_bytes(&rom);
+
+    let unsupported = match rom.console_type {
+        ConsoleType::Nes => None,
+        ConsoleType::VsSystem => Some("a Vs. System arcade board".to_string()),
+        ConsoleType::PlayChoice10 => Some("a PlayChoice-10 arcade board".to_string()),
+        ConsoleType::Extended(kind) => Some(format!("an extended console type ({kind:#x})")),
+    };
+    if let Some(console) = unsupported {
+        return Err(format!(
+            "{file_name} is a dump of {console}, only NES/Famicom games are supported"
+        ));
+    }
+
+    Ok(rom)
+}
+
+// Shakes player 1's gamepad when one of the watched bytes goes down, which
+// for a health or lives counter means the player got hit
+struct Rumble {
+    // Address and value at the end of the last frame
+    watched: Vec<(u16, Option<u8>)>,
+}
+
+impl Rumble {
+    const DURATION_MS: u32 = 200;
+
+    fn new(addrs: Vec<u16>) -> Rumble {
+        Rumble {
+            watched: addrs.into_iter().map(|addr| (addr, None)).collect(),
+        }
+    }
+
+    fn update(&mut self, nes: &Machine, gamepad: Option<&mut GameController>) {
+        let mut decreased = false;
+        for (addr, last) in &mut self.watched {
+            let value = nes.peek(*addr);
+            decreased |= matches!((*last, value), (Some(last), Some(value)) if value < last);
+            *last = value;
+        }
+
+        if let (true, Some(gamepad)) = (decreased, gamepad) {
+            // Not every gamepad can rumble, there is nothing to do about it
+            gamepad.set_rumble(0xffff, 0xffff, Rumble::DURATION_MS).ok();
+        }
+    }
+}
+
+// Reloads the ROM when its file changes, so homebrew developers can see
+// every build right away
+struct RomWatch {
+    file_name: String,
+    patch_files: Vec<String>,
+    keep_sram: bool,
+
+    modified: Option<time::SystemTime>,
+    // Build tools may still be writing a file that just changed, so it is
+    // only reloaded once it stays the same for a whole poll interval.
+    pending: Option<time::SystemTime>,
+    last_poll: time::Instant,
+}
+
+impl RomWatch {
+    const POLL_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
+    fn new(file_name: String, patch_files: Vec<String>, keep_sram: bool) -> RomWatch {
+        let modified = RomWatch::modified(&file_name);
+        RomWatch {
+            file_name,
+            patch_files,
+            keep_sram,
+            modified,
+            pending: None,
+            last_poll: time::Instant::now(),
+        }
+    }
+
+    fn modified(file_name: &str) -> Option<time::SystemTime> {
+        fs::metadata(file_name).and_then(|m| m.modified()).ok()
+    }
+
+    // Restarts the machine with the new ROM if the file changed
+    fn poll(&mut self, nes: &mut Machine) {
+        if self.last_poll.elapsed() < RomWatch::POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = time::Instant::now();
+
+        let modified = RomWatch::modified(&self.file_name);
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        if modified != self.pending {
+            self.pending = modified;
+            return;
+        }
+        self.modified = modified;
+        self.pending = None;
+
+        match load_rom(&self.file_name, &self.patch_files).and_then(|rom| nes.insert_cartridge(rom))
+        {
+            Ok(sram) => {
+                if self.keep_sram {
+                    nes.load_sram(sram);
                 }
+                eprintln!("reloaded {}", self.file_name);
             }
+            Err(e) => eprintln!("[WARN]: could not reload the ROM: {e}"),
+        }
+    }
+}
+
+// Parses `<addr>=<value>` with both numbers in hexadecimal, e.g. `075a=09`.
+fn parse_freeze(spec: &str) -> Option<(u16, u8)> {
+    let (addr, value) = spec.split_once('=')?;
+    let addr = u16::from_str_radix(addr.trim_start_matches('$'), 16).ok()?;
+    let value = u8::from_str_radix(value.trim_start_matches('$'), 16).ok()?;
+    Some((addr, value))
+}
+
+// Runs a ROM on the generic 6502 single board computer, see `System::sbc`
+fn run_sbc(file_name: &str) {
+    let rom = fs::read(file_name).unwrap_or_else(|e| {
+        eprintln!("[ERROR]: could not read {file_name}: {e}");
+        process::exit(1);
+    });
+    let mut system = System::sbc(rom).unwrap_or_else(|e| {
+        eprintln!("[ERROR]: {e}");
+        process::exit(1);
+    });
+
+    loop {
+        system.step();
+    }
+}
 
-            let elapsed_time = start.elapsed();
+fn load_debug_info(file_name: &str) -> DebugInfo {
+    DebugInfo::load(Path::new(file_name)).unwrap_or_else(|e| {
+        eprintln!("[ERROR]: could not read debug info {file_name}: {e}");
+        process::exit(1);
+    })
+}
+
+// Loads a raw binary into the 6502 machine language monitor
+fn run_monitor(file_name: &str, load_addr: Option<&str>, dbg_file: Option<&str>) {
+    let load_addr = match load_addr {
+        Some(addr) => u16::from_str_radix(addr.trim_start_matches('$'), 16).unwrap_or_else(|_| {
+            eprintln!("[ERROR]: invalid load address {addr}");
+            process::exit(1);
+        }),
+        None => monitor::DEFAULT_LOAD_ADDR,
+    };
+    let program = fs::read(file_name).unwrap_or_else(|e| {
+        eprintln!("[ERROR]: could not read {file_name}: {e}");
+        process::exit(1);
+    });
+
+    let mut monitor = Monitor::new(&program, load_addr);
+    if let Some(dbg_file) = dbg_file {
+        monitor.set_debug_info(load_debug_info(dbg_file));
+    }
+    monitor.run();
+}
 
-            thread::sleep(frame_time.saturating_sub(elapsed_time));
+fn list_audio_devices() {
+    let names = sdl2::init().and_then(|sdl| audio::AudioOutput::device_names(&sdl));
+    match names {
+        Ok(names) if names.is_empty() => eprintln!("no audio devices found"),
+        Ok(names) => {
+            for name in names {
+                println!("{name}");
+            }
+        }
+        Err(e) => {
+            eprintln!("[ERROR]: could not list the audio devices: {e}");
+            process::exit(1);
         }
     }
 }
 
+fn unix_time() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |t| t.as_secs())
+}
+
+fn load_stats() -> Option<(PathBuf, Stats)> {
+    let path = Stats::path()?;
+    match Stats::load(&path) {
+        Ok(stats) => Some((path, stats)),
+        Err(e) => {
+            eprintln!(
+                "[WARN]: could not load playtime stats {}: {e}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+fn list_stats() {
+    let Some((_, stats)) = load_stats() else {
+        return;
+    };
+    let now = unix_time();
+    if stats.games().is_empty() {
+        eprintln!("no games played yet");
+    }
+    for game in stats.games() {
+        println!(
+            "{:<40} {:>10} {:>4} launches  last played {}",
+            game.name,
+            stats::format_duration(game.playtime),
+            game.launches,
+            stats::format_ago(game.last_played, now)
+        );
+    }
+}
+
+// Runs the bundled tests, then the blargg style test ROMs given, and prints
+// how each one went
+fn run_selftest(rom_files: &[String]) {
+    // blargg's tests take up to a few seconds each
+    const ROM_MAX_FRAMES: usize = 60 * 60;
+
+    // Failures are followed by the message the test left, if any
+    let mut failed = false;
+    let mut report = |name: &str, outcome: Result<selftest::Outcome, String>, message: String| {
+        failed |= outcome != Ok(selftest::Outcome::Passed);
+        let (result, message) = match outcome {
+            Ok(selftest::Outcome::Passed) => ("pass".to_string(), String::new()),
+            Ok(outcome) => (outcome.to_string(), message),
+            Err(e) => ("FAIL (could not load)".to_string(), e),
+        };
+        println!("{name:<40} {result}");
+        for line in message.lines() {
+            println!("    {line}");
+        }
+    };
+
+    for test in selftest::tests() {
+        let mut nes = Machine::new(INes::from_bytes(&test.rom())).unwrap();
+        let outcome = selftest::run(&mut nes, selftest::SelfTest::MAX_FRAMES);
+        report(test.name, Ok(outcome), String::new());
+    }
+
+    for rom_file in rom_files {
+        match load_rom(rom_file, &[]).and_then(Machine::new) {
+            Ok(mut nes) => {
+                let outcome = selftest::run(&mut nes, ROM_MAX_FRAMES);
+                report(rom_file, Ok(outcome), selftest::message(&nes));
+            }
+            Err(e) => report(rom_file, Err(e), String::new()),
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
 fn main() {
     let mut args = env::args();
     let command = args.next().unwrap();
-    let file_name = args.next().unwrap_or_else(|| {
-        eprintln!("usage: {} <file_name>", command);
+
+    if env::args().nth(1).as_deref() == Some("audio-devices") {
+        return list_audio_devices();
+    }
+    if env::args().nth(1).as_deref() == Some("stats") {
+        return list_stats();
+    }
+    if env::args().nth(1).as_deref() == Some("selftest") {
+        let rom_files: Vec<String> = env::args().skip(2).collect();
+        return run_selftest(&rom_files);
+    }
+
+    if env::args().nth(1).as_deref() == Some("sbc") {
+        match env::args().nth(2) {
+            Some(file_name) if env::args().len() == 3 => return run_sbc(&file_name),
+            _ => {
+                eprintln!("usage: {command} sbc <rom_file>");
+                process::exit(1);
+            }
+        }
+    }
+    if env::args().nth(1).as_deref() == Some("monitor") {
+        let mut args: Vec<String> = env::args().skip(2).collect();
+        let dbg_file = match args.iter().position(|arg| arg == "--dbgfile") {
+            Some(i) if i + 1 < args.len() => args.drain(i..i + 2).nth(1),
+            _ => None,
+        };
+        match args.as_slice() {
+            [file_name] => return run_monitor(file_name, None, dbg_file.as_deref()),
+            [file_name, load_addr] if !load_addr.starts_with('-') => {
+                return run_monitor(file_name, Some(load_addr), dbg_file.as_deref())
+            }
+            _ => {
+                eprintln!(
+                    "usage: {command} monitor [--dbgfile <dbg_file>] <program_file> [<load_addr>]"
+                );
+                process::exit(1);
+            }
+        }
+    }
+    let usage = || -> ! {
+        eprintln!(
+            "usage: {} [--sram <save_file>] [--reference-trace <trace_log>] \
+             [--vram-watch <addr>[-<addr>][:r|w|rw]]... [--freeze <addr>=<value>]... \
+             [--patch <ips_or_bps>]... [--dbgfile <dbg_file>] [--heatmap] \
+             [--coverage <report_file>] [--unmapped ignore|once|all|break] \
+             [--bus-stats] [--log-oam-dma] [--no-fast-path] \
+             [--watch] [--watch-keep-sram] [--blend-frames] [--sprite-outline] \
+             [--audio-device <name>] [--rumble <addr>]... [--expansion arkanoid] \
+             [--colorblind protanopia|deuteranopia|tritanopia|none] [--filter <name>] \
+             [--headless] [--frames <count>] [--dump-frames <dir>] [--format png|raw] \
+             [--dump-every <n>] [--input-script <file>] [--seek-frame <frame>] [--bench] \
+             [--seconds <n>] <file_name>",
+            command
+        );
+        process::exit(1);
+    };
+
+    let mut file_name = None;
+    let mut sram_file = None;
+    let mut reference_file = None;
+    let mut vram_watchpoints = vec![];
+    let mut frozen = vec![];
+    let mut patch_files = vec![];
+    let mut dbg_file = None;
+    let mut heatmap = false;
+    let mut coverage_file = None;
+    let mut unmapped_policy = None;
+    let mut bus_stats = false;
+    let mut log_oam_dma = false;
+    let mut fast_path = true;
+    let mut watch = false;
+    let mut watch_keep_sram = false;
+    let mut audio_device = None;
+    let mut rumble_addrs = vec![];
+    let mut colorblind = None;
+    let mut video_filter = None;
+    let mut headless = false;
+    let mut frames = None;
+    let mut seek_frame = None;
+    let mut bench = false;
+    let mut bench_seconds = 10.0;
+    let mut dump_dir = None;
+    let mut dump_format = DumpFormat::default();
+    let mut dump_every = 1;
+    let mut script_file = None;
+    let mut blend_frames = false;
+    let mut sprite_outline = false;
+    let mut paddle = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sram" => sram_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--reference-trace" => reference_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--vram-watch" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                vram_watchpoints.push(Watchpoint::parse(&spec).unwrap_or_else(|| usage()));
+            }
+            "--heatmap" => heatmap = true,
+            "--bus-stats" => bus_stats = true,
+            "--log-oam-dma" => log_oam_dma = true,
+            "--no-fast-path" => fast_path = false,
+            "--watch" => watch = true,
+            "--blend-frames" => blend_frames = true,
+            "--sprite-outline" => sprite_outline = true,
+            "--watch-keep-sram" => (watch, watch_keep_sram) = (true, true),
+            "--unmapped" => {
+                let policy = args.next().unwrap_or_else(|| usage());
+                unmapped_policy = Some(policy.parse().unwrap_or_else(|e| {
+                    eprintln!("[ERROR]: {e}");
+                    usage()
+                }));
+            }
+            "--headless" => headless = true,
+            "--frames" => {
+                let count = args.next().unwrap_or_else(|| usage());
+                frames = Some(count.parse().unwrap_or_else(|_| usage()));
+            }
+            "--bench" => bench = true,
+            "--seconds" => {
+                let seconds = args.next().unwrap_or_else(|| usage());
+                bench_seconds = seconds.parse().unwrap_or_else(|_| usage());
+            }
+            "--seek-frame" => {
+                let frame = args.next().unwrap_or_else(|| usage());
+                seek_frame = Some(frame.parse().unwrap_or_else(|_| usage()));
+            }
+            "--dump-frames" => {
+                dump_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage())));
+                headless = true;
+            }
+            "--format" => {
+                let format = args.next().unwrap_or_else(|| usage());
+                dump_format = format.parse().unwrap_or_else(|e| {
+                    eprintln!("[ERROR]: {e}");
+                    usage()
+                });
+            }
+            "--dump-every" => {
+                let every = args.next().unwrap_or_else(|| usage());
+                dump_every = every.parse().unwrap_or_else(|_| usage());
+            }
+            "--input-script" => {
+                script_file = Some(PathBuf::from(args.next().unwrap_or_else(|| usage())));
+            }
+            "--coverage" => coverage_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--patch" => patch_files.push(args.next().unwrap_or_else(|| usage())),
+            "--dbgfile" => dbg_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--expansion" => match args.next().as_deref() {
+                Some("arkanoid") => paddle = Some(Rc::new(RefCell::new(ArkanoidPaddle::new()))),
+                _ => usage(),
+            },
+            "--audio-device" => audio_device = Some(args.next().unwrap_or_else(|| usage())),
+            "--colorblind" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                colorblind = match name.as_str() {
+                    "none" => Some(None),
+                    _ => Some(Some(
+                        gfx::ColorBlindness::from_name(&name).unwrap_or_else(|| usage()),
+                    )),
+                };
+            }
+            "--filter" => video_filter = Some(args.next().unwrap_or_else(|| usage())),
+            "--rumble" => {
+                let addr = args.next().unwrap_or_else(|| usage());
+                let addr = u16::from_str_radix(addr.trim_start_matches('$'), 16)
+                    .unwrap_or_else(|_| usage());
+                rumble_addrs.push(addr);
+            }
+            "--freeze" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                frozen.push(parse_freeze(&spec).unwrap_or_else(|| usage()));
+            }
+            _ if file_name.is_none() => file_name = Some(arg),
+            _ => usage(),
+        }
+    }
+    let file_name = file_name.unwrap_or_else(|| usage());
+
+    if patch_files.is_empty() {
+        patch_files.extend(patch::find_for(&file_name));
+    }
+
+    let rom = load_rom(&file_name, &patch_files).unwrap_or_else(|e| {
+        eprintln!("[ERROR]: {e}");
+        process::exit(1);
+    });
+
+    let mut config = match Config::path() {
+        Some(path) if path.exists() => Config::load(&path).unwrap_or_else(|e| {
+            eprintln!("[WARN]: could not load config {}: {e}", path.display());
+            Config::fallback()
+        }),
+        _ => Config::default(),
+    };
+
+    let mut nes = Machine::new(rom).unwrap_or_else(|e| {
+        eprintln!("[ERROR]: {file_name}: {e}");
+        process::exit(1);
+    });
+
+    // Battery-backed games save next to the ROM unless told otherwise
+    let sram_file = sram_file.or_else(|| nes.has_battery().then(|| sram::default_path(&file_name)));
+    if let Some(sram_file) = &sram_file {
+        match sram::import(sram_file) {
+            Ok(sram) => nes.load_sram(sram),
+            // There is no save until the game makes one
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("[WARN]: could not read save file {sram_file}: {e}"),
+        }
+    }
+
+    if let Some(paddle) = &paddle {
+        nes.plug_expansion(Some(paddle.clone()));
+    }
+
+    if let Some(reference_file) = &reference_file {
+        let reference = ReferenceTrace::load(reference_file).unwrap_or_else(|e| {
+            eprintln!("[ERROR]: could not read trace {reference_file}: {e}");
+            process::exit(1);
+        });
+        nes.follow_reference(reference);
+    }
+
+    if let Some(dbg_file) = &dbg_file {
+        nes.set_debug_info(load_debug_info(dbg_file));
+    }
+
+    for watchpoint in vram_watchpoints {
+        nes.watch_vram(watchpoint);
+    }
+
+    for (addr, value) in frozen {
+        eprintln!("freezing {addr:#06x} to {value:#04x}");
+        nes.freeze(addr, value);
+    }
+
+    nes.set_unmapped_policy(unmapped_policy.unwrap_or(config.unmapped));
+
+    if !fast_path {
+        nes.set_fast_path(false);
+    }
+
+    if heatmap {
+        nes.enable_heatmap();
+    }
+
+    if bus_stats {
+        nes.enable_bus_stats();
+    }
+
+    if coverage_file.is_some() {
+        nes.enable_coverage();
+    }
+
+    // The command line wins over the config file
+    let audio_device = audio_device.or_else(|| config.audio_device.clone());
+    let rom_crc = fs::read(&file_name).map_or(0, |rom| patch::rom_crc32(&rom));
+    let colorblind = colorblind.unwrap_or_else(|| config.colorblind(rom_crc));
+    let video_filter = video_filter.unwrap_or_else(|| config.filter.clone());
+    let Some(video_filter) = filter::new(&video_filter) else {
+        let names: Vec<&str> = filter::names().collect();
+        eprintln!(
+            "[ERROR]: unknown video filter {video_filter}, expected one of {}",
+            names.join(", ")
+        );
         process::exit(1);
+    };
+    let script = script_file.map(|file| {
+        InputScript::load(&file).unwrap_or_else(|e| {
+            eprintln!("[ERROR]: {e}");
+            process::exit(1);
+        })
+    });
+    let options = RunOptions {
+        audio_device,
+        log_oam_dma,
+        blend_frames,
+        sprite_outline,
+        color_filter: colorblind.map(gfx::ColorFilter::new),
+        video_filter,
+        save_file: sram_file.clone(),
+        paddle,
+        script,
+    };
+
+    let mut stats = load_stats();
+    if let Some(game) = stats.as_ref().and_then(|(_, stats)| stats.get(rom_crc)) {
+        let sessions = if game.launches == 1 {
+            "session"
+        } else {
+            "sessions"
+        };
+        eprintln!(
+            "played for {} over {} {sessions}, last {}",
+            stats::format_duration(game.playtime),
+            game.launches,
+            stats::format_ago(game.last_played, unix_time())
+        );
+    }
+    let watch =
+        watch.then(|| RomWatch::new(file_name.clone(), patch_files.clone(), watch_keep_sram));
+    let dump = dump_dir.map(|dir| {
+        FrameDump::new(dir.clone(), dump_format, dump_every).unwrap_or_else(|e| {
+            eprintln!("[ERROR]: could not create {}: {e}", dir.display());
+            process::exit(1);
+        })
     });
 
-    let rom = INes::parse(&file_name);
+    // How long the game was played for, only runs in the window count
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        if bench {
+            run_bench(&mut nes, bench_seconds);
+            None
+        } else if headless {
+            run_headless(&mut nes, frames, dump, options.script.as_ref());
+            None
+        } else {
+            // The emulation is deterministic, so running the frames
+            // before the window opens lands on the same frame every time
+            if let Some(frame) = seek_frame {
+                let start = time::Instant::now();
+                run_headless(&mut nes, Some(frame), None, options.script.as_ref());
+                eprintln!(
+                    "reached frame {} in {:.1}s",
+                    nes.frame(),
+                    start.elapsed().as_secs_f32()
+                );
+            }
+            let session_start = time::Instant::now();
+            run(
+                &mut nes,
+                &mut config,
+                options,
+                watch,
+                Rumble::new(rumble_addrs),
+            );
+            Some(session_start.elapsed())
+        }
+    }));
+    let playtime = result.unwrap_or_else(|payload| {
+        let reason = payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| payload.downcast_ref::<&str>().copied())
+            .unwrap_or("unknown panic");
+        let context = CrashContext {
+            rom_file: &file_name,
+            patch_files: &patch_files,
+        };
+        match crash::write_bundle(&mut nes, reason, &context) {
+            Ok(dir) => eprintln!(
+                "[ERROR]: the emulator crashed, please attach {} to the bug report",
+                dir.display()
+            ),
+            Err(e) => eprintln!("[ERROR]: could not write the crash report: {e}"),
+        }
+        // The progress of the game outlives the emulator
+        if let Some(sram_file) = &sram_file {
+            export_sram(&nes, sram_file);
+        }
+        process::exit(101);
+    });
 
-    let mut ram = Ram::new();
-    let prg_start = ((1 << 16) - rom.program.len()).try_into().unwrap();
-    ram.load_vec_at(rom.program, prg_start);
+    if let (Some((path, stats)), Some(playtime)) = (&mut stats, playtime) {
+        let name = Path::new(&file_name)
+            .file_name()
+            .map_or(file_name.clone(), |name| {
+                name.to_string_lossy().into_owned()
+            });
+        stats.record(rom_crc, &name, playtime, unix_time());
+        if let Err(e) = stats.save(path) {
+            eprintln!(
+                "[WARN]: could not save playtime stats {}: {e}",
+                path.display()
+            );
+        }
+    }
 
-    let mut ppu_mem = Ram::new();
-    if let Some(chr_rom) = rom.chr_rom {
-        ppu_mem.load_vec_at(chr_rom, 0);
+    if bus_stats {
+        let frames = nes.frame().max(1) as f64;
+        eprintln!("bus accesses over {} frames:", nes.frame());
+        for (device, count) in nes.bus_stats() {
+            eprintln!(
+                "  {device}: {} reads ({:.1}/frame), {} writes ({:.1}/frame)",
+                count.reads,
+                count.reads as f64 / frames,
+                count.writes,
+                count.writes as f64 / frames
+            );
+        }
     }
 
-    let mut nes = Machine::new(ram, ppu_mem);
+    if let (Some(coverage_file), Some(coverage)) = (&coverage_file, nes.coverage()) {
+        eprintln!("coverage: {:.2}% of PRG executed", coverage.percentage());
+        if let Err(e) = fs::write(coverage_file, coverage.report()) {
+            eprintln!("[ERROR]: could not write coverage report {coverage_file}: {e}");
+        }
+    }
 
-    nes.power_on();
+    if let Some(sram_file) = &sram_file {
+        export_sram(&nes, sram_file);
+    }
 }
@@ -0,0 +1,136 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::asc::MemoryMapped;
+use crate::ines::INes;
+use crate::irq::IrqLine;
+use crate::ppu_core::Mirroring;
+
+const PRG_ROM_START: u16 = 0x8000;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+pub type Cartridge = Rc<RefCell<dyn Mapper>>;
+
+// The hardware on the cartridge board. It sits on both buses: the CPU side
+// gets $4020-$FFFF, where the PRG ROM and the mapper registers live, and the
+// PPU side the pattern tables at $0000-$1FFF. The board also wires the
+// nametables to the console VRAM, which is what the mirroring is, and can
+// pull the /IRQ line with `IrqSource::Mapper` on the line it was built with.
+// Based on https://www.nesdev.org/wiki/Mapper
+pub trait Mapper: fmt::Debug {
+    fn cpu_read(&mut self, addr: u16) -> u8;
+
+    fn cpu_write(&mut self, addr: u16, value: u8);
+
+    fn ppu_read(&mut self, addr: u16) -> u8;
+
+    fn ppu_write(&mut self, addr: u16, value: u8);
+
+    // Asked on every nametable access, so boards like AxROM, MMC1 and MMC3
+    // switch the mirroring mid-game, even mid-frame, by returning the mode
+    // their last register write selected.
+    fn mirroring(&self) -> Mirroring;
+}
+
+// Builds the board of the iNES mapper number of the ROM. The IRQ line goes
+// to the boards that can raise interrupts, none of the supported ones yet.
+pub fn new(rom: INes, _irq: IrqLine) -> Result<Cartridge, String> {
+    match rom.mapper {
+        0 => Ok(Rc::new(RefCell::new(Nrom::new(rom)))),
+        mapper => Err(format!("mapper {mapper} is not supported")),
+    }
+}
+
+// Puts a cartridge on the CPU bus
+pub struct CartridgeSlot {
+    cartridge: Cartridge,
+}
+
+impl CartridgeSlot {
+    pub fn new(cartridge: Cartridge) -> CartridgeSlot {
+        CartridgeSlot { cartridge }
+    }
+}
+
+impl MemoryMapped for CartridgeSlot {
+    fn write(&mut self, addr: u16, value: u8) {
+        self.cartridge.borrow_mut().cpu_write(addr, value);
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.cartridge.borrow_mut().cpu_read(addr)
+    }
+}
+
+// Mapper 0, no bank switching: 16KB or 32KB of PRG ROM at $8000, where 16KB
+// ones show up twice, and 8KB of CHR, RAM when the cartridge has no CHR
+// ROM. Writes to ROM are ignored, with a warning the first time an address
+// is written as they most likely come from a bug.
+// Based on https://www.nesdev.org/wiki/NROM
+#[derive(Debug)]
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+
+    // Addresses whose ROM writes were already reported
+    prg_writes: HashSet<u16>,
+    chr_writes: HashSet<u16>,
+}
+
+impl Nrom {
+    pub fn new(rom: INes) -> Nrom {
+        let (chr, chr_is_ram) = match rom.chr_rom {
+            Some(chr_rom) if !chr_rom.is_empty() => (chr_rom, false),
+            _ => (vec![0; CHR_RAM_SIZE], true),
+        };
+
+        Nrom {
+            prg_rom: rom.program,
+            chr,
+            chr_is_ram,
+            mirroring: rom.mirroring,
+
+            prg_writes: HashSet::new(),
+            chr_writes: HashSet::new(),
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            PRG_ROM_START..=0xffff if !self.prg_rom.is_empty() => {
+                self.prg_rom[(addr - PRG_ROM_START) as usize % self.prg_rom.len()]
+            }
+            // Nothing on the board answers below $8000
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if addr >= PRG_ROM_START && self.prg_writes.insert(addr) {
+            eprintln!("[WARN]: ignored write of ${value:02X} to PRG ROM at ${addr:04X}");
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = value;
+        } else if self.chr_writes.insert(addr) {
+            eprintln!("[WARN]: ignored write of ${value:02X} to CHR ROM at ${addr:04X}");
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
@@ -0,0 +1,226 @@
+use std::io::{self, BufRead, Write};
+
+use crate::cpu;
+use crate::dbgfile::DebugInfo;
+use crate::system::System;
+
+// Where programs are loaded when no address is given
+pub const DEFAULT_LOAD_ADDR: u16 = 0x0800;
+
+// Lines shown by a single L command
+const LIST_LINES: usize = 20;
+
+const BRK: u8 = 0x00;
+
+const HELP: &str = "\
+commands, with addresses and values in hexadecimal:
+  0800           examine a byte
+  0800.081F      examine a range
+  0800: A9 01    deposit bytes
+  0800G          go, running until a BRK
+  0800L          list (disassemble) instructions
+  R              show the registers
+  Q              quit";
+
+enum Command {
+    Examine(u16, u16),
+    Deposit(u16, Vec<u8>),
+    Go(u16),
+    List(u16),
+    Registers,
+    Help,
+    Quit,
+}
+
+// A machine language monitor in the spirit of the Apple II one, driving a
+// 6502 with 64KB of RAM and nothing else.
+pub struct Monitor {
+    system: System,
+    debug_info: Option<DebugInfo>,
+}
+
+impl Monitor {
+    pub fn new(program: &[u8], load_addr: u16) -> Monitor {
+        let mut system = System::builder()
+            .ram(0x0000..=0xffff)
+            .decimal_mode(true)
+            .build();
+
+        for (i, &byte) in program.iter().enumerate() {
+            system.asc.poke(load_addr.wrapping_add(i as u16), byte);
+        }
+        system.cpu.pc = load_addr;
+
+        Monitor {
+            system,
+            debug_info: None,
+        }
+    }
+
+    // Shows the source lines of the program in listings and after G
+    pub fn set_debug_info(&mut self, debug_info: DebugInfo) {
+        self.debug_info = Some(debug_info);
+    }
+
+    // Reads commands from stdin until Q or the end of the input
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut stdout = io::stdout();
+        let mut lines = stdin.lock().lines();
+
+        loop {
+            print!("*");
+            stdout.flush().ok();
+
+            let Some(Ok(line)) = lines.next() else {
+                println!();
+                return;
+            };
+            match parse(&line) {
+                Ok(Some(Command::Quit)) => return,
+                Ok(Some(command)) => self.execute(command, &mut stdout),
+                Ok(None) => {}
+                Err(e) => println!("error: {e}"),
+            }
+        }
+    }
+
+    fn execute(&mut self, command: Command, out: &mut impl Write) {
+        let asc = &mut self.system.asc;
+        match command {
+            Command::Examine(start, end) => {
+                let mut addr = start;
+                loop {
+                    if addr == start || addr % 8 == 0 {
+                        if addr != start {
+                            writeln!(out).ok();
+                        }
+                        write!(out, "{addr:04X}-").ok();
+                    }
+                    write!(out, " {:02X}", asc.peek(addr).unwrap_or(0)).ok();
+
+                    if addr >= end {
+                        break;
+                    }
+                    addr += 1;
+                }
+                writeln!(out).ok();
+            }
+            Command::Deposit(start, bytes) => {
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    asc.poke(start.wrapping_add(i as u16), byte);
+                }
+            }
+            Command::Go(addr) => {
+                self.system.cpu.pc = addr;
+                self.go(out);
+            }
+            Command::List(addr) => {
+                let mut pc = addr;
+                for _ in 0..LIST_LINES {
+                    let (text, len) = cpu::disassemble(asc, pc);
+                    let bytes: Vec<String> = (0..len)
+                        .map(|i| format!("{:02X}", asc.peek(pc.wrapping_add(i)).unwrap_or(0)))
+                        .collect();
+                    let mut line = format!("{pc:04X}-   {:<8}   {text}", bytes.join(" "));
+                    if let Some(source) = self.debug_info.as_ref().and_then(|d| d.source_line(pc)) {
+                        line = format!("{line:<32} ; {source}");
+                    }
+                    writeln!(out, "{line}").ok();
+                    pc = pc.wrapping_add(len);
+                }
+            }
+            Command::Registers => self.show_registers(out),
+            Command::Help => {
+                writeln!(out, "{HELP}").ok();
+            }
+            Command::Quit => {}
+        }
+    }
+
+    // Runs from PC until a BRK, which is left unexecuted so the program can
+    // be resumed past it, or an opcode the CPU doesn't know.
+    fn go(&mut self, out: &mut impl Write) {
+        loop {
+            let pc = self.system.cpu.pc;
+            let opcode = self.system.asc.peek(pc).unwrap_or(BRK);
+            if opcode == BRK {
+                break;
+            }
+            if !cpu::is_known_opcode(opcode) {
+                writeln!(out, "unknown opcode {opcode:02X} at {pc:04X}").ok();
+                break;
+            }
+            self.system.step();
+        }
+        self.show_registers(out);
+    }
+
+    fn show_registers(&self, out: &mut impl Write) {
+        let regs = self.system.cpu.registers();
+        writeln!(
+            out,
+            "{:04X}-   A={:02X} X={:02X} Y={:02X} P={:02X} S={:02X}",
+            regs.pc, regs.a, regs.x, regs.y, regs.status, regs.sp
+        )
+        .ok();
+
+        if let Some(line) = self
+            .debug_info
+            .as_ref()
+            .and_then(|d| d.source_line(regs.pc))
+        {
+            writeln!(out, "         {line}").ok();
+        }
+    }
+}
+
+fn parse(line: &str) -> Result<Option<Command>, String> {
+    let line = line.trim().to_ascii_uppercase();
+    let hex_end = line
+        .find(|c: char| !c.is_ascii_hexdigit())
+        .unwrap_or(line.len());
+    let (addr, rest) = line.split_at(hex_end);
+    let rest = rest.trim();
+
+    if addr.is_empty() {
+        return match rest {
+            "" => Ok(None),
+            "R" => Ok(Some(Command::Registers)),
+            "?" => Ok(Some(Command::Help)),
+            "Q" => Ok(Some(Command::Quit)),
+            _ => Err(format!("unknown command {rest}, ? for help")),
+        };
+    }
+    let addr = parse_hex(addr)?;
+
+    let command = match rest {
+        "" => Command::Examine(addr, addr),
+        "G" => Command::Go(addr),
+        "L" => Command::List(addr),
+        _ => {
+            if let Some(end) = rest.strip_prefix('.') {
+                let end = parse_hex(end.trim())?;
+                if end < addr {
+                    return Err(format!("{end:04X} is before {addr:04X}"));
+                }
+                Command::Examine(addr, end)
+            } else if let Some(values) = rest.strip_prefix(':') {
+                let bytes = values
+                    .split_whitespace()
+                    .map(|value| {
+                        u8::from_str_radix(value, 16).map_err(|_| format!("invalid byte {value}"))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Command::Deposit(addr, bytes)
+            } else {
+                return Err(format!("unknown command {rest}, ? for help"));
+            }
+        }
+    };
+    Ok(Some(command))
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text, 16).map_err(|_| format!("invalid address {text}"))
+}
@@ -0,0 +1,210 @@
+use std::path::Path;
+
+// Applies an IPS or BPS patch, picking the format from the patch header.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else {
+        Err("unknown patch format".to_string())
+    }
+}
+
+// Looks for `<rom>.ips` or `<rom>.bps` next to the ROM file.
+pub fn find_for(rom_path: &str) -> Option<String> {
+    ["ips", "bps"]
+        .iter()
+        .map(|ext| Path::new(rom_path).with_extension(ext))
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+// ROM databases hash the ROM contents without the 16 byte iNES header.
+pub fn rom_crc32(rom: &[u8]) -> u32 {
+    crc32(rom.get(16..).unwrap_or_default())
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    const EOF: &[u8] = b"EOF";
+
+    let mut out = rom.to_vec();
+    let mut pos = 5usize;
+
+    let mut take = |len: usize| -> Result<&[u8], String> {
+        let bytes = pos
+            .checked_add(len)
+            .and_then(|end| patch.get(pos..end))
+            .ok_or("IPS patch ended unexpectedly")?;
+        pos += len;
+        Ok(bytes)
+    };
+
+    loop {
+        let offset = take(3)?;
+        if offset == EOF {
+            break;
+        }
+        let offset = (offset[0] as usize) << 16 | (offset[1] as usize) << 8 | offset[2] as usize;
+
+        let size = take(2)?;
+        let size = (size[0] as usize) << 8 | size[1] as usize;
+
+        let data = if size == 0 {
+            let run = take(3)?;
+            let len = (run[0] as usize) << 8 | run[1] as usize;
+            vec![run[2]; len]
+        } else {
+            take(size)?.to_vec()
+        };
+
+        let end = offset + data.len();
+        if out.len() < end {
+            out.resize(end, 0);
+        }
+        out[offset..end].copy_from_slice(&data);
+    }
+
+    // Lunar IPS extension: an optional size to truncate the output to.
+    if let Ok(size) = take(3) {
+        let size = (size[0] as usize) << 16 | (size[1] as usize) << 8 | size[2] as usize;
+        out.truncate(size);
+    }
+
+    Ok(out)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    const FOOTER_SIZE: usize = 12;
+
+    if patch.len() < 4 + FOOTER_SIZE {
+        return Err("BPS patch is too small".to_string());
+    }
+
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let read_crc = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+    let (source_crc, target_crc, patch_crc) = (read_crc(0), read_crc(4), read_crc(8));
+
+    if crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err("BPS patch is corrupted".to_string());
+    }
+    if crc32(rom) != source_crc {
+        return Err("BPS patch was made for a different ROM".to_string());
+    }
+
+    let actions = &patch[..patch.len() - FOOTER_SIZE];
+    let mut pos = 4;
+
+    let source_size = decode_number(actions, &mut pos)?;
+    let target_size = decode_number(actions, &mut pos)?;
+    let metadata_size = decode_number(actions, &mut pos)?;
+
+    if source_size != rom.len() {
+        return Err("BPS patch was made for a different ROM size".to_string());
+    }
+    pos = pos
+        .checked_add(metadata_size)
+        .ok_or("BPS patch ended unexpectedly")?;
+
+    // The size comes from the patch, a bogus one must not allocate it all
+    let mut out = Vec::with_capacity(target_size.min(actions.len() + rom.len()));
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+
+    let relative = |data: usize, offset: &mut usize| -> Result<(), String> {
+        let delta = data >> 1;
+        *offset = if data & 1 != 0 {
+            offset.checked_sub(delta)
+        } else {
+            offset.checked_add(delta)
+        }
+        .ok_or("BPS patch has an invalid copy offset")?;
+        Ok(())
+    };
+
+    // Byte ranges of the ROM or the patch, which may be out of them
+    let range = |start: usize, len: usize| start..start.saturating_add(len);
+
+    while pos < actions.len() {
+        let data = decode_number(actions, &mut pos)?;
+        let len = (data >> 2) + 1;
+        if len > target_size - out.len().min(target_size) {
+            return Err("BPS patch writes past the end of the target".to_string());
+        }
+
+        match data & 0b11 {
+            // SourceRead
+            0 => {
+                let bytes = rom
+                    .get(range(out.len(), len))
+                    .ok_or("BPS patch reads past the end of the ROM")?;
+                out.extend_from_slice(bytes);
+            }
+            // TargetRead
+            1 => {
+                let bytes = actions
+                    .get(range(pos, len))
+                    .ok_or("BPS patch ended unexpectedly")?;
+                out.extend_from_slice(bytes);
+                pos += len;
+            }
+            // SourceCopy
+            2 => {
+                relative(decode_number(actions, &mut pos)?, &mut source_offset)?;
+                let bytes = rom
+                    .get(range(source_offset, len))
+                    .ok_or("BPS patch reads past the end of the ROM")?;
+                out.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            // TargetCopy, may overlap with the bytes being written
+            _ => {
+                relative(decode_number(actions, &mut pos)?, &mut target_offset)?;
+                for _ in 0..len {
+                    let byte = *out
+                        .get(target_offset)
+                        .ok_or("BPS patch has an invalid copy offset")?;
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if out.len() != target_size || crc32(&out) != target_crc {
+        return Err("BPS patch produced an unexpected result".to_string());
+    }
+
+    Ok(out)
+}
+
+fn decode_number(bytes: &[u8], pos: &mut usize) -> Result<usize, String> {
+    let too_big = || "BPS patch has a number too big".to_string();
+    let mut data = 0usize;
+    let mut shift = 1usize;
+    loop {
+        let x = *bytes.get(*pos).ok_or("BPS patch ended unexpectedly")?;
+        *pos += 1;
+        data = (x as usize & 0x7f)
+            .checked_mul(shift)
+            .and_then(|value| data.checked_add(value))
+            .ok_or_else(too_big)?;
+        if x & 0x80 != 0 {
+            return Ok(data);
+        }
+        shift = shift.checked_mul(0x80).ok_or_else(too_big)?;
+        data = data.checked_add(shift).ok_or_else(too_big)?;
+    }
+}
@@ -1,7 +1,7 @@
-use glow::HasContext;
-
 use crate::asc::MemoryMapped;
-use crate::{gfx, Ram};
+use crate::mapper::Cartridge;
+use crate::ppu_core::{Mirroring, PpuCore, RenderFlags, PALETTE_START, SCREEN_HEIGHT};
+use crate::watch::{Access, WatchHit, Watchpoint};
 
 const NAMETABLE_MASK: u8 = 0b11;
 const VRAM_MASK: u8 = 1 << 2;
@@ -11,32 +11,33 @@ const SPRITE_SIZE_MASK: u8 = 1 << 5;
 const MASTER_SLAVE_MASK: u8 = 1 << 6;
 const NMI_MASK: u8 = 1 << 7;
 
-const VBLANK_MASK: u8 = 1 << 7;
-
-const NUM_CHARS: u32 = 512;
-const CHAR_PIXEL_SIZE: u32 = 8;
-
-const CHARS_WIDTH: u32 = 32;
-const CHARS_HEIGHT: u32 = 30;
-
-const ATRTABLE_SIZE: usize = 8;
-
-#[rustfmt::skip]
-const DEFAULT_SYSTEM_PALLETE: [u32; 64] = [
-    0x626262, 0x002391, 0x1810A6, 0x440099, 0x660071, 0x6D002C, 0x680A00, 0x4D2400,
-    0x2D3F00, 0x034B00, 0x005100, 0x004B1B, 0x003F62, 0x000000, 0x000000, 0x000000,
-    0xABABAB, 0x1E55EA, 0x463BFF, 0x8220F6, 0xAD1CBD, 0xBA2061, 0xB33215, 0x8D5600,
-    0x617900, 0x298B00, 0x079300, 0x008B4A, 0x0079A9, 0x000000, 0x000000, 0x000000,
-    0xFFFFFF, 0x6CA6FF, 0x968BFF, 0xD46FFF, 0xFF6BFF, 0xFF6FB2, 0xFF8263, 0xE0A70C,
-    0xB2CB00, 0x78DE00, 0x55E632, 0x3EDE9A, 0x4ECBFD, 0x4E4E4E, 0x000000, 0x000000,
-    0xFFFFFF, 0xC4DBFF, 0xD5D0FF, 0xEEC5FF, 0xFFC6FF, 0xFFC5E0, 0xFFCDC0, 0xF3DC9D,
-    0xE3ED96, 0xC9F299, 0xBBF5AD, 0xB2F2D7, 0xBBEDFF, 0xB8B8B8, 0x000000, 0x000000,
-];
+const GREYSCALE_MASK: u8 = 1;
+const BACKGROUND_LEFT_MASK: u8 = 1 << 1;
+const SPRITES_LEFT_MASK: u8 = 1 << 2;
+const SHOW_BACKGROUND_MASK: u8 = 1 << 3;
+const SHOW_SPRITES_MASK: u8 = 1 << 4;
+const RENDERING_MASK: u8 = SHOW_BACKGROUND_MASK | SHOW_SPRITES_MASK;
+const EMPHASIS_SHIFT: u8 = 5;
 
+const SPRITE_OVERFLOW_MASK: u8 = 1 << 5;
+const SPRITE_ZERO_HIT_MASK: u8 = 1 << 6;
+const VBLANK_MASK: u8 = 1 << 7;
+// The bits of PPUSTATUS that aren't connected, read from the I/O latch
+const STATUS_OPEN_BUS_MASK: u8 = 0x1f;
+
+// Fields of the v and t registers: yyy NN YYYYY XXXXX
+const COARSE_X_MASK: u16 = 0x001f;
+const COARSE_Y_MASK: u16 = 0x03e0;
+const NAMETABLE_BITS: u16 = 0x0c00;
+const FINE_Y_MASK: u16 = 0x7000;
+// Copied from t to v at the end of every scanline
+const HORIZONTAL_BITS: u16 = 0x041f;
+
+// PPUCTRL starts cleared, so the address moves across at power on
 #[derive(Debug, Default)]
 enum VramIncrement {
-    #[default]
     Down,
+    #[default]
     Across,
 }
 
@@ -54,18 +55,49 @@ enum JobType {
     Output,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScrollPosition {
+    pub coarse_x: u8,
+    pub fine_x: u8,
+    pub coarse_y: u8,
+    pub fine_y: u8,
+    pub nametable: u8,
+}
+
+impl ScrollPosition {
+    // Position of the leftmost pixel of the scanline in the 512x480
+    // nametable space
+    pub fn pixel(&self) -> (u16, u16) {
+        let x = (self.nametable & 1) as u16 * 256 + self.coarse_x as u16 * 8 + self.fine_x as u16;
+        let y = (self.nametable >> 1) as u16 * 240 + self.coarse_y as u16 * 8 + self.fine_y as u16;
+        (x, y)
+    }
+}
+
 #[derive(Debug)]
 pub struct Ppu {
     control: u8,
     mask: u8,
     status: u8,
     oam_addr: u8,
-    oam_data: u8,
-    scroll: u8,
-    addr: u16,
     oam_dma: u8,
+    // The data bus between the CPU and the PPU keeps the last value written
+    // to or read from any of the registers. Reads of the write only ones and
+    // of the unused bits of $2002 return it. On the console it fades to 0
+    // after a while, which games don't rely on.
+    // Based on https://www.nesdev.org/wiki/Open_bus_behavior#PPU_open_bus
+    io_latch: u8,
+    // $2007 reads below the palettes return the byte read by the previous
+    // one, see https://www.nesdev.org/wiki/PPU_registers#The_PPUDATA_read_buffer
+    read_buffer: u8,
+
+    // Internal registers shared by $2005 and $2006, named as in
+    // https://www.nesdev.org/wiki/PPU_scrolling
+    v: u16,  // VRAM address, the scroll position while rendering
+    t: u16,  // Temporary VRAM address, the scroll of the next scanline/frame
+    x: u8,   // Fine X scroll
+    w: bool, // Set between the first and second write to $2005/$2006
 
-    nametable_base: u16,
     vram_increment: VramIncrement,
     sprite_table_addr: u16,
     background_table_addr: u16,
@@ -75,22 +107,29 @@ pub struct Ppu {
 
     vblank: bool,
 
-    memory: Ram,
+    // Dots of the current scanline where the sprite 0 hit and sprite
+    // overflow flags get set, see `start_scanline`
+    sprite_zero_hit_dot: Option<u32>,
+    sprite_overflow_dot: Option<u32>,
 
-    chars_texture: Option<glow::Texture>,
-    char_program: Option<glow::Program>,
+    core: PpuCore,
 
-    first_byte: bool,
+    watchpoints: Vec<Watchpoint>,
+    watch_hits: Vec<WatchHit>,
 
-    system_pallete_texture: Option<glow::Texture>,
+    oam_dma_requested: bool,
 }
 
 impl MemoryMapped for Ppu {
     fn write(&mut self, addr: u16, value: u8) {
+        if addr != 0x4014 {
+            self.io_latch = value;
+        }
+
         match addr {
             0x2000 => {
                 self.control = value;
-                self.nametable_base = 0x2000 + 0x0400 * (self.control & NAMETABLE_MASK) as u16;
+                self.t = (self.t & !NAMETABLE_BITS) | ((value & NAMETABLE_MASK) as u16) << 10;
                 self.vram_increment = if self.control & VRAM_MASK == 0 {
                     VramIncrement::Across
                 } else {
@@ -104,7 +143,7 @@ impl MemoryMapped for Ppu {
                 self.background_table_addr = if self.control & BACKGROUND_MASK == 0 {
                     0
                 } else {
-                    0x100
+                    0x1000
                 };
                 self.sprite_size = if self.control & SPRITE_SIZE_MASK == 0 {
                     SpriteSize::Size8x8
@@ -121,69 +160,128 @@ impl MemoryMapped for Ppu {
             0x2001 => self.mask = value,
             0x2002 => (),
             0x2003 => self.oam_addr = value,
-            0x2004 => self.oam_data = value,
-            0x2005 => self.scroll = value,
+            0x2004 => self.write_oam_data(value),
+            0x2005 => {
+                if !self.w {
+                    self.t = (self.t & !COARSE_X_MASK) | (value >> 3) as u16;
+                    self.x = value & 0b111;
+                } else {
+                    self.t &= !(COARSE_Y_MASK | FINE_Y_MASK);
+                    self.t |= ((value >> 3) as u16) << 5 | ((value & 0b111) as u16) << 12;
+                }
+                self.w = !self.w;
+            }
             0x2006 => {
-                if !self.first_byte {
-                    self.addr = (value as u16) << 8;
+                if !self.w {
+                    // The top bit of the 15 bit register is cleared
+                    self.t = (self.t & 0x00ff) | ((value & 0x3f) as u16) << 8;
                 } else {
-                    self.addr |= value as u16;
+                    self.t = (self.t & 0xff00) | value as u16;
+                    self.v = self.t;
                 }
-                self.first_byte = !self.first_byte;
+                self.w = !self.w;
             }
             0x2007 => {
-                self.memory.write(self.addr, value);
-                self.addr += match self.vram_increment {
-                    VramIncrement::Across => 1,
-                    VramIncrement::Down => 32,
-                }
+                let addr = self.vram_addr();
+                self.watch(addr, value, Access::Write);
+                self.core.write(addr, value);
+                self.increment_vram_addr();
+            }
+            0x4014 => {
+                self.oam_dma = value;
+                self.oam_dma_requested = true;
             }
-            0x4014 => self.oam_dma = value,
             _ => panic!("Address {addr:#x} is not registered by the PPU"),
         }
     }
 
     fn read(&mut self, addr: u16) -> u8 {
-        match addr {
-            0x2000 => self.control,
-            0x2001 => self.mask,
+        let value = match addr {
             0x2002 => {
                 let st = self.status;
                 self.status &= !VBLANK_MASK;
-                self.first_byte = false;
-                st
+                self.w = false;
+                (st & !STATUS_OPEN_BUS_MASK) | (self.io_latch & STATUS_OPEN_BUS_MASK)
             }
-            0x2003 => self.oam_addr,
-            0x2004 => self.oam_data,
-            0x2005 => self.scroll,
-            0x2006 => self.addr as u8,
+            0x2004 => self.core.read_oam(self.oam_addr),
             0x2007 => {
-                let value = self.memory.read(self.addr);
-                self.addr += match self.vram_increment {
-                    VramIncrement::Across => 1,
-                    VramIncrement::Down => 32,
-                };
-                value
+                let addr = self.vram_addr();
+                let value = self.core.read(addr);
+                self.watch(addr, value, Access::Read);
+                self.increment_vram_addr();
+                if addr >= PALETTE_START {
+                    // Palettes come out right away, and the buffer gets the
+                    // nametable byte underneath them
+                    self.read_buffer = self.core.read(addr - 0x1000);
+                    value
+                } else {
+                    std::mem::replace(&mut self.read_buffer, value)
+                }
+            }
+            // Write only
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => self.io_latch,
+            0x4014 => return self.oam_dma,
+            _ => panic!("Address {addr:#x} is not registered by the PPU"),
+        };
+        self.io_latch = value;
+        value
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x2002 => {
+                (self.status & !STATUS_OPEN_BUS_MASK) | (self.io_latch & STATUS_OPEN_BUS_MASK)
             }
+            0x2004 => self.core.read_oam(self.oam_addr),
+            0x2007 => match self.vram_addr() {
+                addr if addr >= PALETTE_START => self.core.read(addr),
+                _ => self.read_buffer,
+            },
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => self.io_latch,
             0x4014 => self.oam_dma,
             _ => panic!("Address {addr:#x} is not registered by the PPU"),
         }
     }
+
+    // Sets the registers that hold a value, and OAM and VRAM at the current
+    // addresses, without moving them. $2002 is read only, and $2005 and
+    // $2006 only make sense as pairs of writes.
+    fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x2000 | 0x2001 | 0x2003 => {
+                let latch = self.io_latch;
+                self.write(addr, value);
+                self.io_latch = latch;
+            }
+            0x2004 => self.core.write_oam(self.oam_addr, value),
+            0x2007 => {
+                let addr = self.vram_addr();
+                self.core.write(addr, value);
+            }
+            0x2002 | 0x2005 | 0x2006 => {}
+            // Without starting a DMA
+            0x4014 => self.oam_dma = value,
+            _ => panic!("Address {addr:#x} is not registered by the PPU"),
+        }
+    }
 }
 
 impl Ppu {
-    pub fn new(memory: Ram) -> Ppu {
+    pub fn new(cartridge: Cartridge) -> Ppu {
         Ppu {
             control: 0,
             mask: 0,
             status: 0,
             oam_addr: 0,
-            oam_data: 0,
-            scroll: 0,
-            addr: 0,
             oam_dma: 0,
+            io_latch: 0,
+            read_buffer: 0,
+
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
 
-            nametable_base: 0,
             vram_increment: VramIncrement::default(),
             sprite_table_addr: 0,
             background_table_addr: 0,
@@ -193,298 +291,228 @@ impl Ppu {
 
             vblank: false,
 
-            memory,
-            chars_texture: None,
-            char_program: None,
+            sprite_zero_hit_dot: None,
+            sprite_overflow_dot: None,
+
+            core: PpuCore::new(cartridge),
 
-            first_byte: false,
+            watchpoints: vec![],
+            watch_hits: vec![],
 
-            system_pallete_texture: None,
+            oam_dma_requested: false,
         }
     }
 
-    fn decode_char(char: [u8; 16]) -> Vec<u8> {
-        let mut decoded = vec![];
-        let (plane0, plane1) = char.split_at(8);
-        for (p0, p1) in plane0.iter().zip(plane1) {
-            for i in (0..8).rev() {
-                let bit0 = (p0 >> i) & 1;
-                let bit1 = (p1 >> i) & 1;
-                let pixel = (bit1 << 1) | bit0;
-                decoded.push(pixel);
-            }
+    pub fn scroll(&self) -> ScrollPosition {
+        ScrollPosition {
+            coarse_x: (self.v & COARSE_X_MASK) as u8,
+            fine_x: self.x,
+            coarse_y: ((self.v & COARSE_Y_MASK) >> 5) as u8,
+            fine_y: ((self.v & FINE_Y_MASK) >> 12) as u8,
+            nametable: ((self.v & NAMETABLE_BITS) >> 10) as u8,
+        }
+    }
+
+    fn render_flags(&self) -> RenderFlags {
+        RenderFlags {
+            background_table: self.background_table_addr,
+            sprite_table: self.sprite_table_addr,
+            tall_sprites: matches!(self.sprite_size, SpriteSize::Size8x16),
+            show_background: self.mask & SHOW_BACKGROUND_MASK != 0,
+            show_sprites: self.mask & SHOW_SPRITES_MASK != 0,
+            background_left: self.mask & BACKGROUND_LEFT_MASK != 0,
+            sprites_left: self.mask & SPRITES_LEFT_MASK != 0,
+            greyscale: self.mask & GREYSCALE_MASK != 0,
+            emphasis: self.mask >> EMPHASIS_SHIFT,
         }
-        return decoded;
     }
 
-    pub fn precal_chars(&mut self, gl: &glow::Context) {
-        let char_program;
+    fn rendering_enabled(&self) -> bool {
+        self.mask & RENDERING_MASK != 0
+    }
 
-        let mut chars: Vec<u8> = vec![];
+    fn vram_addr(&self) -> u16 {
+        self.v & 0x3fff
+    }
 
-        for i in 0..(NUM_CHARS as u16) {
-            let mut char = vec![];
-            for addr in 0..16 {
-                char.push(self.memory.read(addr + 16 * i));
-            }
-            let mut char = Ppu::decode_char(char.try_into().unwrap());
-            chars.append(&mut char);
-        }
+    fn increment_vram_addr(&mut self) {
+        let increment = match self.vram_increment {
+            VramIncrement::Across => 1,
+            VramIncrement::Down => 32,
+        };
+        self.v = (self.v + increment) & 0x7fff;
+    }
+
+    // Works out at which dots of the scanline the sprite 0 hit and sprite
+    // overflow flags get set, from the registers at its start. The flags
+    // change when the CPU gets to those dots, see `run_to_dot`, so games
+    // polling $2002 see them mid-line like on the console.
+    pub fn start_scanline(&mut self, scanline: u32) {
+        let y = scanline as usize;
+        let flags = self.render_flags();
+        // The hit shows up on the dot after the pixel is output, dot 0 is idle
+        self.sprite_zero_hit_dot = self
+            .core
+            .sprite_zero_hit(y, self.scroll(), flags)
+            .map(|x| x as u32 + 2);
+        self.sprite_overflow_dot = self.core.sprite_overflow(y, flags);
+    }
 
-        let chars_texture = gfx::create_tex(
-            gl,
-            glow::TEXTURE_2D,
-            glow::R8 as i32,
-            CHAR_PIXEL_SIZE as i32,
-            (CHAR_PIXEL_SIZE * NUM_CHARS) as i32,
-            glow::RED,
-            chars.as_slice(),
-        );
-
-        unsafe {
-            let vert_shader = include_str!("../assets/char.vert");
-            let frag_shader = include_str!("../assets/char.frag");
-            char_program = gfx::create_program(gl, &vert_shader, &frag_shader);
+    // Sets the flags of the current scanline due up to `dot`
+    pub fn run_to_dot(&mut self, dot: u32) {
+        if self.sprite_zero_hit_dot.is_some_and(|hit| hit <= dot) {
+            self.sprite_zero_hit_dot = None;
+            self.status |= SPRITE_ZERO_HIT_MASK;
+        }
+        if self
+            .sprite_overflow_dot
+            .is_some_and(|overflow| overflow <= dot)
+        {
+            self.sprite_overflow_dot = None;
+            self.status |= SPRITE_OVERFLOW_MASK;
         }
+    }
 
-        (self.chars_texture, self.char_program) = (Some(chars_texture), Some(char_program));
-    }
-
-    pub fn setup_pallet_tex(&mut self, gl: &glow::Context) {
-        self.system_pallete_texture = Some(gfx::create_tex(
-            gl,
-            glow::TEXTURE_1D,
-            glow::RGB as i32,
-            64,
-            0,
-            glow::BGR,
-            &DEFAULT_SYSTEM_PALLETE
-                .iter()
-                .flat_map(|v: &u32| {
-                    let v: [u8; 4] = v.to_le_bytes();
-                    v[0..3].to_vec()
-                })
-                .collect::<Vec<u8>>(),
-        ));
-    }
-
-    unsafe fn draw_char(
-        &self,
-        gl: &glow::Context,
-        index: usize,
-        x: usize,
-        y: usize,
-        palletes_tex: glow::Texture,
-        atrtable_tex: glow::Texture,
-    ) {
-        let mut quad: Vec<f32> = vec![
-            1.0f32, 1.0f32, 1.0f32, 0.0f32, 0.0f32, 0.0f32, 0.0f32, 1.0f32,
-        ];
+    // Both stay set until the pre-render scanline
+    pub fn sprite_zero_hit(&self) -> bool {
+        self.status & SPRITE_ZERO_HIT_MASK != 0
+    }
+
+    pub fn clear_sprite_flags(&mut self) {
+        self.status &= !(SPRITE_ZERO_HIT_MASK | SPRITE_OVERFLOW_MASK);
+    }
 
-        let mut quad_tex = quad.clone();
+    // Draws a visible scanline with the current registers, then moves v to
+    // the next one like the PPU does at the end of the line: down one pixel
+    // and back to the horizontal position in t.
+    pub fn render_scanline(&mut self, scanline: u32) {
+        self.run_to_dot(u32::MAX);
 
-        let x: f32 = x as f32 / CHARS_WIDTH as f32;
-        let y: f32 = y as f32 / CHARS_HEIGHT as f32;
+        let scroll = self.scroll();
+        let flags = self.render_flags();
+        self.core.render_scanline(scanline, scroll, flags);
 
-        quad = quad
-            .iter()
-            .enumerate()
-            .map(|(i, p)| {
-                let mut p = *p;
-                if i % 2 == 0 {
-                    p /= CHARS_WIDTH as f32;
-                    p + x
-                } else {
-                    p /= CHARS_HEIGHT as f32;
-                    p + y
-                }
-            })
-            .collect::<Vec<f32>>()
-            .try_into()
-            .unwrap();
-
-        quad_tex = quad_tex
-            .iter()
-            .enumerate()
-            .map(|(i, p)| {
-                let mut p = *p;
-                if i % 2 == 0 {
-                    p
-                } else {
-                    p += index as f32;
-                    p / NUM_CHARS as f32
-                }
-            })
-            .collect::<Vec<f32>>()
-            .try_into()
-            .unwrap();
-
-        quad.append(&mut quad_tex);
-
-        let verts = core::slice::from_raw_parts(
-            quad.as_ptr() as *const u8,
-            quad.len() * core::mem::size_of::<f32>(),
-        );
-
-        let vbo = gl.create_buffer().unwrap();
-        gl.bind_buffer(glow::ARRAY_BUFFER, Some(vbo));
-        gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, &verts, glow::STATIC_DRAW);
-
-        let vao = gl.create_vertex_array().unwrap();
-        gl.bind_vertex_array(Some(vao));
-
-        let pos_loc = gl
-            .get_attrib_location(self.char_program.unwrap(), "pos")
-            .unwrap();
-        gl.enable_vertex_attrib_array(pos_loc);
-        gl.vertex_attrib_pointer_f32(
-            pos_loc,
-            2,
-            glow::FLOAT,
-            false,
-            2 * core::mem::size_of::<f32>() as i32,
-            0,
-        );
-
-        let pos_uv = gl
-            .get_attrib_location(self.char_program.unwrap(), "uv_in")
-            .unwrap();
-        gl.enable_vertex_attrib_array(pos_uv);
-        gl.vertex_attrib_pointer_f32(
-            pos_uv,
-            2,
-            glow::FLOAT,
-            false,
-            2 * core::mem::size_of::<f32>() as i32,
-            8 * core::mem::size_of::<f32>() as i32,
-        );
-
-        let elements: [u32; 6] = [0, 1, 3, 1, 2, 3];
-        let elements = core::slice::from_raw_parts(
-            elements.as_ptr() as *const u8,
-            elements.len() * core::mem::size_of::<u32>(),
-        );
-        let ebo = gl.create_buffer().unwrap();
-        gl.use_program(self.char_program);
-
-        gl.active_texture(glow::TEXTURE0);
-        gl.bind_texture(glow::TEXTURE_2D, self.chars_texture);
-        let mut sampler = gl
-            .get_uniform_location(self.char_program.unwrap(), "chars_sampler")
-            .unwrap();
-        gl.uniform_1_i32(Some(&sampler), 0);
-
-        gl.active_texture(glow::TEXTURE1);
-        gl.bind_texture(glow::TEXTURE_1D, self.system_pallete_texture);
-        sampler = gl
-            .get_uniform_location(self.char_program.unwrap(), "syspallete_sampler")
-            .unwrap();
-        gl.uniform_1_i32(Some(&sampler), 1);
-
-        gl.active_texture(glow::TEXTURE2);
-        gl.bind_texture(glow::TEXTURE_1D, Some(palletes_tex));
-        sampler = gl
-            .get_uniform_location(self.char_program.unwrap(), "palletes_sampler")
-            .unwrap();
-        gl.uniform_1_i32(Some(&sampler), 2);
-
-        gl.active_texture(glow::TEXTURE3);
-        gl.bind_texture(glow::TEXTURE_2D, Some(atrtable_tex));
-        let sampler = gl
-            .get_uniform_location(self.char_program.unwrap(), "atrtable_sampler")
-            .unwrap();
-        gl.uniform_1_i32(Some(&sampler), 3);
-
-        gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(ebo));
-        gl.buffer_data_u8_slice(glow::ELEMENT_ARRAY_BUFFER, &elements, glow::STATIC_DRAW);
-        gl.draw_elements(glow::TRIANGLES, 6, glow::UNSIGNED_INT, 0);
-
-        gl.delete_buffer(vbo);
-        gl.delete_vertex_array(vao);
-        gl.delete_buffer(ebo);
-    }
-
-    pub fn draw(&mut self, gl: &glow::Context) {
-        let mut pallets = vec![];
-        for i in 0x3f00..=0x3f0f {
-            pallets.push(self.memory.read(i));
+        if self.rendering_enabled() && (scanline as usize) < SCREEN_HEIGHT {
+            self.increment_y();
+            self.v = (self.v & !HORIZONTAL_BITS) | (self.t & HORIZONTAL_BITS);
         }
+    }
 
-        let pallets_tex = gfx::create_tex(
-            gl,
-            glow::TEXTURE_1D,
-            glow::R8 as i32,
-            16,
-            0,
-            glow::RED,
-            pallets.as_slice(),
-        );
-
-        let atrtable_offset = CHARS_WIDTH * CHARS_HEIGHT;
-        let atrtable_addr = self.nametable_base + atrtable_offset as u16;
+    // On the pre-render scanline the PPU reloads all of v from t, so the
+    // scroll set during vblank applies to the whole next frame.
+    pub fn start_frame(&mut self) {
+        if self.rendering_enabled() {
+            self.v = self.t;
+        }
+    }
 
-        let mut attribute_table = vec![];
+    // Based on https://www.nesdev.org/wiki/PPU_scrolling#Wrapping_around
+    fn increment_y(&mut self) {
+        if self.v & FINE_Y_MASK != FINE_Y_MASK {
+            self.v += 0x1000;
+            return;
+        }
 
-        for i in 0..(ATRTABLE_SIZE * ATRTABLE_SIZE) as u16 {
-            attribute_table.push(self.memory.read(atrtable_addr + i));
+        self.v &= !FINE_Y_MASK;
+        let mut coarse_y = (self.v & COARSE_Y_MASK) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // Rows 30 and 31 hold the attributes, scrolling into them wraps
+            // without switching nametables
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
         }
+        self.v = (self.v & !COARSE_Y_MASK) | coarse_y << 5;
+    }
+
+    pub fn scanline_scroll(&self, scanline: u32) -> Option<ScrollPosition> {
+        self.core.scanline_scroll(scanline)
+    }
+
+    // All four nametables with the current pattern table and palettes, see
+    // `PpuCore::render_nametables`
+    pub fn render_nametables(&mut self) -> Vec<u16> {
+        self.core.render_nametables(self.background_table_addr)
+    }
 
-        let mut buf = vec![0; (ATRTABLE_SIZE * ATRTABLE_SIZE * 4) as usize];
+    pub fn mirroring(&self) -> Mirroring {
+        self.core.mirroring()
+    }
 
-        for (i, v) in attribute_table.into_iter().enumerate() {
-            let top_left = (v >> (0 * 2)) & 0x3;
-            let top_right = (v >> (1 * 2)) & 0x3;
-            let bottom_left = (v >> (2 * 2)) & 0x3;
-            let bottom_right = (v >> (3 * 2)) & 0x3;
+    pub fn framebuffer(&self) -> &[u16] {
+        self.core.framebuffer()
+    }
 
-            let mut x = i % ATRTABLE_SIZE;
-            let mut y = i / ATRTABLE_SIZE;
-            x *= 2;
-            y *= 2;
-            let mut pos = x + y * (CHARS_WIDTH / 2) as usize;
-            buf[pos] = top_left;
+    pub fn sprite_mask(&self) -> &[bool] {
+        self.core.sprite_mask()
+    }
 
-            x += 1;
-            pos = x + y * (CHARS_WIDTH / 2) as usize;
-            buf[pos] = top_right;
+    pub fn backdrop(&mut self) -> u8 {
+        self.core.backdrop()
+    }
 
-            y += 1;
-            pos = x + y * (CHARS_WIDTH / 2) as usize;
-            buf[pos] = bottom_right;
+    // The whole PPU address space, $0000-$3FFF
+    pub fn vram(&mut self) -> Vec<u8> {
+        (0..0x4000).map(|addr| self.core.read(addr)).collect()
+    }
 
-            x -= 1;
-            pos = x + y * (CHARS_WIDTH / 2) as usize;
-            buf[pos] = bottom_left;
-        }
+    pub fn oam(&self) -> &[u8] {
+        self.core.oam()
+    }
 
-        buf.truncate((CHARS_WIDTH * CHARS_HEIGHT / 4) as usize);
-        let attribute_table = buf;
-
-        let atrtable_tex = gfx::create_tex(
-            gl,
-            glow::TEXTURE_2D,
-            glow::R8 as i32,
-            (CHARS_WIDTH / 2) as i32,
-            (CHARS_HEIGHT / 2) as i32,
-            glow::RED,
-            attribute_table.as_slice(),
-        );
-
-        unsafe {
-            gl.clear(glow::COLOR_BUFFER_BIT);
-
-            for i in 0..CHARS_WIDTH * CHARS_HEIGHT {
-                let char = self.memory.read(i as u16 + self.nametable_base) as usize
-                    + self.background_table_addr as usize;
-                self.draw_char(
-                    gl,
-                    char,
-                    (i % CHARS_WIDTH) as usize,
-                    (i / CHARS_WIDTH) as usize,
-                    pallets_tex,
-                    atrtable_tex,
-                );
-            }
+    // Registers, VRAM and OAM, everything that affects what the PPU does next
+    pub fn state_bytes(&mut self) -> Vec<u8> {
+        let mut bytes = vec![
+            self.control,
+            self.mask,
+            self.status,
+            self.oam_addr,
+            self.x,
+            self.w as u8,
+            self.io_latch,
+            self.read_buffer,
+        ];
+        bytes.extend(self.v.to_le_bytes());
+        bytes.extend(self.t.to_le_bytes());
+        bytes.extend(self.vram());
+        bytes.extend(self.core.oam());
+        bytes
+    }
+
+    // The CPU page to copy into OAM, if $4014 was written since the last call
+    pub fn take_oam_dma(&mut self) -> Option<u8> {
+        std::mem::take(&mut self.oam_dma_requested).then_some(self.oam_dma)
+    }
+
+    // Writes through OAMDATA, which advances OAMADDR. The DMA uses it too, so
+    // it starts copying at the current OAMADDR.
+    pub fn write_oam_data(&mut self, value: u8) {
+        self.core.write_oam(self.oam_addr, value);
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    pub fn take_watchpoints(&mut self) -> Vec<Watchpoint> {
+        std::mem::take(&mut self.watchpoints)
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    fn watch(&mut self, addr: u16, value: u8, access: Access) {
+        if self.watchpoints.iter().any(|w| w.matches(addr, access)) {
+            self.watch_hits.push(WatchHit {
+                addr,
+                value,
+                access,
+            });
         }
     }
 
@@ -0,0 +1,582 @@
+use std::fmt;
+
+use crate::asc::MemoryMapped;
+use crate::mapper::Cartridge;
+use crate::ppu::ScrollPosition;
+use crate::ram::Ram;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+const TILES_WIDTH: usize = SCREEN_WIDTH / 8;
+const TILES_HEIGHT: usize = SCREEN_HEIGHT / 8;
+
+// The four nametables laid out in a 2x2 grid
+pub const NAMETABLES_WIDTH: usize = SCREEN_WIDTH * 2;
+pub const NAMETABLES_HEIGHT: usize = SCREEN_HEIGHT * 2;
+
+// Held by the cartridge, see `Mapper::ppu_read`
+const PATTERN_TABLES_END: u16 = 0x1fff;
+
+const NAMETABLES_START: u16 = 0x2000;
+// Including the mirror at $3000
+const NAMETABLES_END: u16 = 0x3eff;
+const NAMETABLE_SIZE: u16 = 0x400;
+const ATTRIBUTE_TABLE_OFFSET: u16 = 0x3c0;
+
+pub const PALETTE_START: u16 = 0x3f00;
+// 16 entries for the background followed by 16 for the sprites
+const PALETTE_SIZE: usize = 32;
+const SPRITE_PALETTES: usize = 16;
+
+// 64 sprites of 4 bytes each
+pub const OAM_SIZE: usize = 256;
+const SPRITES_PER_SCANLINE: usize = 8;
+
+// Dots of the scanline where the PPU goes through OAM looking for the sprites
+// of the next line, reading one byte every 2 dots
+const SPRITE_EVALUATION_START: u32 = 65;
+const SPRITE_EVALUATION_END: u32 = 256;
+
+const SPRITE_PALETTE_MASK: u8 = 0b11;
+const SPRITE_BEHIND_MASK: u8 = 1 << 5;
+const SPRITE_FLIP_X_MASK: u8 = 1 << 6;
+const SPRITE_FLIP_Y_MASK: u8 = 1 << 7;
+
+#[rustfmt::skip]
+const SYSTEM_PALETTE: [u32; 64] = [
+    0x626262, 0x002391, 0x1810A6, 0x440099, 0x660071, 0x6D002C, 0x680A00, 0x4D2400,
+    0x2D3F00, 0x034B00, 0x005100, 0x004B1B, 0x003F62, 0x000000, 0x000000, 0x000000,
+    0xABABAB, 0x1E55EA, 0x463BFF, 0x8220F6, 0xAD1CBD, 0xBA2061, 0xB33215, 0x8D5600,
+    0x617900, 0x298B00, 0x079300, 0x008B4A, 0x0079A9, 0x000000, 0x000000, 0x000000,
+    0xFFFFFF, 0x6CA6FF, 0x968BFF, 0xD46FFF, 0xFF6BFF, 0xFF6FB2, 0xFF8263, 0xE0A70C,
+    0xB2CB00, 0x78DE00, 0x55E632, 0x3EDE9A, 0x4ECBFD, 0x4E4E4E, 0x000000, 0x000000,
+    0xFFFFFF, 0xC4DBFF, 0xD5D0FF, 0xEEC5FF, 0xFFC6FF, 0xFFC5E0, 0xFFCDC0, 0xF3DC9D,
+    0xE3ED96, 0xC9F299, 0xBBF5AD, 0xB2F2D7, 0xBBEDFF, 0xB8B8B8, 0x000000, 0x000000,
+];
+
+// How the four nametables of the PPU address space map to the VRAM. The
+// console has memory for two, the cartridge decides which one each
+// nametable uses, or provides memory for the other two (2KB more, for 4KB
+// in total).
+// Based on https://www.nesdev.org/wiki/Mirroring#Nametable_Mirroring
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    // $2000 = $2400 and $2800 = $2C00, for vertical scrolling
+    #[default]
+    Horizontal,
+    // $2000 = $2800 and $2400 = $2C00, for horizontal scrolling
+    Vertical,
+    // All four show the first or the second nametable, under mapper control
+    SingleScreenLower,
+    SingleScreenUpper,
+    // Extra VRAM on the cartridge gives every nametable its own memory
+    FourScreen,
+}
+
+impl Mirroring {
+    // Maps an address in $2000-$3EFF to an offset in the nametable memory
+    fn map(self, addr: u16) -> usize {
+        // $3000-$3EFF mirrors $2000-$2EFF
+        let offset = (addr - NAMETABLES_START) % (4 * NAMETABLE_SIZE);
+        let (nametable, index) = (offset / NAMETABLE_SIZE, offset % NAMETABLE_SIZE);
+
+        let nametable = match self {
+            Mirroring::Horizontal => nametable >> 1,
+            Mirroring::Vertical => nametable & 1,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => nametable,
+        };
+        (nametable * NAMETABLE_SIZE + index) as usize
+    }
+
+    // Bytes of nametable memory the console and cartridge have together
+    fn vram_size(self) -> usize {
+        match self {
+            Mirroring::FourScreen => 4 * NAMETABLE_SIZE as usize,
+            _ => 2 * NAMETABLE_SIZE as usize,
+        }
+    }
+}
+
+impl fmt::Display for Mirroring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Mirroring::Horizontal => "horizontal",
+            Mirroring::Vertical => "vertical",
+            Mirroring::SingleScreenLower => "single screen, lower",
+            Mirroring::SingleScreenUpper => "single screen, upper",
+            Mirroring::FourScreen => "four screen",
+        };
+        write!(f, "{name}")
+    }
+}
+
+// The PPUCTRL and PPUMASK settings used to draw a scanline
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RenderFlags {
+    pub background_table: u16,
+    // Only used by 8x8 sprites, 8x16 ones pick the table with the tile index
+    pub sprite_table: u16,
+    pub tall_sprites: bool,
+    pub show_background: bool,
+    pub show_sprites: bool,
+    // Whether each layer shows on the leftmost 8 pixels
+    pub background_left: bool,
+    pub sprites_left: bool,
+    // Limits the colors to the grey column of the system palette
+    pub greyscale: bool,
+    // Red, green and blue emphasis in the lower 3 bits
+    pub emphasis: u8,
+}
+
+fn sprite_height(flags: RenderFlags) -> usize {
+    if flags.tall_sprites {
+        16
+    } else {
+        8
+    }
+}
+
+// The Y coordinate in OAM is one less than the first line of a sprite
+fn sprite_on_line(sprite_y: u8, y: usize, height: usize) -> bool {
+    (sprite_y as usize + 1..sprite_y as usize + 1 + height).contains(&y)
+}
+
+// The 32 palette bytes repeat up to $3FFF, and the first color of every
+// sprite palette is the same memory as the one of the background palette
+// below it
+fn palette_addr(addr: u16) -> u16 {
+    let addr = PALETTE_START + (addr - PALETTE_START) % PALETTE_SIZE as u16;
+    if addr & 0x13 == 0x10 {
+        addr & !0x10
+    } else {
+        addr
+    }
+}
+
+// The PPU dims the colors that are not emphasized, by about this much
+// Based on https://www.nesdev.org/wiki/NTSC_video#Color_Tint_Bits
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+// Converts a frame of NES color indices, with the emphasis bits on top of
+// them, into 24 bit RGB pixels
+pub fn to_rgb(framebuffer: &[u16]) -> Vec<u8> {
+    framebuffer
+        .iter()
+        .flat_map(|&color| {
+            let [b, g, r, _] = SYSTEM_PALETTE[(color & 0x3f) as usize].to_le_bytes();
+            let emphasis = color >> 6;
+            if emphasis == 0 {
+                return [r, g, b];
+            }
+
+            let mut rgb = [r, g, b];
+            for (channel, value) in rgb.iter_mut().enumerate() {
+                if emphasis & (1 << channel) == 0 {
+                    *value = (*value as f32 * EMPHASIS_ATTENUATION) as u8;
+                }
+            }
+            rgb
+        })
+        .collect()
+}
+
+// The VRAM side of the PPU: owns the nametable and palette memory, reads
+// the pattern tables from the cartridge and renders them into a
+// framebuffer. It knows nothing about the CPU facing registers, which live
+// in `Ppu`, or about how the frame gets to the screen.
+#[derive(Debug)]
+pub struct PpuCore {
+    nametables: Vec<u8>,
+    // The palettes, at $3F00-$3FFF
+    memory: Ram,
+    cartridge: Cartridge,
+    oam: [u8; OAM_SIZE],
+
+    scanline_scroll: Vec<ScrollPosition>,
+
+    // NES color index of every pixel of the last rendered frame, with the
+    // emphasis bits shifted to bits 6-8
+    framebuffer: Vec<u16>,
+    // Pixels of the framebuffer where a sprite was opaque, even if the
+    // background covered it
+    sprite_mask: Vec<bool>,
+}
+
+impl PpuCore {
+    pub fn new(cartridge: Cartridge) -> PpuCore {
+        let vram_size = cartridge.borrow().mirroring().vram_size();
+        PpuCore {
+            nametables: vec![0; vram_size],
+            memory: Ram::new(PALETTE_SIZE),
+            cartridge,
+            oam: [0; OAM_SIZE],
+
+            scanline_scroll: vec![ScrollPosition::default(); SCREEN_HEIGHT],
+
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            sprite_mask: vec![false; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=PATTERN_TABLES_END => self.cartridge.borrow_mut().ppu_read(addr),
+            NAMETABLES_START..=NAMETABLES_END => self.nametables[self.nametable_offset(addr)],
+            _ => self.memory.read(palette_addr(addr)),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=PATTERN_TABLES_END => self.cartridge.borrow_mut().ppu_write(addr, value),
+            NAMETABLES_START..=NAMETABLES_END => {
+                let offset = self.nametable_offset(addr);
+                self.nametables[offset] = value;
+            }
+            _ => self.memory.write(palette_addr(addr), value),
+        }
+    }
+
+    // Decided by the cartridge, which some mappers switch at run time
+    pub fn mirroring(&self) -> Mirroring {
+        self.cartridge.borrow().mirroring()
+    }
+
+    // Mappers only switch between the modes that use the console VRAM, the
+    // wrap keeps a switch on a four-screen board from going out of bounds
+    fn nametable_offset(&self, addr: u16) -> usize {
+        self.mirroring().map(addr) % self.nametables.len()
+    }
+
+    pub fn read_oam(&self, addr: u8) -> u8 {
+        self.oam[addr as usize]
+    }
+
+    pub fn write_oam(&mut self, addr: u8, value: u8) {
+        self.oam[addr as usize] = value;
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    pub fn scanline_scroll(&self, scanline: u32) -> Option<ScrollPosition> {
+        self.scanline_scroll.get(scanline as usize).copied()
+    }
+
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.framebuffer
+    }
+
+    pub fn sprite_mask(&self) -> &[bool] {
+        &self.sprite_mask
+    }
+
+    // Color index of palette entry 0, which transparent pixels show
+    pub fn backdrop(&mut self) -> u8 {
+        self.memory.read(PALETTE_START) & 0x3f
+    }
+
+    // Draws a visible scanline, with the background starting at `scroll`.
+    // Lines are drawn as the PPU gets to them, so mid-frame changes (split
+    // screens, palette gradients, pattern table switches) show up on the
+    // lines they were made for instead of applying to the whole frame.
+    pub fn render_scanline(&mut self, scanline: u32, scroll: ScrollPosition, flags: RenderFlags) {
+        let y = scanline as usize;
+        if y >= SCREEN_HEIGHT {
+            return;
+        }
+        self.scanline_scroll[y] = scroll;
+
+        let palette = self.palette();
+        let color_mask = if flags.greyscale { 0x30 } else { 0x3f };
+        let emphasis = (flags.emphasis as u16) << 6;
+        let background = self.background_line(scroll, flags);
+        let sprites = self.sprite_line(y, flags);
+
+        for (x, (&background, sprite)) in background.iter().zip(sprites).enumerate() {
+            // Transparent pixels of both layers show the backdrop color
+            let color = match sprite {
+                Some((color, behind)) if !behind || background == 0 => color,
+                _ => background,
+            };
+            self.framebuffer[y * SCREEN_WIDTH + x] =
+                (palette[color] & color_mask) as u16 | emphasis;
+            self.sprite_mask[y * SCREEN_WIDTH + x] = sprite.is_some();
+        }
+    }
+
+    // Palette indices of the background pixels of a scanline, 0 where it is
+    // transparent
+    fn background_line(
+        &mut self,
+        scroll: ScrollPosition,
+        flags: RenderFlags,
+    ) -> [usize; SCREEN_WIDTH] {
+        let mut line = [0; SCREEN_WIDTH];
+        if !flags.show_background {
+            return line;
+        }
+
+        let mut nametable = scroll.nametable as u16;
+        let mut tile_col = scroll.coarse_x as usize;
+
+        // With a fine X scroll the line spans parts of 33 tiles
+        for i in 0..=TILES_WIDTH {
+            let colors = self.tile_sliver(
+                nametable,
+                scroll.coarse_y as usize,
+                tile_col,
+                scroll.fine_y,
+                flags.background_table,
+            );
+
+            for (x, &color) in colors.iter().enumerate() {
+                let Some(screen_x) = (i * 8 + x).checked_sub(scroll.fine_x as usize) else {
+                    continue;
+                };
+                if screen_x >= SCREEN_WIDTH {
+                    break;
+                }
+                line[screen_x] = color;
+            }
+
+            // Scrolling past the right edge continues on the horizontally
+            // adjacent nametable
+            tile_col += 1;
+            if tile_col == TILES_WIDTH {
+                tile_col = 0;
+                nametable ^= 1;
+            }
+        }
+
+        if !flags.background_left {
+            line[..8].fill(0);
+        }
+        line
+    }
+
+    // Palette indices of the sprite pixels of a scanline, with whether the
+    // sprite is behind the background. Like the PPU only the first 8 sprites
+    // in OAM that are on the line are drawn, and where they overlap the one
+    // that comes first in OAM wins, even when it is behind the background
+    // and a later one isn't.
+    fn sprite_line(
+        &mut self,
+        y: usize,
+        flags: RenderFlags,
+    ) -> [Option<(usize, bool)>; SCREEN_WIDTH] {
+        let mut line = [None; SCREEN_WIDTH];
+        if !flags.show_sprites {
+            return line;
+        }
+
+        let height = sprite_height(flags);
+        let sprites: Vec<[u8; 4]> = self
+            .oam
+            .chunks(4)
+            .filter(|sprite| sprite_on_line(sprite[0], y, height))
+            .take(SPRITES_PER_SCANLINE)
+            .map(|sprite| sprite.try_into().unwrap())
+            .collect();
+
+        for sprite in sprites {
+            let [_, _, attributes, sprite_x] = sprite;
+            let palette_index = (attributes & SPRITE_PALETTE_MASK) as usize;
+            let behind = attributes & SPRITE_BEHIND_MASK != 0;
+
+            for (i, pixel) in self.sprite_row(sprite, y, flags).into_iter().enumerate() {
+                let x = sprite_x as usize + i;
+                if x >= SCREEN_WIDTH {
+                    break;
+                }
+
+                if pixel != 0 && line[x].is_none() && (flags.sprites_left || x >= 8) {
+                    line[x] = Some((SPRITE_PALETTES + palette_index * 4 + pixel, behind));
+                }
+            }
+        }
+
+        line
+    }
+
+    // Pixel values (0 for transparent, 1-3 for a color of its palette) of
+    // the row of a sprite that shows on scanline `y`, from left to right
+    fn sprite_row(&mut self, sprite: [u8; 4], y: usize, flags: RenderFlags) -> [usize; 8] {
+        let [sprite_y, tile, attributes, _] = sprite;
+        let height = sprite_height(flags);
+        let mut row = y - (sprite_y as usize + 1);
+        if attributes & SPRITE_FLIP_Y_MASK != 0 {
+            row = height - 1 - row;
+        }
+
+        // 8x16 sprites take the pattern table from bit 0 of the tile
+        // index, and use an even tile on top of the next odd one
+        let (pattern_table, tile) = if flags.tall_sprites {
+            ((tile & 1) as u16 * 0x1000, (tile & 0xfe) + (row >= 8) as u8)
+        } else {
+            (flags.sprite_table, tile)
+        };
+        let pattern_addr = pattern_table + tile as u16 * 16 + (row % 8) as u16;
+        let plane0 = self.read(pattern_addr);
+        let plane1 = self.read(pattern_addr + 8);
+
+        let flip_x = attributes & SPRITE_FLIP_X_MASK != 0;
+        std::array::from_fn(|i| {
+            let bit = if flip_x { i } else { 7 - i };
+            (((plane1 >> bit) & 1) << 1 | ((plane0 >> bit) & 1)) as usize
+        })
+    }
+
+    // X of the first pixel of scanline `y` where an opaque pixel of sprite 0
+    // overlaps an opaque background pixel, which sets the sprite 0 hit flag.
+    // Priority doesn't matter, but the hit needs both layers enabled, can't
+    // happen where the left 8 pixels of either layer are hidden, nor on the
+    // last pixel of the line.
+    // Based on https://www.nesdev.org/wiki/PPU_OAM#Sprite_zero_hits
+    pub fn sprite_zero_hit(
+        &mut self,
+        y: usize,
+        scroll: ScrollPosition,
+        flags: RenderFlags,
+    ) -> Option<usize> {
+        let sprite: [u8; 4] = self.oam[..4].try_into().unwrap();
+        if !flags.show_background
+            || !flags.show_sprites
+            || y >= SCREEN_HEIGHT
+            || !sprite_on_line(sprite[0], y, sprite_height(flags))
+        {
+            return None;
+        }
+
+        let background = self.background_line(scroll, flags);
+        let sprite_x = sprite[3] as usize;
+        self.sprite_row(sprite, y, flags)
+            .into_iter()
+            .enumerate()
+            .map(|(i, pixel)| (sprite_x + i, pixel))
+            .find(|&(x, pixel)| {
+                let left_hidden = x < 8 && !(flags.background_left && flags.sprites_left);
+                pixel != 0 && x < SCREEN_WIDTH - 1 && !left_hidden && background[x] != 0
+            })
+            .map(|(x, _)| x)
+    }
+
+    // The dot of scanline `y` where the sprite evaluation for the next line
+    // finds a ninth sprite on it and sets the overflow flag, if it does.
+    // After the eighth sprite the PPU keeps checking Y coordinates but, by a
+    // hardware bug, also moves to the next byte of each sprite on every miss,
+    // so it compares tiles, attributes and X coordinates as if they were Y
+    // ones: it can both miss real overflows and report false ones.
+    // Based on https://www.nesdev.org/wiki/PPU_sprite_evaluation
+    pub fn sprite_overflow(&self, y: usize, flags: RenderFlags) -> Option<u32> {
+        if (!flags.show_background && !flags.show_sprites) || y >= SCREEN_HEIGHT {
+            return None;
+        }
+
+        let height = sprite_height(flags);
+        // A Y read takes 2 dots, and copying the other 3 bytes of a sprite
+        // on the line 6 more
+        let mut dot = SPRITE_EVALUATION_START;
+        let mut found = 0;
+        let mut n = 0;
+        while n < OAM_SIZE / 4 && found < SPRITES_PER_SCANLINE {
+            dot += 2;
+            if sprite_on_line(self.oam[n * 4], y + 1, height) {
+                dot += 6;
+                found += 1;
+            }
+            n += 1;
+        }
+
+        let mut m = 0;
+        while n < OAM_SIZE / 4 && dot < SPRITE_EVALUATION_END {
+            dot += 2;
+            if sprite_on_line(self.oam[n * 4 + m], y + 1, height) {
+                return Some(dot);
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+        None
+    }
+
+    // Draws all four nametables, ignoring the scroll, into a
+    // NAMETABLES_WIDTH x NAMETABLES_HEIGHT image of NES color indices
+    pub fn render_nametables(&mut self, pattern_table: u16) -> Vec<u16> {
+        let palette = self.palette();
+        let mut image = vec![0; NAMETABLES_WIDTH * NAMETABLES_HEIGHT];
+
+        for nametable in 0..4 {
+            let (left, top) = ((nametable & 1) as usize, (nametable >> 1) as usize);
+            for tile_row in 0..TILES_HEIGHT {
+                for fine_y in 0..8 {
+                    let y = top * SCREEN_HEIGHT + tile_row * 8 + fine_y as usize;
+                    for tile_col in 0..TILES_WIDTH {
+                        let colors =
+                            self.tile_sliver(nametable, tile_row, tile_col, fine_y, pattern_table);
+                        let x = left * SCREEN_WIDTH + tile_col * 8;
+                        for (i, &color) in colors.iter().enumerate() {
+                            image[y * NAMETABLES_WIDTH + x + i] = (palette[color] & 0x3f) as u16;
+                        }
+                    }
+                }
+            }
+        }
+
+        image
+    }
+
+    fn palette(&mut self) -> [u8; PALETTE_SIZE] {
+        let mut palette = [0; PALETTE_SIZE];
+        for (i, entry) in palette.iter_mut().enumerate() {
+            *entry = self.read(PALETTE_START + i as u16);
+        }
+        palette
+    }
+
+    // The 8 pixels of row `fine_y` of a background tile, as indices into
+    // the background palette
+    fn tile_sliver(
+        &mut self,
+        nametable: u16,
+        tile_row: usize,
+        tile_col: usize,
+        fine_y: u8,
+        pattern_table: u16,
+    ) -> [usize; 8] {
+        let nametable_base = NAMETABLES_START + NAMETABLE_SIZE * nametable;
+        let tile_addr = nametable_base + (tile_row * TILES_WIDTH + tile_col) as u16;
+        let tile = self.read(tile_addr);
+
+        // Each attribute byte holds the palettes of a 4x4 tile area,
+        // 2 bits for every 2x2 quadrant.
+        let attribute_addr = nametable_base
+            + ATTRIBUTE_TABLE_OFFSET
+            + (tile_row / 4 * (TILES_WIDTH / 4) + tile_col / 4) as u16;
+        let attribute = self.read(attribute_addr);
+        let shift = ((tile_row % 4) / 2 * 2 + (tile_col % 4) / 2) * 2;
+        let palette_index = ((attribute >> shift) & 0b11) as usize;
+
+        let pattern_addr = pattern_table + tile as u16 * 16 + fine_y as u16;
+        let plane0 = self.read(pattern_addr);
+        let plane1 = self.read(pattern_addr + 8);
+
+        let mut colors = [0; 8];
+        for (x, color) in colors.iter_mut().enumerate() {
+            let bit = 7 - x;
+            let pixel = (((plane1 >> bit) & 1) << 1 | ((plane0 >> bit) & 1)) as usize;
+
+            // Color 0 of every palette shows the backdrop color
+            *color = if pixel == 0 {
+                0
+            } else {
+                palette_index * 4 + pixel
+            };
+        }
+        colors
+    }
+}
@@ -1,33 +1,42 @@
-use std::collections::HashMap;
-
 use crate::asc::MemoryMapped;
 
-#[derive(Debug, Default)]
+// RAM chip of `size` bytes. Addresses wrap around it, so mapped on a range
+// as long as itself it sees every address once wherever the range starts,
+// and mapped on a longer one it repeats over it.
+#[derive(Debug)]
 pub struct Ram {
-    memory: HashMap<u16, u8>,
+    memory: Box<[u8]>,
 }
 
 impl Ram {
-    pub fn new() -> Ram {
+    pub fn new(size: usize) -> Ram {
         Ram {
-            memory: HashMap::new(),
+            memory: vec![0; size].into_boxed_slice(),
         }
     }
 
-    pub fn load_vec_at(&mut self, bytes: Vec<u8>, offset: u16) {
-        for (a, v) in bytes.into_iter().enumerate() {
-            self.write(a as u16 + offset, v);
-        }
+    fn offset(&self, addr: u16) -> usize {
+        addr as usize % self.memory.len()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Fills the RAM from the start, bytes past its size are dropped
+    pub fn load(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(self.memory.len());
+        self.memory[..len].copy_from_slice(&bytes[..len]);
     }
 }
 
 impl MemoryMapped for Ram {
     fn write(&mut self, addr: u16, value: u8) {
-        self.memory.insert(addr, value);
+        let offset = self.offset(addr);
+        self.memory[offset] = value;
     }
 
     fn read(&mut self, addr: u16) -> u8 {
-        let x = self.memory.get(&addr).unwrap_or(&0);
-        return *x;
+        self.memory[self.offset(addr)]
     }
 }
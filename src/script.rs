@@ -0,0 +1,106 @@
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::input::Button;
+
+// What player 1 does during some frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    frames: RangeInclusive<usize>,
+    buttons: Vec<Button>,
+}
+
+// Controller input played back as player 1, so tests can get through menus
+// without a TAS movie, or a `--seek-frame` can get past them. Steps are separated by new lines or `;`:
+//
+//     frame 120: press Start
+//     frames 200-260: hold Right+A  # jump while running
+//
+// `press` and `hold` both keep the buttons down over the frames given, the
+// first reads better on a single frame. Frames count from 0 like
+// `--frames`, and buttons use the names of the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    steps: Vec<Step>,
+}
+
+impl InputScript {
+    pub fn load(path: &Path) -> Result<InputScript, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        InputScript::parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut steps = vec![];
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(line, _)| line);
+            for step in line.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+                steps.push(parse_step(step).map_err(|e| format!("line {}: {e}", number + 1))?);
+            }
+        }
+        Ok(InputScript { steps })
+    }
+
+    // The frame of the last step, None for an empty script
+    pub fn last_frame(&self) -> Option<usize> {
+        self.steps.iter().map(|step| *step.frames.end()).max()
+    }
+
+    pub fn is_held(&self, frame: usize, button: Button) -> bool {
+        self.steps
+            .iter()
+            .any(|step| step.frames.contains(&frame) && step.buttons.contains(&button))
+    }
+}
+
+// `frame <n>: <action> <buttons>` or `frames <first>-<last>: ...`
+fn parse_step(step: &str) -> Result<Step, String> {
+    let (frames, action) = step
+        .split_once(':')
+        .ok_or_else(|| format!("expected `frame <n>: <action>` in `{step}`"))?;
+
+    let frames = match frames.split_whitespace().collect::<Vec<_>>()[..] {
+        ["frame" | "frames", range] => parse_frames(range)?,
+        _ => {
+            return Err(format!(
+                "expected `frame <n>` or `frames <n>-<n>`, got `{}`",
+                frames.trim()
+            ))
+        }
+    };
+
+    let buttons = match action.split_whitespace().collect::<Vec<_>>()[..] {
+        ["press" | "hold", buttons] => buttons
+            .split('+')
+            .map(|name| Button::from_name(name).ok_or_else(|| format!("unknown button {name}")))
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => {
+            return Err(format!(
+                "expected `press <buttons>` or `hold <buttons>`, got `{}`",
+                action.trim()
+            ))
+        }
+    };
+
+    Ok(Step { frames, buttons })
+}
+
+fn parse_frames(range: &str) -> Result<RangeInclusive<usize>, String> {
+    let parse = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| format!("invalid frame number {s}"))
+    };
+    let frames = match range.split_once('-') {
+        Some((first, last)) => parse(first)?..=parse(last)?,
+        None => {
+            let frame = parse(range)?;
+            frame..=frame
+        }
+    };
+    if frames.is_empty() {
+        return Err(format!("frame range {range} goes backwards"));
+    }
+    Ok(frames)
+}
@@ -0,0 +1,397 @@
+use std::fmt;
+
+use crate::machine::Machine;
+
+// Results are reported like blargg's test ROMs do: $6000 holds $80 while the
+// test runs and then its result, 0 for success or the number of the check
+// that failed. $6001-$6003 hold a signature marking $6000 as valid, and a
+// message can follow as a zero terminated string at $6004.
+// Based on https://github.com/christopherpow/nes-test-roms/blob/master/blargg_testrom.txt
+const STATUS: u16 = 0x6000;
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const MESSAGE: u16 = 0x6004;
+const RUNNING: u8 = 0x80;
+
+// Routines at the end of the bundled ROMs, at fixed addresses so the tests
+// can jump to them without an assembler resolving labels
+const FAIL: u16 = 0xff00;
+const PASS: u16 = 0xff10;
+const SUBROUTINE: u16 = 0xff20;
+const NMI_HANDLER: u16 = 0xff30;
+const IRQ_HANDLER: u16 = 0xff40;
+const PROGRAM_START: u16 = 0xc000;
+const PRG_ROM_SIZE: usize = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    // Number of the failed check
+    Failed(u8),
+    // The test never reported a result
+    TimedOut,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "pass"),
+            Outcome::Failed(check) => write!(f, "FAIL (check {check})"),
+            Outcome::TimedOut => write!(f, "FAIL (timed out)"),
+        }
+    }
+}
+
+// Runs a ROM following the protocol above until it reports a result, for
+// at most `max_frames` frames
+pub fn run(nes: &mut Machine, max_frames: usize) -> Outcome {
+    while nes.frame() < max_frames && !nes.is_halted() {
+        nes.run_frame();
+
+        let signature = [0, 1, 2].map(|i| nes.peek(STATUS + 1 + i).unwrap_or(0));
+        match nes.peek(STATUS) {
+            _ if signature != SIGNATURE => {}
+            Some(0) => return Outcome::Passed,
+            // $81 asks for a reset button press, which none of the tests
+            // run here need
+            Some(status) if status < RUNNING => return Outcome::Failed(status),
+            _ => {}
+        }
+    }
+    Outcome::TimedOut
+}
+
+// The text a test left at $6004, usually why it failed
+pub fn message(nes: &Machine) -> String {
+    let bytes: Vec<u8> = (MESSAGE..0x8000)
+        .map_while(|addr| nes.peek(addr).filter(|&byte| byte != 0))
+        .collect();
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+// A test bundled with the emulator. They are small enough to be written
+// straight in machine code and are built into NROM images on demand.
+pub struct SelfTest {
+    pub name: &'static str,
+    program: Vec<u8>,
+}
+
+impl SelfTest {
+    // Longer than any of them takes
+    pub const MAX_FRAMES: usize = 10;
+
+    // The iNES image of the test, 16KB of PRG ROM at $C000 and 8KB of CHR
+    // where every tile is solid color 3
+    pub fn rom(&self) -> Vec<u8> {
+        let mut prg = vec![0xff; PRG_ROM_SIZE];
+        let mut place = |addr: u16, bytes: &[u8]| {
+            let offset = (addr - PROGRAM_START) as usize;
+            prg[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        #[rustfmt::skip]
+        let prologue = [
+            0x78,                   // SEI
+            0xd8,                   // CLD
+            0xa2, 0xff,             // LDX #$FF
+            0x9a,                   // TXS
+            0xa9, SIGNATURE[0],     // LDA #$DE
+            0x8d, 0x01, 0x60,       // STA $6001
+            0xa9, SIGNATURE[1],     // LDA #$B0
+            0x8d, 0x02, 0x60,       // STA $6002
+            0xa9, SIGNATURE[2],     // LDA #$61
+            0x8d, 0x03, 0x60,       // STA $6003
+            0xa9, 0x00,             // LDA #$00
+            0x8d, 0x04, 0x60,       // STA $6004
+            0xa9, RUNNING,          // LDA #$80
+            0x8d, 0x00, 0x60,       // STA $6000
+        ];
+        let mut code = prologue.to_vec();
+        code.extend(&self.program);
+        code.extend(jmp(PASS));
+        place(PROGRAM_START, &code);
+
+        #[rustfmt::skip]
+        place(FAIL, &[
+            0x8d, 0x00, 0x60,       // STA $6000
+            0x4c, 0x03, 0xff,       // JMP *
+        ]);
+        #[rustfmt::skip]
+        place(PASS, &[
+            0xa9, 0x00,             // LDA #$00
+            0x8d, 0x00, 0x60,       // STA $6000
+            0x4c, 0x15, 0xff,       // JMP *
+        ]);
+        #[rustfmt::skip]
+        place(SUBROUTINE, &[
+            0xa2, 0x33,             // LDX #$33
+            0x60,                   // RTS
+        ]);
+        #[rustfmt::skip]
+        place(NMI_HANDLER, &[
+            0xe6, 0x11,             // INC $11
+            0x40,                   // RTI
+        ]);
+        place(IRQ_HANDLER, &[0x40]); // RTI
+
+        let vectors = [NMI_HANDLER, PROGRAM_START, IRQ_HANDLER];
+        place(0xfffa, &vectors.map(u16::to_le_bytes).concat());
+
+        let mut rom = b"NES\x1a".to_vec();
+        rom.extend([1, 1]);
+        rom.resize(16, 0);
+        rom.extend(prg);
+        rom.extend(vec![0xff; 0x2000]);
+        rom
+    }
+}
+
+// Jumps to the failure routine with check `check` unless the branch
+// `opcode` is taken
+fn expect(opcode: u8, check: u8) -> Vec<u8> {
+    let mut code = vec![opcode, 5, 0xa9, check];
+    code.extend(jmp(FAIL));
+    code
+}
+
+fn jmp(addr: u16) -> [u8; 3] {
+    let [low, high] = addr.to_le_bytes();
+    [0x4c, low, high]
+}
+
+const BEQ: u8 = 0xf0;
+const BNE: u8 = 0xd0;
+const BMI: u8 = 0x30;
+const BCC: u8 = 0x90;
+const BVS: u8 = 0x70;
+
+#[rustfmt::skip]
+const WAIT_VBLANK: [u8; 5] = [
+    0x2c, 0x02, 0x20,       // BIT $2002
+    0x10, 0xfb,             // BPL *-3
+];
+
+pub fn tests() -> Vec<SelfTest> {
+    #[rustfmt::skip]
+    let cpu = [
+        vec![
+            0xa9, 0x7f,             // LDA #$7F
+            0x18,                   // CLC
+            0x69, 0x01,             // ADC #$01
+        ],
+        expect(BVS, 1),
+        expect(BMI, 2),
+        expect(BCC, 3),
+        vec![0xc9, 0x80],           // CMP #$80
+        expect(BEQ, 4),
+        vec![
+            0xa9, 0x42,             // LDA #$42
+            0x48,                   // PHA
+            0xa9, 0x00,             // LDA #$00
+            0x68,                   // PLA
+            0xc9, 0x42,             // CMP #$42
+        ],
+        expect(BEQ, 5),
+        vec![
+            0x20, 0x20, 0xff,       // JSR SUBROUTINE
+            0xe0, 0x33,             // CPX #$33
+        ],
+        expect(BEQ, 6),
+        vec![
+            0xa9, 0x5a,             // LDA #$5A
+            0x85, 0x10,             // STA $10
+            0xa2, 0x05,             // LDX #$05
+            0xb5, 0x0b,             // LDA $0B,X
+            0xc9, 0x5a,             // CMP #$5A
+        ],
+        expect(BEQ, 7),
+        vec![
+            0x38,                   // SEC
+            0xa9, 0x10,             // LDA #$10
+            0xe9, 0x20,             // SBC #$20
+        ],
+        expect(BCC, 8),
+        vec![0xc9, 0xf0],           // CMP #$F0
+        expect(BEQ, 9),
+    ];
+
+    // The flag is set at the start of vblank and cleared by reading it, and
+    // with NMIs enabled the next vblank runs the NMI handler. A missing
+    // vblank or NMI makes the test time out.
+    #[rustfmt::skip]
+    let vblank = [
+        WAIT_VBLANK.to_vec(),
+        vec![
+            0xad, 0x02, 0x20,       // LDA $2002
+            0x29, 0x80,             // AND #$80
+        ],
+        expect(BEQ, 1),
+        WAIT_VBLANK.to_vec(),
+        vec![
+            0xa9, 0x00,             // LDA #$00
+            0x85, 0x11,             // STA $11
+            0xa9, 0x80,             // LDA #$80
+            0x8d, 0x00, 0x20,       // STA $2000
+            0xa5, 0x11,             // LDA $11
+            0xf0, 0xfc,             // BEQ *-2
+            0xa9, 0x00,             // LDA #$00
+            0x8d, 0x00, 0x20,       // STA $2000
+        ],
+    ];
+
+    // Palette reads skip the $2007 read buffer, and $3F10 and $3F20 are
+    // mirrors of $3F00
+    let set_addr =
+        |high: u8, low: u8| vec![0xa9, high, 0x8d, 0x06, 0x20, 0xa9, low, 0x8d, 0x06, 0x20];
+    #[rustfmt::skip]
+    let read_palette = |low: u8, expected: u8, check: u8| [
+        set_addr(0x3f, low),
+        vec![
+            0xad, 0x07, 0x20,       // LDA $2007
+            0x29, 0x3f,             // AND #$3F
+            0xc9, expected,         // CMP #expected
+        ],
+        expect(BEQ, check),
+    ].concat();
+    #[rustfmt::skip]
+    let palette = [
+        WAIT_VBLANK.to_vec(),
+        vec![0xad, 0x02, 0x20],     // LDA $2002
+        set_addr(0x3f, 0x00),
+        vec![
+            0xa9, 0x2a,             // LDA #$2A
+            0x8d, 0x07, 0x20,       // STA $2007
+            0xa9, 0x15,             // LDA #$15
+            0x8d, 0x07, 0x20,       // STA $2007
+        ],
+        read_palette(0x01, 0x15, 1),
+        read_palette(0x10, 0x2a, 2),
+        read_palette(0x20, 0x2a, 3),
+    ];
+
+    // Reads below the palettes return the byte of the previous read, and a
+    // palette read fills the buffer with the nametable byte under it
+    #[rustfmt::skip]
+    let read_buffer = [
+        WAIT_VBLANK.to_vec(),
+        vec![0xad, 0x02, 0x20],     // LDA $2002
+        set_addr(0x20, 0x00),
+        vec![
+            0xa9, 0x5a,             // LDA #$5A
+            0x8d, 0x07, 0x20,       // STA $2007
+        ],
+        set_addr(0x2f, 0x00),
+        vec![
+            0xa9, 0x77,             // LDA #$77
+            0x8d, 0x07, 0x20,       // STA $2007
+        ],
+        set_addr(0x20, 0x00),
+        vec![
+            0xad, 0x07, 0x20,       // LDA $2007
+            0xad, 0x07, 0x20,       // LDA $2007
+            0xc9, 0x5a,             // CMP #$5A
+        ],
+        expect(BEQ, 1),
+        set_addr(0x3f, 0x00),
+        vec![0xad, 0x07, 0x20],     // LDA $2007
+        set_addr(0x20, 0x00),
+        vec![
+            0xad, 0x07, 0x20,       // LDA $2007
+            0xc9, 0x77,             // CMP #$77
+        ],
+        expect(BEQ, 2),
+    ];
+
+    // Sprite 0 over the solid background sets the hit flag mid-frame, and it
+    // stays set through vblank until the pre-render line clears it. All the
+    // other sprites sit on the top lines, which overflows them.
+    #[rustfmt::skip]
+    let sprite_zero = [
+        WAIT_VBLANK.to_vec(),
+        vec![
+            0xa9, 0x00,             // LDA #$00
+            0x8d, 0x03, 0x20,       // STA $2003
+            0xa9, 0x32,             // LDA #$32
+            0x8d, 0x04, 0x20,       // STA $2004
+            0xa9, 0x00,             // LDA #$00
+            0x8d, 0x04, 0x20,       // STA $2004
+            0x8d, 0x04, 0x20,       // STA $2004
+            0xa9, 0x64,             // LDA #$64
+            0x8d, 0x04, 0x20,       // STA $2004
+            0xa9, 0x1e,             // LDA #$1E
+            0x8d, 0x01, 0x20,       // STA $2001
+            0x2c, 0x02, 0x20,       // BIT $2002
+            0x50, 0xfb,             // BVC *-3
+            0xad, 0x02, 0x20,       // LDA $2002
+            0x29, 0x20,             // AND #$20
+        ],
+        expect(BNE, 1),
+        WAIT_VBLANK.to_vec(),
+        vec![
+            0xad, 0x02, 0x20,       // LDA $2002
+            0x29, 0x40,             // AND #$40
+        ],
+        expect(BNE, 2),
+        vec![
+            0x2c, 0x02, 0x20,       // BIT $2002
+            0x70, 0xfb,             // BVS *-3
+            0xad, 0x02, 0x20,       // LDA $2002
+            0x29, 0x20,             // AND #$20
+        ],
+        expect(BEQ, 3),
+        vec![
+            0x2c, 0x02, 0x20,       // BIT $2002
+            0x50, 0xfb,             // BVC *-3
+            0xa9, 0x00,             // LDA #$00
+            0x8d, 0x01, 0x20,       // STA $2001
+        ],
+    ];
+
+    // Writes to any register fill the I/O latch, which shows up in the low
+    // bits of $2002 and in reads of the write only registers
+    #[rustfmt::skip]
+    let open_bus = [
+        vec![
+            0xa9, 0x15,             // LDA #$15
+            0x8d, 0x03, 0x20,       // STA $2003
+            0xad, 0x02, 0x20,       // LDA $2002
+            0x29, 0x1f,             // AND #$1F
+            0xc9, 0x15,             // CMP #$15
+        ],
+        expect(BEQ, 1),
+        vec![
+            0xa9, 0x0a,             // LDA #$0A
+            0x8d, 0x05, 0x20,       // STA $2005
+            0x8d, 0x05, 0x20,       // STA $2005
+            0xad, 0x06, 0x20,       // LDA $2006
+            0xc9, 0x0a,             // CMP #$0A
+        ],
+        expect(BEQ, 2),
+    ];
+
+    vec![
+        SelfTest {
+            name: "cpu basics",
+            program: cpu.concat(),
+        },
+        SelfTest {
+            name: "ppu vblank",
+            program: vblank.concat(),
+        },
+        SelfTest {
+            name: "palette",
+            program: palette.concat(),
+        },
+        SelfTest {
+            name: "ppu read buffer",
+            program: read_buffer.concat(),
+        },
+        SelfTest {
+            name: "sprite 0 hit",
+            program: sprite_zero.concat(),
+        },
+        SelfTest {
+            name: "ppu open bus",
+            program: open_bus.concat(),
+        },
+    ]
+}
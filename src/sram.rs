@@ -0,0 +1,39 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const SRAM_START: u16 = 0x6000;
+pub const SRAM_SIZE: usize = 8 * (1 << 10);
+
+// FCEUX, Mesen and Nestopia all store battery saves as the raw 8KB of the
+// $6000-$7FFF window. Some other emulators pad the file up to a bigger size,
+// so only the first 8KB are meaningful. Short files are filled with zeros.
+pub fn import(path: &str) -> io::Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    Ok(normalize(bytes))
+}
+
+// Where battery saves go without --sram: next to the ROM, with the same
+// name and a .sav extension, as the other emulators look for them
+pub fn default_path(rom_file: &str) -> String {
+    Path::new(rom_file)
+        .with_extension("sav")
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn export(path: &str, sram: &[u8]) -> io::Result<()> {
+    fs::write(path, sram)
+}
+
+fn normalize(mut bytes: Vec<u8>) -> Vec<u8> {
+    if bytes.len() < SRAM_SIZE {
+        eprintln!(
+            "[WARN]: save file has only {} bytes, filling the rest of the SRAM with zeros",
+            bytes.len()
+        );
+    }
+
+    bytes.resize(SRAM_SIZE, 0);
+    bytes
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlayStats {
+    // ROM file name, without the directory
+    pub name: String,
+    pub launches: u32,
+    pub playtime: Duration,
+    // Unix time of the end of the last session
+    pub last_played: u64,
+}
+
+// Playtime of every game run so far, by the CRC32 of the ROM (see
+// `patch::rom_crc32`). Stored one game per line, with the fields separated
+// by tabs: CRC32, launches, seconds played, last played and name.
+#[derive(Debug, Default)]
+pub struct Stats {
+    games: HashMap<u32, PlayStats>,
+}
+
+impl Stats {
+    // `$XDG_DATA_HOME/rodomo/stats.tsv`, or `~/.local/share/rodomo/stats.tsv`
+    pub fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+        };
+        Some(dir.join("rodomo").join("stats.tsv"))
+    }
+
+    // A missing file has no games yet
+    pub fn load(path: &Path) -> Result<Stats, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Stats::parse(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Stats::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Stats, String> {
+        let mut games = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let error = || format!("line {}: expected 5 tab separated fields", i + 1);
+
+            let fields: Vec<&str> = line.splitn(5, '\t').collect();
+            let [crc, launches, seconds, last_played, name] = fields[..] else {
+                return Err(error());
+            };
+            let crc = u32::from_str_radix(crc, 16).map_err(|_| error())?;
+            let stats = PlayStats {
+                name: name.to_string(),
+                launches: launches.parse().map_err(|_| error())?,
+                playtime: Duration::from_secs(seconds.parse().map_err(|_| error())?),
+                last_played: last_played.parse().map_err(|_| error())?,
+            };
+            games.insert(crc, stats);
+        }
+        Ok(Stats { games })
+    }
+
+    // Writes the stats, creating the data directory if needed
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, self.to_tsv()).map_err(|e| e.to_string())
+    }
+
+    fn to_tsv(&self) -> String {
+        let mut crcs: Vec<&u32> = self.games.keys().collect();
+        crcs.sort();
+
+        let mut text = String::new();
+        for crc in crcs {
+            let game = &self.games[crc];
+            text += &format!(
+                "{crc:08X}\t{}\t{}\t{}\t{}\n",
+                game.launches,
+                game.playtime.as_secs(),
+                game.last_played,
+                game.name
+            );
+        }
+        text
+    }
+
+    pub fn get(&self, rom_crc: u32) -> Option<&PlayStats> {
+        self.games.get(&rom_crc)
+    }
+
+    // Every game, the most recently played first
+    pub fn games(&self) -> Vec<&PlayStats> {
+        let mut games: Vec<&PlayStats> = self.games.values().collect();
+        games.sort_by_key(|game| std::cmp::Reverse(game.last_played));
+        games
+    }
+
+    // Adds a session that ended at `now` (Unix time)
+    pub fn record(&mut self, rom_crc: u32, name: &str, playtime: Duration, now: u64) {
+        let game = self.games.entry(rom_crc).or_default();
+        game.name = name.to_string();
+        game.launches += 1;
+        game.playtime += playtime;
+        game.last_played = now;
+    }
+}
+
+// `1h 05m`, or `3m 20s` for less than an hour
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+    if hours > 0 {
+        format!("{hours}h {minutes:02}m")
+    } else {
+        format!("{minutes}m {:02}s", seconds % 60)
+    }
+}
+
+// How long before `now` a Unix time was, e.g. `3 days ago`
+pub fn format_ago(time: u64, now: u64) -> String {
+    let seconds = now.saturating_sub(time);
+    let (amount, unit) = match seconds {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{amount} {unit}{plural} ago")
+}
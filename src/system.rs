@@ -0,0 +1,222 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::asc::{Asc, Device, MemoryMapped, FAST_RAM_SIZE};
+use crate::cpu::{Cpu, StepInfo};
+use crate::ram::Ram;
+
+// Memory map of `System::sbc`
+const SBC_RAM: RangeInclusive<u16> = 0x0000..=0xbfff;
+const SBC_CONSOLE: u16 = 0xc000;
+const SBC_ROM_START: u16 = 0xe000;
+
+// A 6502 and the devices on its bus, without any NES hardware. `Machine`
+// builds the NES on top of one, other 6502 computers can be put together
+// with `SystemBuilder`.
+pub struct System {
+    pub cpu: Cpu,
+    pub asc: Asc,
+}
+
+impl System {
+    pub fn builder() -> SystemBuilder {
+        SystemBuilder::new(Asc::new())
+    }
+
+    // A small single board computer: RAM at $0000-$BFFF, a serial console on
+    // stdin/stdout at $C000 and up to 8KB of ROM ending at $FFFF, which holds
+    // the reset vector.
+    pub fn sbc(rom: Vec<u8>) -> Result<System, String> {
+        let max_size = 0x10000 - SBC_ROM_START as usize;
+        if rom.is_empty() || rom.len() > max_size {
+            return Err(format!("the ROM must be 1 to {max_size} bytes long"));
+        }
+        let rom_start = (0x10000 - rom.len()) as u16;
+
+        Ok(System::builder()
+            .ram(SBC_RAM)
+            .device(
+                SBC_CONSOLE..=SBC_CONSOLE + 1,
+                Rc::new(RefCell::new(SerialConsole::new(SBC_CONSOLE))),
+            )
+            .rom(rom_start, rom)
+            .decimal_mode(true)
+            .build())
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.reset(&mut self.asc);
+    }
+
+    pub fn step(&mut self) -> StepInfo {
+        self.cpu.step(&mut self.asc)
+    }
+}
+
+// Maps devices on a bus and wires it to a CPU
+pub struct SystemBuilder {
+    asc: Asc,
+    decimal_mode: bool,
+    // See `SystemBuilder::fast_path`
+    fast_path: bool,
+}
+
+impl SystemBuilder {
+    // Starts from an existing bus, keeping its settings (stats, heatmap,
+    // unmapped access policy) but none of its devices.
+    pub fn new(mut asc: Asc) -> SystemBuilder {
+        asc.unregister_all();
+        SystemBuilder {
+            asc,
+            decimal_mode: false,
+            fast_path: false,
+        }
+    }
+
+    // Turns the fast path off if the device is in its range, only `ram` and
+    // `fast_path` know plain RAM is there
+    pub fn device(mut self, addrs: impl Iterator<Item = u16>, device: Device) -> SystemBuilder {
+        let addrs: Vec<u16> = addrs.collect();
+        if addrs.iter().any(|&addr| (addr as usize) < FAST_RAM_SIZE) {
+            self.fast_path = false;
+        }
+        self.asc.register_device_range(addrs.into_iter(), device);
+        self
+    }
+
+    // See `Asc::register_mirror`
+    pub fn mirror(mut self, addrs: RangeInclusive<u16>, mask: u16) -> SystemBuilder {
+        self.asc.register_mirror(addrs, mask);
+        self
+    }
+
+    // Uses the fast path if the RAM covers all of its range
+    pub fn ram(self, addrs: RangeInclusive<u16>) -> SystemBuilder {
+        let size = addrs.len();
+        let fast_path = *addrs.start() == 0 && *addrs.end() as usize >= FAST_RAM_SIZE - 1;
+        let builder = self.device(addrs, Rc::new(RefCell::new(Ram::new(size))));
+        builder.fast_path(fast_path)
+    }
+
+    pub fn rom(self, start: u16, bytes: Vec<u8>) -> SystemBuilder {
+        let end = start + (bytes.len() - 1) as u16;
+        self.device(start..=end, Rc::new(RefCell::new(Rom { start, bytes })))
+    }
+
+    // See `Cpu::set_decimal_mode`, off like on the NES by default
+    pub fn decimal_mode(mut self, enabled: bool) -> SystemBuilder {
+        self.decimal_mode = enabled;
+        self
+    }
+
+    // Serves $0000-$01FF from a copy kept in the bus (see
+    // `Asc::set_fast_path`). Only for buses with plain RAM over all that
+    // range, any other device there would miss its accesses.
+    pub fn fast_path(mut self, enabled: bool) -> SystemBuilder {
+        self.fast_path = enabled;
+        self
+    }
+
+    // Powers the system on, with the CPU jumping to the reset vector
+    pub fn build(mut self) -> System {
+        let mut cpu = Cpu::new();
+        cpu.set_decimal_mode(self.decimal_mode);
+        self.asc.set_fast_path(self.fast_path);
+
+        let mut system = System { cpu, asc: self.asc };
+        system.reset();
+        system
+    }
+}
+
+// Read-only memory, writes are ignored
+struct Rom {
+    start: u16,
+    bytes: Vec<u8>,
+}
+
+impl MemoryMapped for Rom {
+    fn write(&mut self, _addr: u16, _value: u8) {}
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.bytes[(addr - self.start) as usize]
+    }
+
+    // Patches the ROM, for the monitor
+    fn poke(&mut self, addr: u16, value: u8) {
+        self.bytes[(addr - self.start) as usize] = value;
+    }
+}
+
+// A serial port with two registers: data at `base`, where writes send a
+// byte to stdout and reads take the next byte from stdin (0 if there is
+// none), and status at `base + 1`, with bit 0 set while input is waiting and
+// bit 1 always set since output never blocks.
+pub struct SerialConsole {
+    base: u16,
+    input: Receiver<u8>,
+    pending: Option<u8>,
+}
+
+impl SerialConsole {
+    pub fn new(base: u16) -> SerialConsole {
+        // stdin is read on its own thread so the CPU never waits for input
+        let (sender, input) = mpsc::channel();
+        thread::spawn(move || {
+            let mut byte = [0];
+            while let Ok(1) = io::stdin().read(&mut byte) {
+                if sender.send(byte[0]).is_err() {
+                    break;
+                }
+            }
+        });
+
+        SerialConsole {
+            base,
+            input,
+            pending: None,
+        }
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        if self.pending.is_none() {
+            self.pending = self.input.try_recv().ok();
+        }
+        self.pending
+    }
+}
+
+impl MemoryMapped for SerialConsole {
+    fn write(&mut self, addr: u16, value: u8) {
+        if addr == self.base {
+            let mut stdout = io::stdout();
+            stdout.write_all(&[value]).ok();
+            stdout.flush().ok();
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr == self.base {
+            self.poll();
+            self.pending.take().unwrap_or(0)
+        } else {
+            0b10 | self.poll().is_some() as u8
+        }
+    }
+
+    // Polling only moves input that arrived into `pending`, the byte is
+    // still there for the next read
+    fn peek(&mut self, addr: u16) -> u8 {
+        if addr == self.base {
+            self.poll().unwrap_or(0)
+        } else {
+            0b10 | self.poll().is_some() as u8
+        }
+    }
+
+    fn poke(&mut self, _addr: u16, _value: u8) {}
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::fs;
+use std::io;
+
+use crate::cpu::Registers;
+
+// The B and unused bits don't exist inside the CPU, every emulator reports
+// them differently, so they are left out of the comparison.
+const STATUS_COMPARE_MASK: u8 = 0b1100_1111;
+
+const STATUS_LETTERS: &str = "nvubdizc";
+
+#[derive(Debug)]
+pub struct Divergence {
+    pub line: usize,
+    pub expected: Registers,
+    pub found: Registers,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "diverged from reference trace at line {}", self.line)?;
+        writeln!(f, "expected: {}", format_registers(&self.expected))?;
+        write!(f, "found:    {}", format_registers(&self.found))
+    }
+}
+
+// Reads trace logs in the nestest/Mesen style, where each line starts with
+// the PC and holds the registers as `A:00 X:00 Y:00 P:24 SP:FD` (Mesen uses
+// `S:` for the stack pointer and can print the status as flag letters).
+#[derive(Debug)]
+pub struct ReferenceTrace {
+    entries: Vec<(usize, Registers)>,
+    next: usize,
+}
+
+impl ReferenceTrace {
+    pub fn load(path: &str) -> io::Result<ReferenceTrace> {
+        let text = fs::read_to_string(path)?;
+        Ok(ReferenceTrace::parse(&text))
+    }
+
+    pub fn parse(text: &str) -> ReferenceTrace {
+        let entries = text
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| parse_line(line).map(|regs| (i + 1, regs)))
+            .collect();
+
+        ReferenceTrace { entries, next: 0 }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.entries.len()
+    }
+
+    // Compares the state right before the next instruction runs with the next
+    // line of the reference.
+    pub fn check(&mut self, found: Registers) -> Result<(), Divergence> {
+        let Some(&(line, expected)) = self.entries.get(self.next) else {
+            return Ok(());
+        };
+        self.next += 1;
+
+        let matches = expected.pc == found.pc
+            && expected.sp == found.sp
+            && expected.a == found.a
+            && expected.x == found.x
+            && expected.y == found.y
+            && expected.status & STATUS_COMPARE_MASK == found.status & STATUS_COMPARE_MASK;
+
+        if matches {
+            Ok(())
+        } else {
+            Err(Divergence {
+                line,
+                expected,
+                found,
+            })
+        }
+    }
+}
+
+fn format_registers(regs: &Registers) -> String {
+    format!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+        regs.pc, regs.a, regs.x, regs.y, regs.status, regs.sp
+    )
+}
+
+fn parse_line(line: &str) -> Option<Registers> {
+    let pc = u16::from_str_radix(line.get(0..4)?, 16).ok()?;
+
+    let mut regs = Registers {
+        pc,
+        sp: 0,
+        a: 0,
+        x: 0,
+        y: 0,
+        status: 0,
+    };
+
+    let mut fields = 0;
+    for token in line.split_whitespace() {
+        let Some((name, value)) = token.split_once(':') else {
+            continue;
+        };
+
+        let field = match name {
+            "A" => &mut regs.a,
+            "X" => &mut regs.x,
+            "Y" => &mut regs.y,
+            "SP" | "S" => &mut regs.sp,
+            "P" => &mut regs.status,
+            _ => continue,
+        };
+
+        *field = if name == "P" && value.len() == 8 {
+            parse_status_letters(value)?
+        } else {
+            u8::from_str_radix(value, 16).ok()?
+        };
+        fields += 1;
+    }
+
+    if fields == 5 {
+        Some(regs)
+    } else {
+        None
+    }
+}
+
+fn parse_status_letters(value: &str) -> Option<u8> {
+    let mut status = 0;
+    for (i, (c, expected)) in value.chars().zip(STATUS_LETTERS.chars()).enumerate() {
+        if c.to_ascii_lowercase() != expected {
+            return None;
+        }
+        if c.is_ascii_uppercase() {
+            status |= 1 << (7 - i);
+        }
+    }
+    Some(status)
+}
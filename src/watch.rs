@@ -0,0 +1,73 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    addrs: RangeInclusive<u16>,
+    on_read: bool,
+    on_write: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub value: u8,
+    pub access: Access,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(f, "read {:#06x} -> {:#04x}", self.addr, self.value),
+            Access::Write => write!(f, "write {:#06x} <- {:#04x}", self.addr, self.value),
+        }
+    }
+}
+
+impl Watchpoint {
+    // Parses `<addr>[-<addr>][:r|w|rw]` with hexadecimal addresses, e.g.
+    // `2000-23ff:w`. Without a suffix both reads and writes are watched.
+    pub fn parse(spec: &str) -> Option<Watchpoint> {
+        let (range, kind) = spec.split_once(':').unwrap_or((spec, "rw"));
+
+        let parse_addr = |s: &str| {
+            let s = s.trim_start_matches('$').trim_start_matches("0x");
+            u16::from_str_radix(s, 16).ok()
+        };
+
+        let addrs = match range.split_once('-') {
+            Some((start, end)) => parse_addr(start)?..=parse_addr(end)?,
+            None => {
+                let addr = parse_addr(range)?;
+                addr..=addr
+            }
+        };
+
+        let (on_read, on_write) = match kind {
+            "r" => (true, false),
+            "w" => (false, true),
+            "rw" => (true, true),
+            _ => return None,
+        };
+
+        Some(Watchpoint {
+            addrs,
+            on_read,
+            on_write,
+        })
+    }
+
+    pub fn matches(&self, addr: u16, access: Access) -> bool {
+        let kind = match access {
+            Access::Read => self.on_read,
+            Access::Write => self.on_write,
+        };
+        kind && self.addrs.contains(&addr)
+    }
+}